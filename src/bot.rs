@@ -50,21 +50,66 @@ impl BotService {
         let config = AppConfig::get()?;
         let storage = DialogueService::get_dialogue_storage(&config.storage).await?;
 
-        crate::command::setup_user_commands(&bot).await?;
+        crate::command::setup_default_commands(&bot).await?;
 
         let handler = get_handler();
 
-        Dispatcher::builder(bot, handler)
+        let mut dispatcher = Dispatcher::builder(bot, handler)
             .dependencies(dptree::deps![storage])
             .error_handler(LoggingErrorHandler::with_custom_text(
                 "An error has occurred in the dispatcher",
             ))
-            .enable_ctrlc_handler()
-            .build()
-            // .dispatch_with_listener(update_listener, update_listener_error_handler)
-            .dispatch()
-            .await;
+            .build();
 
+        // Updates keep being handled while the runtime drains, so users can still cancel or get told
+        let shutdown_token = dispatcher.shutdown_token();
+        tokio::spawn(async move {
+            wait_for_shutdown_signal().await;
+            info!("Shutdown signal received");
+
+            match AppState::get() {
+                Ok(state) => {
+                    if let Err(e) = state.shutdown().await {
+                        error!("Failed to shut down cleanly: {}", e);
+                    }
+                }
+                Err(e) => error!("Failed to get app state on shutdown: {}", e),
+            }
+
+            match shutdown_token.shutdown() {
+                Ok(stopped) => stopped.await,
+                Err(e) => warn!("Failed to stop the dispatcher: {}", e),
+            }
+        });
+
+        // dispatcher.dispatch_with_listener(update_listener, update_listener_error_handler)
+        dispatcher.dispatch().await;
+
+        info!("Dispatcher stopped");
         Ok(())
     }
 }
+
+/// Resolves on ctrl-c, or on SIGTERM which is how the host stops the bot
+async fn wait_for_shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(e) => {
+                warn!("Failed to listen for SIGTERM: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
@@ -20,6 +20,11 @@ pub enum Command {
     Help,
     Stats,
     Status,
+    DocId(String),
+    Endpoint(String),
+    DeadLetters,
+    Requeue(String),
+    Cancel,
 }
 
 impl Display for Command {
@@ -29,34 +34,51 @@ impl Display for Command {
 }
 
 impl Command {
-    pub fn user_commands() -> Vec<BotCommand> {
+    pub fn user_commands(locale: &str) -> Vec<BotCommand> {
         vec![
-            BotCommand::new("start", t!("commands.description.start")),
-            BotCommand::new("help", t!("commands.description.help")),
-            BotCommand::new("language", t!("commands.description.language")),
+            BotCommand::new("start", t!("commands.description.start", locale = locale)),
+            BotCommand::new("help", t!("commands.description.help", locale = locale)),
+            BotCommand::new("language", t!("commands.description.language", locale = locale)),
+            BotCommand::new("cancel", t!("commands.description.cancel", locale = locale)),
         ]
     }
 
-    pub fn admin_commands() -> Vec<BotCommand> {
+    pub fn admin_commands(locale: &str) -> Vec<BotCommand> {
         vec![
-            BotCommand::new("start", t!("commands.description.start")),
-            BotCommand::new("help", t!("commands.description.help")),
-            BotCommand::new("language", t!("commands.description.language")),
-            BotCommand::new("stats", t!("commands.description.stats")),
-            BotCommand::new("status", t!("commands.description.status")),
+            BotCommand::new("start", t!("commands.description.start", locale = locale)),
+            BotCommand::new("help", t!("commands.description.help", locale = locale)),
+            BotCommand::new("language", t!("commands.description.language", locale = locale)),
+            BotCommand::new("cancel", t!("commands.description.cancel", locale = locale)),
+            BotCommand::new("stats", t!("commands.description.stats", locale = locale)),
+            BotCommand::new("status", t!("commands.description.status", locale = locale)),
+            BotCommand::new("docid", t!("commands.description.docid", locale = locale)),
+            BotCommand::new("endpoint", t!("commands.description.endpoint", locale = locale)),
+            BotCommand::new("deadletters", t!("commands.description.deadletters", locale = locale)),
+            BotCommand::new("requeue", t!("commands.description.requeue", locale = locale)),
         ]
     }
 }
 
-pub async fn setup_user_commands(bot: &Throttle<Bot>) -> HandlerResult<()> {
+/// Sets the commands shown to users who haven't opened a chat with the bot yet
+pub async fn setup_default_commands(bot: &Throttle<Bot>) -> HandlerResult<()> {
     bot.delete_my_commands().await?;
-    bot.set_my_commands(Command::user_commands()).await?;
+    bot.set_my_commands(Command::user_commands(&rust_i18n::locale()))
+        .await?;
     Ok(())
 }
 
-pub async fn setup_admin_commands(bot: &Throttle<Bot>, chat_id: ChatId) -> HandlerResult<()> {
-    bot.delete_my_commands().await?;
-    bot.set_my_commands(Command::admin_commands())
+/// Commands are scoped to the chat, so that every user sees them in their own language
+pub async fn setup_user_commands(bot: &Throttle<Bot>, chat_id: ChatId, locale: &str) -> HandlerResult<()> {
+    bot.set_my_commands(Command::user_commands(locale))
+        .scope(BotCommandScope::Chat {
+            chat_id: Recipient::Id(chat_id),
+        })
+        .await?;
+    Ok(())
+}
+
+pub async fn setup_admin_commands(bot: &Throttle<Bot>, chat_id: ChatId, locale: &str) -> HandlerResult<()> {
+    bot.set_my_commands(Command::admin_commands(locale))
         .scope(BotCommandScope::Chat {
             chat_id: Recipient::Id(chat_id),
         })
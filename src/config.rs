@@ -21,6 +21,7 @@ pub struct AppConfig {
     pub admin: AdminConfig,
     pub runtime: RuntimeConfig,
     pub service: ServiceConfig,
+    pub platform: PlatformConfig,
 }
 
 impl AppConfig {
@@ -31,6 +32,7 @@ impl AppConfig {
             admin: AdminConfig::from_env(secret_store)?,
             runtime: RuntimeConfig::from_env(secret_store)?,
             service: ServiceConfig::from_env(secret_store)?,
+            platform: PlatformConfig::from_env(secret_store)?,
         };
 
         let _ = APP_CONFIG
@@ -127,20 +129,80 @@ pub struct RuntimeConfig {
 
 impl RuntimeConfig {
     pub fn from_env(secret_store: &SecretStore) -> Result<Self, ConfigError> {
-        Ok(Self {
-            queue: QueueConfig {
-                capacity: secret_store
-                    .get("QUEUE_CAPACITY")
-                    .ok_or_else(|| ConfigError::LoadConfigError("Missing QUEUE_CAPACITY".to_string()))?
-                    .parse::<usize>()
-                    .map_err(|_| ConfigError::InvalidConfig("Invalid QUEUE_CAPACITY".to_string()))?,
-                worker_count: secret_store
-                    .get("QUEUE_WORKER_COUNT")
-                    .ok_or_else(|| ConfigError::LoadConfigError("Missing QUEUE_WORKER_COUNT".to_string()))?
-                    .parse::<usize>()
-                    .map_err(|_| ConfigError::InvalidConfig("Invalid QUEUE_WORKER_COUNT".to_string()))?,
+        let queue = QueueConfig {
+            capacity: secret_store
+                .get("QUEUE_CAPACITY")
+                .ok_or_else(|| ConfigError::LoadConfigError("Missing QUEUE_CAPACITY".to_string()))?
+                .parse::<usize>()
+                .map_err(|_| ConfigError::InvalidConfig("Invalid QUEUE_CAPACITY".to_string()))?,
+            worker_count: secret_store
+                .get("QUEUE_WORKER_COUNT")
+                .ok_or_else(|| ConfigError::LoadConfigError("Missing QUEUE_WORKER_COUNT".to_string()))?
+                .parse::<usize>()
+                .map_err(|_| ConfigError::InvalidConfig("Invalid QUEUE_WORKER_COUNT".to_string()))?,
+            backend: match secret_store
+                .get("QUEUE_BACKEND")
+                .unwrap_or_else(|| "memory".to_string())
+                .as_str()
+            {
+                "memory" => QueueBackendKind::Memory,
+                "redis" => QueueBackendKind::Redis,
+                _ => return Err(ConfigError::InvalidConfig("Invalid QUEUE_BACKEND".to_string())),
             },
-        })
+            visibility_timeout_secs: secret_store
+                .get("QUEUE_VISIBILITY_TIMEOUT_SECS")
+                .unwrap_or_else(|| QueueConfig::DEFAULT_VISIBILITY_TIMEOUT_SECS.to_string())
+                .parse::<u64>()
+                .map_err(|_| ConfigError::InvalidConfig("Invalid QUEUE_VISIBILITY_TIMEOUT_SECS".to_string()))?,
+            confirmation_ttl_secs: secret_store
+                .get("QUEUE_CONFIRMATION_TTL_SECS")
+                .unwrap_or_else(|| QueueConfig::DEFAULT_CONFIRMATION_TTL_SECS.to_string())
+                .parse::<u64>()
+                .map_err(|_| ConfigError::InvalidConfig("Invalid QUEUE_CONFIRMATION_TTL_SECS".to_string()))?,
+            confirmation_capacity: secret_store
+                .get("QUEUE_CONFIRMATION_CAPACITY")
+                .unwrap_or_else(|| QueueConfig::DEFAULT_CONFIRMATION_CAPACITY.to_string())
+                .parse::<usize>()
+                .map_err(|_| ConfigError::InvalidConfig("Invalid QUEUE_CONFIRMATION_CAPACITY".to_string()))?,
+            max_in_flight_per_user: secret_store
+                .get("QUEUE_MAX_IN_FLIGHT_PER_USER")
+                .unwrap_or_else(|| QueueConfig::DEFAULT_MAX_IN_FLIGHT_PER_USER.to_string())
+                .parse::<usize>()
+                .map_err(|_| ConfigError::InvalidConfig("Invalid QUEUE_MAX_IN_FLIGHT_PER_USER".to_string()))?,
+            aging_secs: secret_store
+                .get("QUEUE_AGING_SECS")
+                .unwrap_or_else(|| QueueConfig::DEFAULT_AGING_SECS.to_string())
+                .parse::<u64>()
+                .map_err(|_| ConfigError::InvalidConfig("Invalid QUEUE_AGING_SECS".to_string()))?,
+            fetch_timeout_secs: secret_store
+                .get("QUEUE_FETCH_TIMEOUT_SECS")
+                .unwrap_or_else(|| QueueConfig::DEFAULT_FETCH_TIMEOUT_SECS.to_string())
+                .parse::<u64>()
+                .map_err(|_| ConfigError::InvalidConfig("Invalid QUEUE_FETCH_TIMEOUT_SECS".to_string()))?,
+            send_timeout_secs: secret_store
+                .get("QUEUE_SEND_TIMEOUT_SECS")
+                .unwrap_or_else(|| QueueConfig::DEFAULT_SEND_TIMEOUT_SECS.to_string())
+                .parse::<u64>()
+                .map_err(|_| ConfigError::InvalidConfig("Invalid QUEUE_SEND_TIMEOUT_SECS".to_string()))?,
+            shutdown_timeout_secs: secret_store
+                .get("QUEUE_SHUTDOWN_TIMEOUT_SECS")
+                .unwrap_or_else(|| QueueConfig::DEFAULT_SHUTDOWN_TIMEOUT_SECS.to_string())
+                .parse::<u64>()
+                .map_err(|_| ConfigError::InvalidConfig("Invalid QUEUE_SHUTDOWN_TIMEOUT_SECS".to_string()))?,
+        };
+
+        // A task still running when its lease ends is delivered to another slot and its media sent twice, a
+        // bulk download fetches and sends within the same task
+        if queue.backend == QueueBackendKind::Redis
+            && queue.visibility_timeout_secs <= queue.fetch_timeout_secs + queue.send_timeout_secs
+        {
+            return Err(ConfigError::InvalidConfig(
+                "QUEUE_VISIBILITY_TIMEOUT_SECS must exceed QUEUE_FETCH_TIMEOUT_SECS + QUEUE_SEND_TIMEOUT_SECS"
+                    .to_string(),
+            ));
+        }
+
+        Ok(Self { queue })
     }
 }
 
@@ -149,6 +211,44 @@ pub struct QueueConfig {
     pub capacity: usize,
     #[allow(unused)]
     pub worker_count: usize,
+    pub backend: QueueBackendKind,
+    /// How long a popped task may stay unacknowledged before it is delivered again, only used by Redis
+    pub visibility_timeout_secs: u64,
+    /// How long a download preview can be confirmed
+    pub confirmation_ttl_secs: u64,
+    /// Maximum number of previews waiting for confirmation, the ones closest to expiry are evicted first
+    pub confirmation_capacity: usize,
+    /// Tasks of a single user that may be processed at the same time, 0 for no limit
+    pub max_in_flight_per_user: usize,
+    /// How long a queued task waits before it is raised by one priority level, 0 disables aging
+    pub aging_secs: u64,
+    /// How long fetching a resource may take before the attempt fails and is retried
+    pub fetch_timeout_secs: u64,
+    /// How long sending media to a chat may take before the attempt fails and is retried
+    pub send_timeout_secs: u64,
+    /// How long running tasks may take to finish on shutdown before they are abandoned
+    pub shutdown_timeout_secs: u64,
+}
+
+/// Defaults of the settings added after the first deployments, these keep their previous behaviour: tasks in
+/// memory, no per-user limit and no aging
+impl QueueConfig {
+    const DEFAULT_VISIBILITY_TIMEOUT_SECS: u64 = 900;
+    const DEFAULT_CONFIRMATION_TTL_SECS: u64 = 3600;
+    const DEFAULT_CONFIRMATION_CAPACITY: usize = 1000;
+    const DEFAULT_MAX_IN_FLIGHT_PER_USER: usize = 0;
+    const DEFAULT_AGING_SECS: u64 = 0;
+    const DEFAULT_FETCH_TIMEOUT_SECS: u64 = 120;
+    const DEFAULT_SEND_TIMEOUT_SECS: u64 = 300;
+    const DEFAULT_SHUTDOWN_TIMEOUT_SECS: u64 = 30;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueueBackendKind {
+    /// Tasks are lost on restart, meant for tests and local development
+    Memory,
+    /// Tasks and pending confirmations survive restarts
+    Redis,
 }
 
 // -----------------
@@ -195,6 +295,13 @@ impl ServiceConfig {
                     .ok_or_else(|| ConfigError::LoadConfigError("Missing LANGUAGE_CACHE_CAPACITY".to_string()))?
                     .parse::<usize>()
                     .map_err(|_| ConfigError::InvalidConfig("Invalid LANGUAGE_CACHE_CAPACITY".to_string()))?,
+                sync_interval_secs: secret_store
+                    .get("LANGUAGE_SYNC_INTERVAL_SECS")
+                    .unwrap_or_else(|| LanguageConfig::DEFAULT_SYNC_INTERVAL_SECS.to_string())
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|secs| *secs > 0)
+                    .ok_or_else(|| ConfigError::InvalidConfig("Invalid LANGUAGE_SYNC_INTERVAL_SECS".to_string()))?,
             },
             interaction: InteractionConfig {
                 cache_capacity: secret_store
@@ -211,6 +318,22 @@ impl ServiceConfig {
                     .map_err(|_| {
                         ConfigError::InvalidConfig("Invalid INTERACTION_INTERFACE_LIFESPAN_SECS".to_string())
                     })?,
+                cleanup_interval_secs: secret_store
+                    .get("INTERACTION_CLEANUP_INTERVAL_SECS")
+                    .unwrap_or_else(|| InteractionConfig::DEFAULT_CLEANUP_INTERVAL_SECS.to_string())
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|secs| *secs > 0)
+                    .ok_or_else(|| {
+                        ConfigError::InvalidConfig("Invalid INTERACTION_CLEANUP_INTERVAL_SECS".to_string())
+                    })?,
+                sync_interval_secs: secret_store
+                    .get("INTERACTION_SYNC_INTERVAL_SECS")
+                    .unwrap_or_else(|| InteractionConfig::DEFAULT_SYNC_INTERVAL_SECS.to_string())
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|secs| *secs > 0)
+                    .ok_or_else(|| ConfigError::InvalidConfig("Invalid INTERACTION_SYNC_INTERVAL_SECS".to_string()))?,
             },
             cache: CacheConfig {
                 ttl: secret_store
@@ -245,15 +368,64 @@ pub struct LanguageConfig {
     /// Memory usage estimate:
     /// - Language cache: ~41 bytes per entry × 20,000 = ~0.82 MB
     pub cache_capacity: usize,
+    /// How often choices that failed to write through are retried
+    pub sync_interval_secs: u64,
+}
+
+impl LanguageConfig {
+    const DEFAULT_SYNC_INTERVAL_SECS: u64 = 300;
 }
 
 #[derive(Clone, Debug)]
 pub struct InteractionConfig {
     pub cache_capacity: usize,
     pub interface_lifespan_secs: i64,
+    pub cleanup_interval_secs: u64,
+    /// How often the cached interfaces are written to the database, a crash loses the changes since
+    pub sync_interval_secs: u64,
+}
+
+impl InteractionConfig {
+    const DEFAULT_CLEANUP_INTERVAL_SECS: u64 = 3600;
+    const DEFAULT_SYNC_INTERVAL_SECS: u64 = 300;
 }
 
 #[derive(Clone, Debug)]
 pub struct CacheConfig {
     pub ttl: u64,
 }
+
+// -----------------
+
+#[derive(Clone, Debug)]
+pub struct PlatformConfig {
+    pub instagram: InstagramConfig,
+}
+
+impl PlatformConfig {
+    pub fn from_env(secret_store: &SecretStore) -> Result<Self, ConfigError> {
+        Ok(Self {
+            instagram: InstagramConfig {
+                api_endpoint: secret_store
+                    .get("INSTAGRAM_API_ENDPOINT")
+                    .unwrap_or_else(|| InstagramConfig::DEFAULT_API_ENDPOINT.to_string()),
+                doc_id: secret_store
+                    .get("INSTAGRAM_DOC_ID")
+                    .unwrap_or_else(|| InstagramConfig::DEFAULT_DOC_ID.to_string()),
+            },
+        })
+    }
+}
+
+/// Initial values only, both can be swapped at runtime by the admin when Meta rotates them
+#[derive(Clone, Debug)]
+pub struct InstagramConfig {
+    pub api_endpoint: String,
+    pub doc_id: String,
+}
+
+impl InstagramConfig {
+    /// Used when the secrets are not set, as before they were configurable
+    const DEFAULT_API_ENDPOINT: &'static str = "https://www.instagram.com/graphql/query/";
+    const DEFAULT_DOC_ID: &'static str = "8845758582119845";
+}
@@ -1,70 +1,182 @@
-use std::sync::{Arc, OnceLock};
-
 use serde::{Deserialize, Serialize};
-use teloxide::types::UserId;
-use tokio::sync::Mutex;
+use teloxide::types::{Update, User, UserId};
+
+use crate::{service::Language, state::AppState, utils::is_admin};
 
-#[derive(Debug)]
+/// The user behind an update, resolved for every update and injected into handlers through dptree
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct UserContext {
-    pub telegram_user_id: OnceLock<UserId>,
-    pub telegram_user_name: OnceLock<String>,
-    pub is_admin: OnceLock<bool>,
-    user_tier: Arc<Mutex<UserTier>>,
+    user_id: UserId,
+    user_name: String,
+    is_admin: bool,
+    user_tier: UserTier,
+    language: Language,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Ord, PartialOrd)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Ord, PartialOrd)]
 pub enum UserTier {
     Subscriber = 3,
     OneTimePaid = 2,
+    #[default]
     Free = 1,
 }
 
 impl UserContext {
-    pub fn new() -> Self {
+    pub fn new(user_id: UserId, user_name: String, is_admin: bool, user_tier: UserTier, language: Language) -> Self {
         Self {
-            telegram_user_id: OnceLock::new(),
-            telegram_user_name: OnceLock::new(),
-            is_admin: OnceLock::new(),
-            user_tier: Arc::new(Mutex::new(UserTier::Free)),
+            user_id,
+            user_name,
+            is_admin,
+            user_tier,
+            language,
         }
     }
 
-    pub fn init(&self, user_id: UserId, user_name: String, is_admin: bool) {
-        let _ = self.telegram_user_id.set(user_id);
-        let _ = self.telegram_user_name.set(user_name);
-        let _ = self.is_admin.set(is_admin);
+    pub fn from_user(user: &User) -> Self {
+        let is_admin = is_admin(user.id).unwrap_or_else(|e| {
+            error!("Failed to check admin status of {}: {}", user.id, e);
+            false
+        });
+
+        let language = user
+            .language_code
+            .as_deref()
+            .and_then(Language::from_telegram_code)
+            .unwrap_or_default();
+
+        // Paid tiers are not available yet, everyone starts as a free user
+        Self::new(
+            user.id,
+            user.first_name.clone(),
+            is_admin,
+            UserTier::default(),
+            language,
+        )
+    }
+
+    /// Resolves the context of an update's sender, with the language they picked rather than their
+    /// Telegram client's. Updates without a sender (e.g. channel posts) have no context.
+    pub async fn resolve(update: Update) -> Option<Self> {
+        let user = update.from()?;
+        let mut context = Self::from_user(user);
+
+        let language = match AppState::get() {
+            Ok(state) => {
+                state
+                    .service_registry
+                    .language
+                    .resolve_user_language(&user.id.to_string(), user.language_code.as_deref())
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+
+        match language {
+            Ok(language) => context.language = language,
+            Err(e) => error!("Failed to resolve the language of {}: {}", user.id, e),
+        }
+
+        Some(context)
     }
 
     pub fn user_id(&self) -> UserId {
-        *self.telegram_user_id.get().expect("UserContext not initialized")
+        self.user_id
     }
 
     pub fn user_name(&self) -> &str {
-        self.telegram_user_name.get().expect("UserContext not initialized")
+        &self.user_name
     }
 
     pub fn is_admin(&self) -> bool {
-        *self.is_admin.get().expect("UserContext not initialized")
+        self.is_admin
+    }
+
+    pub fn user_tier(&self) -> UserTier {
+        self.user_tier
+    }
+
+    pub fn language(&self) -> Language {
+        self.language
     }
 
-    pub async fn user_tier(&self) -> UserTier {
-        self.user_tier.lock().await.clone()
+    pub fn locale(&self) -> &'static str {
+        self.language.locale()
     }
 
-    // pub async fn set_user_tier(&self, user_tier: UserTier) {
-    //     *self.user_tier.lock().await = user_tier;
-    // }
+    pub fn with_language(&self, language: Language) -> Self {
+        Self {
+            language,
+            ..self.clone()
+        }
+    }
 }
 
-static USER_DATA: OnceLock<UserContext> = OnceLock::new();
+#[cfg(test)]
+mod tests {
+    use std::ops::ControlFlow;
 
-impl UserContext {
-    pub fn global() -> &'static UserContext {
-        USER_DATA.get_or_init(UserContext::new)
+    use teloxide::{dispatching::DpHandlerDescription, dptree};
+
+    use super::*;
+
+    fn message_update(user_id: u64, first_name: &str, language_code: &str) -> Update {
+        // `Update` only deserializes from borrowed input, hence the round trip through a string
+        let update = serde_json::json!({
+            "update_id": user_id,
+            "message": {
+                "message_id": 1,
+                "date": 1_700_000_000,
+                "chat": { "id": user_id, "type": "private", "first_name": first_name },
+                "from": { "id": user_id, "is_bot": false, "first_name": first_name, "language_code": language_code },
+                "text": "/start",
+            }
+        });
+
+        serde_json::from_str(&update.to_string()).unwrap()
     }
 
-    pub async fn ensure_initialized(user_id: UserId, user_name: String, is_admin: bool) {
-        let user_data = Self::global();
-        user_data.init(user_id, user_name, is_admin);
+    #[tokio::test]
+    async fn test_concurrent_users_are_isolated() {
+        let handler: dptree::Handler<'_, dptree::di::DependencyMap, UserContext, DpHandlerDescription> =
+            dptree::filter_map(|update: Update| update.from().map(UserContext::from_user)).endpoint(
+                |context: UserContext| async move {
+                    tokio::task::yield_now().await;
+                    context
+                },
+            );
+
+        let (first, second) = tokio::join!(
+            handler.dispatch(dptree::deps![message_update(1, "Alice", "en-US")]),
+            handler.dispatch(dptree::deps![message_update(2, "Bob", "de")]),
+        );
+
+        let (ControlFlow::Break(first), ControlFlow::Break(second)) = (first, second) else {
+            panic!("handler did not run");
+        };
+
+        assert_eq!(first.user_id(), UserId(1));
+        assert_eq!(first.user_name(), "Alice");
+        assert_eq!(second.user_id(), UserId(2));
+        assert_eq!(second.user_name(), "Bob");
+        assert!(!first.is_admin() && !second.is_admin());
+        assert_eq!(first.user_tier(), UserTier::Free);
+        assert_eq!(first.locale(), "en");
+        assert_eq!(second.locale(), "de");
+    }
+
+    #[tokio::test]
+    async fn test_update_without_sender() {
+        let update = serde_json::json!({
+            "update_id": 1,
+            "channel_post": {
+                "message_id": 1,
+                "date": 1_700_000_000,
+                "chat": { "id": -100, "type": "channel", "title": "news" },
+                "text": "hello",
+            }
+        });
+        let update: Update = serde_json::from_str(&update.to_string()).unwrap();
+
+        assert!(UserContext::resolve(update).await.is_none());
     }
 }
@@ -1,17 +1,29 @@
+use std::str::FromStr;
+
 use teloxide::{
     adaptors::Throttle,
     dispatching::dialogue::ErasedStorage,
-    payloads::EditMessageTextSetters,
+    payloads::{EditMessageTextSetters, SendMessageSetters},
     prelude::{Dialogue, Requester},
+    types::{ChatId, MessageId},
     Bot,
 };
 
 use teloxide::types::MaybeInaccessibleMessage;
+use tokio::{sync::oneshot, task::JoinSet};
 
 use crate::{
-    error::HandlerResult,
-    handler::keyboard::{get_back_to_main_menu_keyboard, get_main_menu_keyboard, get_platform_keyboard},
-    platform::{Platform, PostDownloadState},
+    context::UserContext,
+    error::{BotError, HandlerResult},
+    handler::keyboard::{
+        get_back_to_main_menu_keyboard, get_download_ask_for_link_keyboard, get_main_menu_keyboard,
+        get_platform_keyboard,
+    },
+    platform::{
+        instagram::model::{InstagramIdentifier, ProfileRange},
+        DownloadState, Platform, PlatformIdentifier, PlatformInstagram, PostDownloadState,
+    },
+    runtime::{replace_preview, DownloadTask, ProgressReporter, TaskContext},
     service::dialogue::model::DialogueState,
     state::AppState,
 };
@@ -20,15 +32,16 @@ pub(super) async fn handle_callback_select_platform(
     bot: &Throttle<Bot>,
     dialogue: Dialogue<DialogueState, ErasedStorage<DialogueState>>,
     message: MaybeInaccessibleMessage,
+    context: &UserContext,
 ) -> HandlerResult<()> {
     info!("handle_callback_select_platform");
 
     bot.edit_message_text(
         message.chat().id,
         message.id(),
-        t!("callbacks.download.select_platform"),
+        t!("callbacks.download.select_platform", locale = context.locale()),
     )
-    .reply_markup(get_platform_keyboard().await?)
+    .reply_markup(get_platform_keyboard(context.locale()).await?)
     .await?;
 
     dialogue.update(DialogueState::SelectPlatform).await?;
@@ -40,6 +53,7 @@ pub(super) async fn handle_callback_asking_for_download_link(
     bot: &Throttle<Bot>,
     dialogue: Dialogue<DialogueState, ErasedStorage<DialogueState>>,
     message: MaybeInaccessibleMessage,
+    context: &UserContext,
     platform: Platform,
 ) -> HandlerResult<()> {
     info!("handle_callback_asking_for_download_link");
@@ -48,10 +62,11 @@ pub(super) async fn handle_callback_asking_for_download_link(
         message.id(),
         t!(
             "callbacks.download.ask_for_download_link",
+            locale = context.locale(),
             platform = platform.to_string()
         ),
     )
-    .reply_markup(get_back_to_main_menu_keyboard())
+    .reply_markup(get_back_to_main_menu_keyboard(context.locale()))
     .await?;
 
     dialogue
@@ -68,40 +83,353 @@ pub(super) async fn handle_callback_confirm_download(
     bot: &Throttle<Bot>,
     dialogue: Dialogue<DialogueState, ErasedStorage<DialogueState>>,
     message: MaybeInaccessibleMessage,
+    context: &UserContext,
 ) -> HandlerResult<()> {
     info!("handle_callback_confirm_download");
 
     if let Some(DialogueState::ConfirmDownload { media_file }) = dialogue.get().await? {
         let queue_manager = &AppState::get()?.runtime.queue_manager;
 
-        let state = queue_manager.handle_download_confirmation(&media_file.id).await?;
+        let Some(rx) = queue_manager
+            .handle_download_confirmation(context.user_id().0, &media_file.id)
+            .await?
+        else {
+            // The preview was not confirmed in time
+            let is_photo = message.regular_message().and_then(|m| m.photo()).is_some();
+            replace_preview(
+                bot,
+                message.chat().id,
+                message.id(),
+                is_photo,
+                t!("callbacks.download.expired", locale = context.locale()),
+                context.locale(),
+            )
+            .await?;
+            dialogue.update(DialogueState::Start).await?;
+            return Ok(());
+        };
+
+        // Waiting here would hold back the other updates of the chat, such as the cancel button of the task
+        tokio::spawn(track_delivery(
+            bot.clone(),
+            dialogue,
+            message.chat().id,
+            message.id(),
+            rx,
+            context.locale(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Waits for the delivery of a confirmed preview and updates the dialogue once it is done
+async fn track_delivery(
+    bot: Throttle<Bot>,
+    dialogue: Dialogue<DialogueState, ErasedStorage<DialogueState>>,
+    chat_id: ChatId,
+    message_id: MessageId,
+    rx: oneshot::Receiver<PostDownloadState>,
+    locale: &'static str,
+) {
+    let result = match rx.await {
+        Ok(PostDownloadState::Error) => bot
+            .edit_message_text(chat_id, message_id, t!("callbacks.download.error", locale = locale))
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string()),
+        Ok(PostDownloadState::Success | PostDownloadState::Cancelled) => {
+            dialogue.update(DialogueState::Start).await.map_err(|e| e.to_string())
+        }
+        // Dropped on shutdown, the user was told already
+        Err(_) => Ok(()),
+    };
+
+    if let Err(e) = result {
+        error!("Failed to finish delivery in chat {}: {}", chat_id, e);
+    }
+}
+
+pub(super) async fn handle_callback_profile_range(
+    bot: &Throttle<Bot>,
+    dialogue: Dialogue<DialogueState, ErasedStorage<DialogueState>>,
+    message: MaybeInaccessibleMessage,
+    context: &UserContext,
+    range: &str,
+) -> HandlerResult<()> {
+    info!("handle_callback_profile_range");
+
+    let Some(DialogueState::SelectProfileRange { username }) = dialogue.get().await? else {
+        return Ok(());
+    };
+
+    let range = ProfileRange::from_str(range).map_err(|e| BotError::DialogueStateError(e.to_string()))?;
+
+    bot.edit_message_text(
+        message.chat().id,
+        message.id(),
+        t!(
+            "callbacks.download.profile.fetching",
+            locale = context.locale(),
+            username = username
+        ),
+    )
+    .await?;
+
+    let app_state = AppState::get()?;
+    let telegram_user_id = context.user_id().to_string();
+
+    let platform = app_state
+        .platform_registry
+        .get_platform::<PlatformInstagram>(&Platform::Instagram)
+        .ok_or_else(|| BotError::AppStateError("Instagram platform not registered".into()))?;
+
+    // The whole job takes a single slot of the rate limit, its posts are not counted one by one
+    let identifier = app_state
+        .platform_registry
+        .generate_identifier(&PlatformIdentifier::Instagram(InstagramIdentifier::Profile {
+            username: username.clone(),
+        }));
+    if !app_state
+        .service_registry
+        .ratelimit
+        .check_rate_limit(&telegram_user_id, &identifier)
+        .await?
+    {
+        bot.edit_message_text(
+            message.chat().id,
+            message.id(),
+            t!("messages.download.download_limit_reached", locale = context.locale()),
+        )
+        .reply_markup(get_main_menu_keyboard(context.locale()))
+        .await?;
+
+        dialogue.update(DialogueState::Start).await?;
+
+        return Ok(());
+    }
+
+    let posts = match platform.fetch_profile_posts(&username, &range, &telegram_user_id).await {
+        Ok(posts) => posts,
+        Err(e) => {
+            error!("Failed to fetch profile posts of {}: {}", username, e);
 
-        match state {
-            PostDownloadState::Success => dialogue.update(DialogueState::Start).await?,
+            bot.edit_message_text(
+                message.chat().id,
+                message.id(),
+                t!(
+                    "callbacks.download.profile.failed",
+                    locale = context.locale(),
+                    username = username
+                ),
+            )
+            .reply_markup(get_main_menu_keyboard(context.locale()))
+            .await?;
 
-            PostDownloadState::Error => {
-                bot.edit_message_text(message.chat().id, message.id(), t!("callbacks.download.error"))
-                    .await?;
+            dialogue.update(DialogueState::Start).await?;
 
-                // TODO: 重试
+            return Ok(());
+        }
+    };
+
+    if posts.is_empty() {
+        bot.edit_message_text(
+            message.chat().id,
+            message.id(),
+            t!(
+                "callbacks.download.profile.no_posts",
+                locale = context.locale(),
+                username = username
+            ),
+        )
+        .reply_markup(get_download_ask_for_link_keyboard(
+            Platform::Instagram,
+            context.locale(),
+        ))
+        .await?;
+
+        dialogue.update(DialogueState::Start).await?;
+
+        return Ok(());
+    }
+
+    let task_context = TaskContext {
+        user_id: context.user_id().0,
+        chat_id: message.chat().id.0,
+        message_id: message.id().0,
+        user_tier: context.user_tier(),
+        platform: Platform::Instagram,
+        language: context.language(),
+    };
+
+    let queue_manager = &app_state.runtime.queue_manager;
+
+    let total = posts.len();
+    let mut results = Vec::with_capacity(total);
+
+    for post in posts {
+        let task = DownloadTask::new_bulk(post.url(), task_context.clone());
+        match queue_manager.enqueue_download_task(task).await {
+            Ok(rx) => results.push(rx),
+            Err(e) if results.is_empty() => return Err(e.into()),
+            // The posts queued so far are still delivered and tracked, the rest count as failed
+            Err(e) => {
+                warn!("Queued {} of {} posts of {}: {}", results.len(), total, username, e);
+                break;
             }
         }
     }
+    let skipped = total - results.len();
+
+    bot.edit_message_text(
+        message.chat().id,
+        message.id(),
+        t!(
+            "callbacks.download.profile.progress",
+            locale = context.locale(),
+            username = username,
+            completed = skipped,
+            total = total,
+            failed = skipped
+        ),
+    )
+    .await?;
+
+    dialogue.update(DialogueState::Start).await?;
+
+    tokio::spawn(track_bulk_download(
+        bot.clone(),
+        message.chat().id,
+        message.id(),
+        username,
+        results,
+        skipped,
+        context.locale(),
+    ));
 
     Ok(())
 }
 
+/// Keeps a single progress message up to date while the tasks of a bulk download complete, `skipped` posts
+/// could not be queued and count as failed
+async fn track_bulk_download(
+    bot: Throttle<Bot>,
+    chat_id: ChatId,
+    message_id: MessageId,
+    username: String,
+    results: Vec<oneshot::Receiver<DownloadState>>,
+    skipped: usize,
+    locale: &'static str,
+) {
+    let total = results.len() + skipped;
+    let mut completed = skipped;
+    let mut failed = skipped;
+
+    let mut pending = JoinSet::new();
+    for rx in results {
+        pending.spawn(rx);
+    }
+
+    let reporter = ProgressReporter::spawn(bot.clone(), chat_id, message_id, None, {
+        let username = username.clone();
+        move |(completed, failed): &(usize, usize)| {
+            t!(
+                "callbacks.download.profile.progress",
+                locale = locale,
+                username = username,
+                completed = completed,
+                total = total,
+                failed = failed
+            )
+            .to_string()
+        }
+    });
+
+    while let Some(result) = pending.join_next().await {
+        completed += 1;
+
+        if !matches!(result, Ok(Ok(DownloadState::Success(_)))) {
+            failed += 1;
+        }
+
+        reporter.report((completed, failed));
+    }
+
+    reporter.finish().await;
+
+    let _ = bot.delete_message(chat_id, message_id).await;
+
+    if let Err(e) = bot
+        .send_message(
+            chat_id,
+            t!(
+                "callbacks.download.profile.completed",
+                locale = locale,
+                username = username,
+                succeeded = total - failed,
+                total = total
+            ),
+        )
+        .reply_markup(get_download_ask_for_link_keyboard(Platform::Instagram, locale))
+        .await
+    {
+        error!("Failed to send bulk download summary: {}", e);
+    }
+}
+
 pub(super) async fn handle_callback_cancel_download(
     bot: &Throttle<Bot>,
+    dialogue: Dialogue<DialogueState, ErasedStorage<DialogueState>>,
     message: MaybeInaccessibleMessage,
+    context: &UserContext,
 ) -> HandlerResult<()> {
     info!("handle_callback_cancel_download");
+
+    if let Some(DialogueState::ConfirmDownload { media_file }) = dialogue.get().await? {
+        AppState::get()?
+            .runtime
+            .queue_manager
+            .cancel_pending_confirmation(context.user_id().0, &media_file.id)
+            .await?;
+        dialogue.update(DialogueState::Start).await?;
+    }
+
+    let is_photo = message.regular_message().and_then(|m| m.photo()).is_some();
+    replace_preview(
+        bot,
+        message.chat().id,
+        message.id(),
+        is_photo,
+        t!("callbacks.download.cancel_download", locale = context.locale()),
+        context.locale(),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Cancels a queued or running download from the button on its progress message
+pub(super) async fn handle_callback_cancel_task(
+    bot: &Throttle<Bot>,
+    message: MaybeInaccessibleMessage,
+    context: &UserContext,
+    task_id: &str,
+) -> HandlerResult<()> {
+    info!("handle_callback_cancel_task");
+
+    let queue_manager = &AppState::get()?.runtime.queue_manager;
+
+    // The task already finished and replaced this message
+    if !queue_manager.cancel_task(context.user_id().0, task_id).await? {
+        return Ok(());
+    }
+
     bot.edit_message_text(
         message.chat().id,
         message.id(),
-        t!("callbacks.download.cancel_download"),
+        t!("callbacks.download.cancel_download", locale = context.locale()),
     )
-    .reply_markup(get_main_menu_keyboard())
+    .reply_markup(get_main_menu_keyboard(context.locale()))
     .await?;
 
     Ok(())
@@ -14,16 +14,18 @@ pub async fn handle_callback_language_change(
     bot: &Throttle<Bot>,
     dialogue: Dialogue<DialogueState, ErasedStorage<DialogueState>>,
     message: MaybeInaccessibleMessage,
+    context: &UserContext,
     lang_code: &str,
 ) -> HandlerResult<()> {
     let status_message = bot
-        .send_message(message.chat().id, t!("callbacks.language.change_language_status"))
+        .send_message(
+            message.chat().id,
+            t!("callbacks.language.change_language_status", locale = context.locale()),
+        )
         .await?;
 
     let language = Language::from_str(lang_code).unwrap_or(Language::English);
 
-    let context = UserContext::global();
-
     let user_id = context.user_id().to_string();
 
     let app_state = AppState::get()?;
@@ -33,7 +35,8 @@ pub async fn handle_callback_language_change(
         .set_user_language(&user_id, language)
         .await?;
 
-    rust_i18n::set_locale(&language.to_string());
+    // Everything from here on is rendered in the new language
+    let context = &context.with_language(language);
 
     let status_msg = bot
         .edit_message_text(
@@ -41,7 +44,11 @@ pub async fn handle_callback_language_change(
             status_message.id,
             t!(
                 "callbacks.language.change_language",
-                language = t!(format!("buttons.language_menu.{}", language.to_string()))
+                locale = context.locale(),
+                language = t!(
+                    format!("buttons.language_menu.{}", language.to_string()),
+                    locale = context.locale()
+                )
             ),
         )
         .await?;
@@ -54,13 +61,11 @@ pub async fn handle_callback_language_change(
         .unwrap()
         .unwrap_or_default();
 
-    let context = UserContext::global();
-
     // Update commands
     if context.is_admin() {
-        command::setup_admin_commands(bot, message.chat().id).await?;
+        command::setup_admin_commands(bot, message.chat().id, context.locale()).await?;
     } else {
-        command::setup_user_commands(bot).await?;
+        command::setup_user_commands(bot, message.chat().id, context.locale()).await?;
     }
 
     bot.delete_message(message.chat().id, status_msg.id).await?;
@@ -70,20 +75,22 @@ pub async fn handle_callback_language_change(
         s if s.starts_with("platform:") => {
             let platform_str = s.split(":").nth(1).unwrap_or("instagram");
             let platform = Platform::from_str(platform_str)?;
-            super::download::handle_callback_asking_for_download_link(bot, dialogue, message, platform).await?
+            super::download::handle_callback_asking_for_download_link(bot, dialogue, message, context, platform).await?
         }
         // "confirm_download" => super::download::handle_callback_confirm_download(bot, dialogue, message).await?,
-        "cancel_download" => super::download::handle_callback_cancel_download(bot, message).await?,
+        "cancel_download" => super::download::handle_callback_cancel_download(bot, dialogue, message, context).await?,
 
         // profile
-        "profile_menu" | "cancel_auth" => super::profile::handle_callback_profile_menu(bot, message).await?,
-        "auth_login" => super::profile::handle_callback_auth_login(bot, dialogue, message).await?,
-        "show_usage" => super::profile::handle_callback_show_usage(bot, message).await?,
+        "profile_menu" | "cancel_auth" => super::profile::handle_callback_profile_menu(bot, message, context).await?,
+        "auth_login" => super::profile::handle_callback_auth_login(bot, dialogue, message, context).await?,
+        "show_usage" => super::profile::handle_callback_show_usage(bot, message, context).await?,
 
         // navigation
-        "back_to_main_menu" => super::navigation::handle_callback_back_to_main_menu(bot, dialogue, message).await?,
+        "back_to_main_menu" => {
+            super::navigation::handle_callback_back_to_main_menu(bot, dialogue, message, context).await?
+        }
 
-        _ => super::navigation::handle_callback_back_to_main_menu(bot, dialogue, message).await?,
+        _ => super::navigation::handle_callback_back_to_main_menu(bot, dialogue, message, context).await?,
     }
 
     Ok(())
@@ -27,6 +27,7 @@ async fn handle_callback(
     bot: Throttle<Bot>,
     dialogue: Dialogue<DialogueState, ErasedStorage<DialogueState>>,
     q: CallbackQuery,
+    context: UserContext,
 ) -> HandlerResult<()> {
     let data = q
         .data
@@ -39,8 +40,6 @@ async fn handle_callback(
         .message
         .ok_or_else(|| BotError::DialogueStateError("No message".into()))?;
 
-    let context = UserContext::global();
-
     let telegram_user_id = context.user_id().to_string();
 
     match data.as_str() {
@@ -50,7 +49,7 @@ async fn handle_callback(
                 .set_last_interface(&telegram_user_id, "select_platform")
                 .await?;
 
-            download::handle_callback_select_platform(&bot, dialogue, message).await?
+            download::handle_callback_select_platform(&bot, dialogue, message, &context).await?
         }
 
         s if s.starts_with("platform:") => {
@@ -62,20 +61,33 @@ async fn handle_callback(
 
             let platform = Platform::from_str(platform_str).unwrap_or_default();
 
-            download::handle_callback_asking_for_download_link(&bot, dialogue, message, platform).await?
+            download::handle_callback_asking_for_download_link(&bot, dialogue, message, &context, platform).await?
         }
 
         "confirm_download" => {
             interaction
                 .set_last_interface(&telegram_user_id, "confirm_download")
                 .await?;
-            download::handle_callback_confirm_download(&bot, dialogue, message).await?
+            download::handle_callback_confirm_download(&bot, dialogue, message, &context).await?
+        }
+        s if s.starts_with("profile_range:") => {
+            interaction
+                .set_last_interface(&telegram_user_id, "profile_range")
+                .await?;
+
+            let range = s.trim_start_matches("profile_range:");
+
+            download::handle_callback_profile_range(&bot, dialogue, message, &context, range).await?
         }
         "cancel_download" => {
             interaction
                 .set_last_interface(&telegram_user_id, "cancel_download")
                 .await?;
-            download::handle_callback_cancel_download(&bot, message).await?
+            download::handle_callback_cancel_download(&bot, dialogue, message, &context).await?
+        }
+        s if s.starts_with("cancel_task:") => {
+            let task_id = s.trim_start_matches("cancel_task:");
+            download::handle_callback_cancel_task(&bot, message, &context, task_id).await?
         }
 
         // profile
@@ -83,18 +95,18 @@ async fn handle_callback(
             interaction
                 .set_last_interface(&telegram_user_id, "profile_menu")
                 .await?;
-            profile::handle_callback_profile_menu(&bot, message).await?
+            profile::handle_callback_profile_menu(&bot, message, &context).await?
         }
 
         "show_usage" => {
             interaction.set_last_interface(&telegram_user_id, "show_usage").await?;
-            profile::handle_callback_show_usage(&bot, message).await?
+            profile::handle_callback_show_usage(&bot, message, &context).await?
         }
         // "cancel_auth" => {
         //     interaction
         //         .set_last_interface(ctx.telegram_user_id.to_string().as_str(), "cancel_auth")
         //         .await?;
-        //     profile::handle_callback_profile_menu(&bot, message).await?
+        //     profile::handle_callback_profile_menu(&bot, message, &context).await?
         // }
         // "auth_login" => {
         //     interaction
@@ -108,17 +120,17 @@ async fn handle_callback(
             // interaction
             //     .set_last_interface(ctx.telegram_user_id.to_string().as_str(), "back_to_main_menu")
             //     .await?;
-            navigation::handle_callback_back_to_main_menu(&bot, dialogue, message).await?
+            navigation::handle_callback_back_to_main_menu(&bot, dialogue, message, &context).await?
         }
 
         // language
         s if s.starts_with("lang:") => {
             let lang_code = s.split(":").nth(1).unwrap_or("en");
-            language::handle_callback_language_change(&bot, dialogue, message, lang_code).await?
+            language::handle_callback_language_change(&bot, dialogue, message, &context, lang_code).await?
         }
         _ => {
-            bot.send_message(message.chat().id, t!("callback.unknown"))
-                .reply_markup(get_main_menu_keyboard())
+            bot.send_message(message.chat().id, t!("callback.unknown", locale = context.locale()))
+                .reply_markup(get_main_menu_keyboard(context.locale()))
                 .await?;
         }
     }
@@ -7,19 +7,23 @@ use teloxide::{
     Bot,
 };
 
-use crate::{error::HandlerResult, handler::keyboard::get_main_menu_keyboard, service::dialogue::model::DialogueState};
+use crate::{
+    context::UserContext, error::HandlerResult, handler::keyboard::get_main_menu_keyboard,
+    service::dialogue::model::DialogueState,
+};
 
 pub(super) async fn handle_callback_back_to_main_menu(
     bot: &Throttle<Bot>,
     dialogue: Dialogue<DialogueState, ErasedStorage<DialogueState>>,
     message: MaybeInaccessibleMessage,
+    context: &UserContext,
 ) -> HandlerResult<()> {
     bot.edit_message_text(
         message.chat().id,
         message.id(),
-        t!("callbacks.navigation.back_to_main_menu"),
+        t!("callbacks.navigation.back_to_main_menu", locale = context.locale()),
     )
-    .reply_markup(get_main_menu_keyboard())
+    .reply_markup(get_main_menu_keyboard(context.locale()))
     .await?;
 
     dialogue.update(DialogueState::Start).await?;
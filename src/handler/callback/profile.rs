@@ -18,28 +18,38 @@ use crate::{
     state::AppState,
 };
 
-pub async fn handle_callback_profile_menu(bot: &Throttle<Bot>, message: MaybeInaccessibleMessage) -> HandlerResult<()> {
+pub async fn handle_callback_profile_menu(
+    bot: &Throttle<Bot>,
+    message: MaybeInaccessibleMessage,
+    context: &UserContext,
+) -> HandlerResult<()> {
     info!("handle_callback_profile_menu");
-    bot.edit_message_text(message.chat().id, message.id(), t!("callbacks.profile.profile_menu"))
-        .reply_markup(get_profile_menu_keyboard())
-        .await?;
+    bot.edit_message_text(
+        message.chat().id,
+        message.id(),
+        t!("callbacks.profile.profile_menu", locale = context.locale()),
+    )
+    .reply_markup(get_profile_menu_keyboard(context.locale()))
+    .await?;
 
     Ok(())
 }
 
-pub async fn handle_callback_show_usage(bot: &Throttle<Bot>, message: MaybeInaccessibleMessage) -> HandlerResult<()> {
+pub async fn handle_callback_show_usage(
+    bot: &Throttle<Bot>,
+    message: MaybeInaccessibleMessage,
+    context: &UserContext,
+) -> HandlerResult<()> {
     info!("handle_callback_show_usage");
 
     let processing_msg = bot
         .edit_message_text(
             message.chat().id,
             message.id(),
-            t!("callbacks.profile.usage_processing"),
+            t!("callbacks.profile.usage_processing", locale = context.locale()),
         )
         .await?;
 
-    let context = UserContext::global();
-
     let telegram_user_id = context.user_id().to_string();
 
     let state = AppState::get()?;
@@ -51,6 +61,7 @@ pub async fn handle_callback_show_usage(bot: &Throttle<Bot>, message: MaybeInacc
     // TODO: add more data besides rate limit info
     let usage_text = t!(
         "callbacks.profile.usage",
+        locale = context.locale(),
         total_requests = rate_limit_info.total_requests,
         total_used_requests = rate_limit_info.total_used_requests,
         remaining_requests = rate_limit_info.remaining_requests,
@@ -58,7 +69,7 @@ pub async fn handle_callback_show_usage(bot: &Throttle<Bot>, message: MaybeInacc
     );
 
     bot.edit_message_text(message.chat().id, processing_msg.id, usage_text)
-        .reply_markup(get_back_to_main_menu_keyboard())
+        .reply_markup(get_back_to_main_menu_keyboard(context.locale()))
         .await?;
 
     Ok(())
@@ -68,12 +79,17 @@ pub(super) async fn handle_callback_auth_login(
     bot: &Throttle<Bot>,
     dialogue: Dialogue<DialogueState, ErasedStorage<DialogueState>>,
     message: MaybeInaccessibleMessage,
+    context: &UserContext,
 ) -> HandlerResult<()> {
     info!("handle_callback_auth_login");
 
     let username_msg = bot
-        .edit_message_text(message.chat().id, message.id(), t!("callbacks.profile.auth_login"))
-        .reply_markup(get_cancel_auth_keyboard()) // TODO not working?
+        .edit_message_text(
+            message.chat().id,
+            message.id(),
+            t!("callbacks.profile.auth_login", locale = context.locale()),
+        )
+        .reply_markup(get_cancel_auth_keyboard(context.locale())) // TODO not working?
         .await?;
 
     dialogue
@@ -107,7 +123,7 @@ pub(super) async fn handle_callback_auth_login(
 //     info!("handle_callback_cancel_logout");
 
 //     bot.edit_message_text(message.chat().id, message.id(), t!("callbacks.profile.cancel_logout"))
-//         .reply_markup(get_profile_menu_keyboard())
+//         .reply_markup(get_profile_menu_keyboard(context.locale()))
 //         .await?;
 
 //     Ok(())
@@ -141,7 +157,7 @@ pub(super) async fn handle_callback_auth_login(
 //         .await?;
 
 //     bot.edit_message_text(message.chat().id, status_msg.id, t!("callbacks.profile.logout_success"))
-//         .reply_markup(get_profile_menu_keyboard())
+//         .reply_markup(get_profile_menu_keyboard(context.locale()))
 //         .await?;
 
 //     dialogue.update(DialogueState::ConfirmLogout).await?;
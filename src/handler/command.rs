@@ -1,3 +1,4 @@
+use chrono::Utc;
 use teloxide::adaptors::Throttle;
 use teloxide::dispatching::dialogue::ErasedStorage;
 use teloxide::dispatching::{HandlerExt, UpdateHandler};
@@ -8,15 +9,16 @@ use crate::command::{self, Command};
 use crate::config::AppConfig;
 use crate::context::UserContext;
 use crate::error::{BotError, HandlerResult};
+use crate::platform::{Platform, PlatformInstagram};
 use crate::service::dialogue::model::DialogueState;
-use crate::utils::is_admin;
+use crate::state::AppState;
 
 use super::keyboard::{get_language_menu_keyboard, get_main_menu_keyboard};
 
-async fn handle_language(bot: Throttle<Bot>, msg: Message) -> HandlerResult<()> {
+async fn handle_language(bot: Throttle<Bot>, msg: Message, context: &UserContext) -> HandlerResult<()> {
     bot.delete_message(msg.chat.id, msg.id).await?;
-    bot.send_message(msg.chat.id, t!("commands.language"))
-        .reply_markup(get_language_menu_keyboard())
+    bot.send_message(msg.chat.id, t!("commands.language", locale = context.locale()))
+        .reply_markup(get_language_menu_keyboard(context.locale()))
         .await?;
 
     Ok(())
@@ -26,21 +28,13 @@ async fn handle_start(
     bot: Throttle<Bot>,
     dialogue: Dialogue<DialogueState, ErasedStorage<DialogueState>>,
     msg: Message,
+    context: &UserContext,
 ) -> HandlerResult<()> {
-    let user_id = msg.from.clone().unwrap().id;
-
-    let first_name = msg.from.clone().unwrap().first_name;
-
-    let is_admin = is_admin(user_id)?;
-
-    UserContext::ensure_initialized(user_id, first_name, is_admin).await;
-
-    let context = UserContext::global();
-
     info!("context: {:?}", context);
 
     let welcome_text = t!(
         "commands.start.unauthenticated",
+        locale = context.locale(),
         first_name = context.user_name(),
         telegram_user_id = context.user_id().to_string()
     );
@@ -48,7 +42,7 @@ async fn handle_start(
     bot.delete_message(msg.chat.id, msg.id).await?;
 
     bot.send_message(msg.chat.id, welcome_text)
-        .reply_markup(get_main_menu_keyboard())
+        .reply_markup(get_main_menu_keyboard(context.locale()))
         .await?;
 
     dialogue
@@ -57,28 +51,36 @@ async fn handle_start(
         .map_err(|e| BotError::DialogueStateError(e.to_string()))?;
 
     // setup commands
-    if is_admin {
-        command::setup_admin_commands(&bot, msg.chat.id).await?;
+    if context.is_admin() {
+        command::setup_admin_commands(&bot, msg.chat.id, context.locale()).await?;
     } else {
-        command::setup_user_commands(&bot).await?;
+        command::setup_user_commands(&bot, msg.chat.id, context.locale()).await?;
     }
 
     Ok(())
 }
 
-async fn handle_help(bot: Throttle<Bot>, msg: Message) -> HandlerResult<()> {
+async fn handle_help(bot: Throttle<Bot>, msg: Message, context: &UserContext) -> HandlerResult<()> {
     bot.delete_message(msg.chat.id, msg.id).await?;
     let download_limit = AppConfig::get()?.service.ratelimit.daily_limit;
-    bot.send_message(msg.chat.id, t!("commands.help", download_limit = download_limit))
-        .reply_markup(get_main_menu_keyboard())
-        .await?;
+    bot.send_message(
+        msg.chat.id,
+        t!(
+            "commands.help",
+            locale = context.locale(),
+            download_limit = download_limit
+        ),
+    )
+    .reply_markup(get_main_menu_keyboard(context.locale()))
+    .await?;
 
     Ok(())
 }
 
-async fn handle_unknown_command(bot: Throttle<Bot>, msg: Message) -> HandlerResult<()> {
+async fn handle_unknown_command(bot: Throttle<Bot>, msg: Message, context: &UserContext) -> HandlerResult<()> {
     bot.delete_message(msg.chat.id, msg.id).await?;
-    bot.send_message(msg.chat.id, t!("commands.unknown_command")).await?;
+    bot.send_message(msg.chat.id, t!("commands.unknown_command", locale = context.locale()))
+        .await?;
     Ok(())
 }
 
@@ -99,18 +101,268 @@ async fn handle_unknown_command(bot: Throttle<Bot>, msg: Message) -> HandlerResu
 //     Ok(())
 // }
 
+/// Shows the Instagram API config, or swaps one of its values when an argument is given
+async fn handle_instagram_config(
+    bot: Throttle<Bot>,
+    msg: Message,
+    cmd: Command,
+    context: &UserContext,
+) -> HandlerResult<()> {
+    bot.delete_message(msg.chat.id, msg.id).await?;
+
+    let platform = AppState::get()?
+        .platform_registry
+        .get_platform::<PlatformInstagram>(&Platform::Instagram)
+        .ok_or_else(|| BotError::AppStateError("Instagram platform not registered".into()))?;
+
+    let text = match cmd {
+        Command::DocId(doc_id) if !doc_id.trim().is_empty() => {
+            platform.set_doc_id(doc_id.trim());
+            t!("commands.instagram_config.updated", locale = context.locale())
+        }
+        Command::Endpoint(endpoint) if !endpoint.trim().is_empty() => {
+            match platform.set_api_endpoint(endpoint.trim()) {
+                Ok(()) => t!("commands.instagram_config.updated", locale = context.locale()),
+                Err(_) => t!(
+                    "commands.instagram_config.invalid_endpoint",
+                    locale = context.locale(),
+                    endpoint = endpoint.trim()
+                ),
+            }
+        }
+        _ => Default::default(),
+    };
+
+    let api_config = platform.api_config();
+
+    let current = t!(
+        "commands.instagram_config.current",
+        locale = context.locale(),
+        endpoint = api_config.api_endpoint,
+        doc_id = api_config.doc_id
+    );
+
+    bot.send_message(msg.chat.id, format!("{}{}", text, current)).await?;
+
+    Ok(())
+}
+
+/// Shows runtime health to admins: the download failures per reason since startup, the media cache, the
+/// queues, the worker slots and the background jobs
+async fn handle_status(bot: Throttle<Bot>, msg: Message, context: &UserContext) -> HandlerResult<()> {
+    let app_state = AppState::get()?;
+    let failures = app_state.platform_registry.failure_stats();
+
+    let failures = if failures.is_empty() {
+        t!("commands.status.no_failures", locale = context.locale()).to_string()
+    } else {
+        failures
+            .iter()
+            .map(|(reason, count)| format!("• {:?}: {}", reason, count))
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    let jobs = app_state
+        .runtime
+        .scheduler
+        .job_statuses()
+        .iter()
+        .map(|job| match (job.last_run, job.last_duration) {
+            (Some(last_run), Some(duration)) => format!(
+                "• {} ({}): {} {}, {} ms{}",
+                job.name,
+                job.schedule,
+                if job.last_error.is_some() { "❌" } else { "✅" },
+                last_run.format("%Y-%m-%d %H:%M:%S UTC"),
+                duration.as_millis(),
+                job.last_error
+                    .as_ref()
+                    .map(|e| format!("\n  {}", e))
+                    .unwrap_or_default()
+            ),
+            _ => format!(
+                "• {} ({}): {}",
+                job.name,
+                job.schedule,
+                t!("commands.status.job_not_run", locale = context.locale())
+            ),
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let cache = app_state.service_registry.cache.stats();
+
+    let [(download, download_capacity), (post_download, post_download_capacity)] =
+        app_state.runtime.queue_manager.queue_stats().await;
+
+    // ⏳ is how long the current task has been running, 🔁 how often the slot was restarted after a panic
+    let now = Utc::now();
+    let workers = app_state
+        .runtime
+        .worker_pool
+        .health()
+        .iter()
+        .flat_map(|worker| {
+            worker.slots.iter().map(move |slot| {
+                format!(
+                    "• {}: {}{}{}",
+                    slot.name,
+                    if worker.running && slot.alive { "✅" } else { "❌" },
+                    slot.busy_since
+                        .map(|since| format!(" ⏳ {}s", (now - since).num_seconds()))
+                        .unwrap_or_default(),
+                    if slot.restarts > 0 {
+                        format!(" 🔁 {}", slot.restarts)
+                    } else {
+                        String::new()
+                    }
+                )
+            })
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    bot.send_message(
+        msg.chat.id,
+        format!(
+            "{}\n\n{}\n\n{}\n\n{}\n\n{}",
+            t!(
+                "commands.status.failures",
+                locale = context.locale(),
+                failures = failures
+            ),
+            t!(
+                "commands.status.cache",
+                locale = context.locale(),
+                hits = cache.hits,
+                misses = cache.misses,
+                refreshes = cache.refreshes
+            ),
+            t!(
+                "commands.status.queues",
+                locale = context.locale(),
+                download = download,
+                download_capacity = download_capacity,
+                post_download = post_download,
+                post_download_capacity = post_download_capacity
+            ),
+            t!("commands.status.workers", locale = context.locale(), workers = workers),
+            t!("commands.status.jobs", locale = context.locale(), jobs = jobs)
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Dead letters are the tasks that ran out of retries, admins can list them or requeue one by id
+async fn handle_dead_letters(
+    bot: Throttle<Bot>,
+    msg: Message,
+    cmd: Command,
+    context: &UserContext,
+) -> HandlerResult<()> {
+    let queue_manager = &AppState::get()?.runtime.queue_manager;
+
+    let text = match cmd {
+        Command::Requeue(id) if !id.trim().is_empty() => {
+            let id = id.trim();
+            if queue_manager.requeue_dead_letter(id).await? {
+                t!("commands.dead_letters.requeued", locale = context.locale(), id = id)
+            } else {
+                t!("commands.dead_letters.not_found", locale = context.locale(), id = id)
+            }
+        }
+        _ => {
+            let dead_letters = queue_manager.dead_letters().await?;
+
+            if dead_letters.is_empty() {
+                t!("commands.dead_letters.empty", locale = context.locale())
+            } else {
+                let list = dead_letters
+                    .iter()
+                    .take(20)
+                    .map(|letter| {
+                        format!(
+                            "• {}\n  {}\n  {} ({}×, {})",
+                            letter.task_id,
+                            letter.summary,
+                            letter.error,
+                            letter.attempts,
+                            letter.failed_at.format("%Y-%m-%d %H:%M:%S UTC")
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+
+                t!(
+                    "commands.dead_letters.list",
+                    locale = context.locale(),
+                    count = dead_letters.len(),
+                    dead_letters = list
+                )
+            }
+        }
+    };
+
+    bot.send_message(msg.chat.id, text).await?;
+
+    Ok(())
+}
+
+/// Stops all of the user's downloads, queued, running or waiting for confirmation
+async fn handle_cancel(
+    bot: Throttle<Bot>,
+    dialogue: Dialogue<DialogueState, ErasedStorage<DialogueState>>,
+    msg: Message,
+    context: &UserContext,
+) -> HandlerResult<()> {
+    let cancelled = AppState::get()?.runtime.cancel_user_work(context.user_id().0).await?;
+
+    let text = if cancelled == 0 {
+        t!("commands.cancel.nothing", locale = context.locale())
+    } else {
+        t!(
+            "commands.cancel.cancelled",
+            locale = context.locale(),
+            count = cancelled
+        )
+    };
+
+    bot.send_message(msg.chat.id, text)
+        .reply_markup(get_main_menu_keyboard(context.locale()))
+        .await?;
+
+    dialogue
+        .update(DialogueState::Start)
+        .await
+        .map_err(|e| BotError::DialogueStateError(e.to_string()))?;
+
+    Ok(())
+}
+
 async fn handle_command(
     bot: Throttle<Bot>,
     msg: Message,
     cmd: Command,
     dialogue: Dialogue<DialogueState, ErasedStorage<DialogueState>>,
+    context: UserContext,
 ) -> HandlerResult<()> {
     match cmd {
-        Command::Start => handle_start(bot, dialogue, msg).await?,
-        Command::Help => handle_help(bot, msg).await?,
-        Command::Language => handle_language(bot, msg).await?,
+        Command::Start => handle_start(bot, dialogue, msg, &context).await?,
+        Command::Help => handle_help(bot, msg, &context).await?,
+        Command::Language => handle_language(bot, msg, &context).await?,
+        Command::Cancel => handle_cancel(bot, dialogue, msg, &context).await?,
         // Command::Stats if is_admin(msg.clone().from.unwrap().id)? => handle_stats(bot, msg).await?,
-        _ => handle_unknown_command(bot, msg).await?,
+        Command::Status if context.is_admin() => handle_status(bot, msg, &context).await?,
+        Command::DocId(_) | Command::Endpoint(_) if context.is_admin() => {
+            handle_instagram_config(bot, msg, cmd, &context).await?
+        }
+        Command::DeadLetters | Command::Requeue(_) if context.is_admin() => {
+            handle_dead_letters(bot, msg, cmd, &context).await?
+        }
+        _ => handle_unknown_command(bot, msg, &context).await?,
     }
 
     Ok(())
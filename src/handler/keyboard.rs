@@ -1,8 +1,12 @@
 use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup};
 
-use crate::{error::BotResult, platform::Platform, state::AppState};
+use crate::{
+    error::BotResult,
+    platform::{instagram::model::ProfileRange, Platform},
+    state::AppState,
+};
 
-pub async fn get_platform_keyboard() -> BotResult<InlineKeyboardMarkup> {
+pub async fn get_platform_keyboard(locale: &str) -> BotResult<InlineKeyboardMarkup> {
     let app_state = AppState::get()?;
     let supported_platforms = app_state.platform_registry.get_supported_platforms().await;
 
@@ -12,112 +16,155 @@ pub async fn get_platform_keyboard() -> BotResult<InlineKeyboardMarkup> {
 
         let callback_data = format!("platform:{}", platform.to_string().to_lowercase());
 
-        buttons.push(vec![InlineKeyboardButton::callback(t!(text), callback_data)]);
+        buttons.push(vec![InlineKeyboardButton::callback(
+            t!(text, locale = locale),
+            callback_data,
+        )]);
     }
 
     buttons.push(vec![InlineKeyboardButton::callback(
-        t!("buttons.back_to_main_menu"),
+        t!("buttons.back_to_main_menu", locale = locale),
         "back_to_main_menu",
     )]);
 
     Ok(InlineKeyboardMarkup::new(buttons))
 }
 
-pub fn get_main_menu_keyboard() -> InlineKeyboardMarkup {
+pub fn get_main_menu_keyboard(locale: &str) -> InlineKeyboardMarkup {
     InlineKeyboardMarkup::new([
         [InlineKeyboardButton::callback(
-            t!("buttons.main_menu.download"),
+            t!("buttons.main_menu.download", locale = locale),
             "select_platform_menu",
         )],
         [InlineKeyboardButton::callback(
-            t!("buttons.main_menu.profile"),
+            t!("buttons.main_menu.profile", locale = locale),
             "profile_menu",
         )],
     ])
 }
 
-pub fn get_back_to_main_menu_keyboard() -> InlineKeyboardMarkup {
+pub fn get_back_to_main_menu_keyboard(locale: &str) -> InlineKeyboardMarkup {
     InlineKeyboardMarkup::new([[InlineKeyboardButton::callback(
-        t!("buttons.back_to_main_menu"),
+        t!("buttons.back_to_main_menu", locale = locale),
         "back_to_main_menu",
     )]])
 }
 
-pub fn get_download_ask_for_link_keyboard(platform: Platform) -> InlineKeyboardMarkup {
+pub fn get_download_ask_for_link_keyboard(platform: Platform, locale: &str) -> InlineKeyboardMarkup {
     let callback_data = format!("platform:{}", platform.to_string().to_lowercase());
 
     InlineKeyboardMarkup::new([
         [InlineKeyboardButton::callback(
-            t!("buttons.download_menu.continue"),
+            t!("buttons.download_menu.continue", locale = locale),
             callback_data,
         )],
         [InlineKeyboardButton::callback(
-            t!("buttons.download_menu.cancel"),
+            t!("buttons.download_menu.cancel", locale = locale),
             "cancel_download",
         )],
     ])
 }
 
-pub fn get_confirm_download_keyboard() -> InlineKeyboardMarkup {
+pub fn get_confirm_download_keyboard(locale: &str) -> InlineKeyboardMarkup {
     InlineKeyboardMarkup::new([
         [InlineKeyboardButton::callback(
-            t!("buttons.confirm_download.confirm"),
+            t!("buttons.confirm_download.confirm", locale = locale),
             "confirm_download",
         )],
         [InlineKeyboardButton::callback(
-            t!("buttons.confirm_download.cancel"),
+            t!("buttons.confirm_download.cancel", locale = locale),
             "cancel_download",
         )],
     ])
 }
 
-pub fn get_profile_menu_keyboard() -> InlineKeyboardMarkup {
+pub fn get_cancel_task_keyboard(task_id: &str, locale: &str) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new([[InlineKeyboardButton::callback(
+        t!("buttons.download_menu.cancel", locale = locale),
+        format!("cancel_task:{}", task_id),
+    )]])
+}
+
+pub fn get_profile_range_keyboard(locale: &str) -> InlineKeyboardMarkup {
+    let button = |text: String, range: ProfileRange| {
+        vec![InlineKeyboardButton::callback(text, format!("profile_range:{}", range))]
+    };
+
+    InlineKeyboardMarkup::new([
+        button(
+            t!("buttons.profile_range.latest", locale = locale, count = 10).to_string(),
+            ProfileRange::Latest(10),
+        ),
+        button(
+            t!("buttons.profile_range.latest", locale = locale, count = 30).to_string(),
+            ProfileRange::Latest(30),
+        ),
+        button(
+            t!("buttons.profile_range.days", locale = locale, count = 7).to_string(),
+            ProfileRange::Days(7),
+        ),
+        button(
+            t!("buttons.profile_range.days", locale = locale, count = 30).to_string(),
+            ProfileRange::Days(30),
+        ),
+        button(
+            t!("buttons.profile_range.reels", locale = locale, count = 10).to_string(),
+            ProfileRange::Reels(10),
+        ),
+        vec![InlineKeyboardButton::callback(
+            t!("buttons.back_to_main_menu", locale = locale),
+            "back_to_main_menu",
+        )],
+    ])
+}
+
+pub fn get_profile_menu_keyboard(locale: &str) -> InlineKeyboardMarkup {
     let mut keyboard = Vec::new();
 
     keyboard.push(vec![InlineKeyboardButton::callback(
-        t!("buttons.profile_menu.usage"),
+        t!("buttons.profile_menu.usage", locale = locale),
         "show_usage",
     )]);
 
     keyboard.push(vec![InlineKeyboardButton::callback(
-        t!("buttons.back_to_main_menu"),
+        t!("buttons.back_to_main_menu", locale = locale),
         "back_to_main_menu",
     )]);
 
     InlineKeyboardMarkup::new(keyboard)
 }
 
-pub fn get_cancel_auth_keyboard() -> InlineKeyboardMarkup {
+pub fn get_cancel_auth_keyboard(locale: &str) -> InlineKeyboardMarkup {
     InlineKeyboardMarkup::new([[InlineKeyboardButton::callback(
-        t!("buttons.login_dialogue.cancel"),
+        t!("buttons.login_dialogue.cancel", locale = locale),
         "cancel_auth",
     )]])
 }
 
-pub fn get_language_menu_keyboard() -> InlineKeyboardMarkup {
+pub fn get_language_menu_keyboard(locale: &str) -> InlineKeyboardMarkup {
     InlineKeyboardMarkup::new([
         [InlineKeyboardButton::callback(
-            t!("buttons.language_menu.en"),
+            t!("buttons.language_menu.en", locale = locale),
             "lang:en",
         )],
         [InlineKeyboardButton::callback(
-            t!("buttons.language_menu.zh"),
+            t!("buttons.language_menu.zh", locale = locale),
             "lang:zh",
         )],
         [InlineKeyboardButton::callback(
-            t!("buttons.language_menu.de"),
+            t!("buttons.language_menu.de", locale = locale),
             "lang:de",
         )],
         [InlineKeyboardButton::callback(
-            t!("buttons.language_menu.fr"),
+            t!("buttons.language_menu.fr", locale = locale),
             "lang:fr",
         )],
         [InlineKeyboardButton::callback(
-            t!("buttons.language_menu.ja"),
+            t!("buttons.language_menu.ja", locale = locale),
             "lang:ja",
         )],
         [InlineKeyboardButton::callback(
-            t!("buttons.language_menu.es"),
+            t!("buttons.language_menu.es", locale = locale),
             "lang:es",
         )],
     ])
@@ -1,35 +1,71 @@
 use crate::context::UserContext;
 use crate::error::{BotError, HandlerResult};
 
-use crate::handler::keyboard::get_back_to_main_menu_keyboard;
+use crate::handler::keyboard::{get_back_to_main_menu_keyboard, get_cancel_task_keyboard, get_profile_range_keyboard};
 
-use crate::platform::{extract_url_from_message, Platform};
-use crate::runtime::{DownloadTask, TaskContext};
+use crate::platform::instagram::extract_instagram_profile;
+use crate::platform::{extract_url_from_message, DownloadState, Platform};
+use crate::runtime::{DownloadTask, Progress, ProgressReporter, RuntimeError, TaskContext};
 use crate::service::dialogue::model::DialogueState;
 
 use crate::state::AppState;
 use teloxide::{adaptors::Throttle, dispatching::dialogue::ErasedStorage, prelude::*, types::MessageId};
+use tokio::sync::oneshot;
 
 pub(super) async fn handle_message_awaiting_download_link(
     bot: Throttle<Bot>,
     dialogue: Dialogue<DialogueState, ErasedStorage<DialogueState>>,
     msg: Message,
     (message_id, platform): (MessageId, Platform),
+    context: UserContext,
 ) -> HandlerResult<()> {
     info!("handle_message_awaiting_download_link");
 
     bot.delete_message(msg.chat.id, message_id).await?;
 
     let processing_msg = bot
-        .send_message(msg.chat.id, t!("messages.download.processing_request"))
+        .send_message(
+            msg.chat.id,
+            t!("messages.download.processing_request", locale = context.locale()),
+        )
         .await?;
 
+    if let Some(username) = msg
+        .text()
+        .filter(|_| platform == Platform::Instagram)
+        .and_then(extract_instagram_profile)
+    {
+        bot.delete_message(msg.chat.id, msg.id).await?;
+
+        bot.edit_message_text(
+            msg.chat.id,
+            processing_msg.id,
+            t!(
+                "messages.download.profile.select_range",
+                locale = context.locale(),
+                username = username
+            ),
+        )
+        .reply_markup(get_profile_range_keyboard(context.locale()))
+        .await?;
+
+        dialogue
+            .update(DialogueState::SelectProfileRange { username })
+            .await
+            .map_err(|e| BotError::DialogueStateError(e.to_string()))?;
+
+        return Ok(());
+    }
+
     let url_str = match msg.text().and_then(|text| extract_url_from_message(&platform, text)) {
         Some(url) => url,
         None => {
             let msg = bot
-                .send_message(msg.chat.id, t!("messages.download.invalid_url"))
-                .reply_markup(get_back_to_main_menu_keyboard())
+                .send_message(
+                    msg.chat.id,
+                    t!("messages.download.invalid_url", locale = context.locale()),
+                )
+                .reply_markup(get_back_to_main_menu_keyboard(context.locale()))
                 .await?;
 
             dialogue
@@ -46,34 +82,83 @@ pub(super) async fn handle_message_awaiting_download_link(
 
     bot.delete_message(msg.chat.id, msg.id).await?; // Delete the URL message from User
 
-    let context = UserContext::global();
+    let app_state = AppState::get()?;
+
+    // Links that can't be parsed are left to the worker, which reports them as failed
+    let resource_id = app_state.platform_registry.identify(&platform, &url_str).await.ok();
 
-    let download_task = DownloadTask::new(
+    let mut download_task = DownloadTask::new(
         url_str,
         TaskContext {
             user_id: context.user_id().0,
             chat_id: msg.chat.id.0,
             message_id: processing_msg.id.0,
-            user_tier: context.user_tier().await,
+            user_tier: context.user_tier(),
             platform,
+            language: context.language(),
         },
     );
 
-    let queue_manager = &AppState::get()?.runtime.queue_manager;
+    if let Some(resource_id) = resource_id {
+        download_task = download_task.with_resource_id(resource_id);
+    }
 
-    let state = queue_manager.push_download_task(download_task).await?;
+    let task_id = download_task.id.clone();
+    let locale = context.locale();
+
+    bot.edit_message_reply_markup(msg.chat.id, processing_msg.id)
+        .reply_markup(get_cancel_task_keyboard(&task_id, locale))
+        .await?;
+
+    let queue_manager = &app_state.runtime.queue_manager;
+
+    let rx = match queue_manager.enqueue_download_task(download_task).await {
+        Ok(rx) => rx,
+        Err(RuntimeError::DuplicateTask(_)) => {
+            bot.edit_message_text(
+                msg.chat.id,
+                processing_msg.id,
+                t!("messages.download.already_queued", locale = locale),
+            )
+            .reply_markup(get_back_to_main_menu_keyboard(locale))
+            .await?;
 
-    match state {
-        crate::platform::DownloadState::RateLimited => {
-            dialogue.update(DialogueState::Start).await?;
-        }
-        crate::platform::DownloadState::Success(media_file) => {
-            dialogue.update(DialogueState::ConfirmDownload { media_file }).await?;
-        }
-        crate::platform::DownloadState::Error => {
             dialogue.update(DialogueState::Start).await?;
+            return Ok(());
         }
-    }
+        Err(e) => return Err(e.into()),
+    };
+
+    queue_manager.watch_download_position(
+        &task_id,
+        ProgressReporter::spawn(
+            bot.clone(),
+            msg.chat.id,
+            processing_msg.id,
+            Some(get_cancel_task_keyboard(&task_id, locale)),
+            move |progress: &Progress| progress.message(locale),
+        ),
+    );
+
+    // The task reports to the user itself, waiting here would hold back the other updates of the chat such
+    // as its cancel button
+    dialogue.update(DialogueState::Start).await?;
+    tokio::spawn(track_download(dialogue, rx));
 
     Ok(())
 }
+
+/// Waits for the download and lets the user confirm its preview
+async fn track_download(
+    dialogue: Dialogue<DialogueState, ErasedStorage<DialogueState>>,
+    rx: oneshot::Receiver<DownloadState>,
+) {
+    // Dropped on shutdown or ended otherwise, the dialogue is back at the start already
+    let Ok(DownloadState::Success(media_file)) = rx.await else {
+        return;
+    };
+
+    if let Err(e) = dialogue.update(DialogueState::ConfirmDownload { media_file }).await {
+        error!("Failed to await the confirmation of chat {}: {}", dialogue.chat_id(), e);
+    }
+}
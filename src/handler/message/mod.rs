@@ -12,6 +12,7 @@ use teloxide::{
 };
 
 use crate::{
+    context::UserContext,
     error::{BotError, HandlerResult},
     service::dialogue::model::DialogueState,
 };
@@ -38,11 +39,15 @@ pub async fn handle_message_unknown(
     bot: Throttle<Bot>,
     message: Message,
     dialogue: Dialogue<DialogueState, ErasedStorage<DialogueState>>,
+    context: UserContext,
 ) -> HandlerResult<()> {
     bot.delete_message(message.chat.id, message.id).await?;
-    bot.send_message(message.chat.id, t!("messages.unknown_message"))
-        .reply_markup(get_main_menu_keyboard())
-        .await?;
+    bot.send_message(
+        message.chat.id,
+        t!("messages.unknown_message", locale = context.locale()),
+    )
+    .reply_markup(get_main_menu_keyboard(context.locale()))
+    .await?;
 
     dialogue
         .update(DialogueState::Start)
@@ -26,6 +26,7 @@ pub(super) async fn handle_message_username(
     dialogue: Dialogue<DialogueState, ErasedStorage<DialogueState>>,
     msg: Message,
     prompt_msg_id: MessageId,
+    context: UserContext,
 ) -> HandlerResult<()> {
     info!("handle_message_username");
 
@@ -48,7 +49,10 @@ pub(super) async fn handle_message_username(
     info!("username_input: {:?}", username_input);
 
     let validating_msg = bot
-        .send_message(msg.chat.id, t!("messages.profile.username.validating"))
+        .send_message(
+            msg.chat.id,
+            t!("messages.profile.username.validating", locale = context.locale()),
+        )
         .await?;
 
     let username = match process_instagram_username(&raw_text) {
@@ -59,6 +63,7 @@ pub(super) async fn handle_message_username(
                 validating_msg.id,
                 t!(
                     "messages.profile.username.invalid",
+                    locale = context.locale(),
                     username = username_input.to_string()
                 ),
             )
@@ -76,12 +81,13 @@ pub(super) async fn handle_message_username(
         .edit_message_text(
             msg.chat.id,
             validating_msg.id,
-            t!("messages.profile.username.validating_session"),
+            t!(
+                "messages.profile.username.validating_session",
+                locale = context.locale()
+            ),
         )
         .await?;
 
-    let context = UserContext::global();
-
     let telegram_user_id = context.user_id().to_string();
 
     let state = AppState::get()?;
@@ -106,9 +112,12 @@ pub(super) async fn handle_message_username(
                     bot.edit_message_text(
                         msg.chat.id,
                         session_msg.id,
-                        t!("messages.profile.username.validating_session_success"),
+                        t!(
+                            "messages.profile.username.validating_session_success",
+                            locale = context.locale()
+                        ),
                     )
-                    .reply_markup(get_main_menu_keyboard())
+                    .reply_markup(get_main_menu_keyboard(context.locale()))
                     .await?;
                     return Ok(());
                 }
@@ -126,10 +135,11 @@ pub(super) async fn handle_message_username(
             session_msg.id,
             t!(
                 "messages.profile.username.invalid_session",
+                locale = context.locale(),
                 username = username.to_string()
             ),
         )
-        .reply_markup(get_cancel_auth_keyboard())
+        .reply_markup(get_cancel_auth_keyboard(context.locale()))
         .await?;
 
     dialogue
@@ -149,6 +159,7 @@ pub(super) async fn handle_message_password(
     dialogue: Dialogue<DialogueState, ErasedStorage<DialogueState>>,
     msg: Message,
     (username, prompt_msg_id): (String, MessageId),
+    context: UserContext,
 ) -> HandlerResult<()> {
     bot.delete_message(msg.chat.id, prompt_msg_id).await?;
 
@@ -166,8 +177,11 @@ pub(super) async fn handle_message_password(
 
     if !validate_instagram_password(&raw_text) {
         bot.delete_message(msg.chat.id, msg.id).await?;
-        bot.send_message(msg.chat.id, t!("messages.profile.password.invalid"))
-            .await?;
+        bot.send_message(
+            msg.chat.id,
+            t!("messages.profile.password.invalid", locale = context.locale()),
+        )
+        .await?;
 
         dialogue
             .update(DialogueState::AwaitingPassword {
@@ -181,11 +195,13 @@ pub(super) async fn handle_message_password(
     bot.delete_message(msg.chat.id, msg.id).await?;
 
     let status_msg = bot
-        .send_message(msg.chat.id, t!("messages.profile.password.logging_in"))
+        .send_message(
+            msg.chat.id,
+            t!("messages.profile.password.logging_in", locale = context.locale()),
+        )
         .await?;
 
     let state = AppState::get()?;
-    let context = UserContext::global();
     let telegram_user_id = context.user_id().to_string();
 
     let auth_service = state.service_registry.auth.lock().await;
@@ -221,9 +237,9 @@ pub(super) async fn handle_message_password(
                 bot.edit_message_text(
                     msg.chat.id,
                     status_msg.id,
-                    t!("messages.profile.password.login_success"),
+                    t!("messages.profile.password.login_success", locale = context.locale()),
                 )
-                .reply_markup(get_main_menu_keyboard())
+                .reply_markup(get_main_menu_keyboard(context.locale()))
                 .await?;
             } else {
                 let msg = bot
@@ -232,6 +248,7 @@ pub(super) async fn handle_message_password(
                         status_msg.id,
                         t!(
                             "messages.profile.password.login_failed",
+                            locale = context.locale(),
                             error = "I don't know".to_string() // TODO
                         ),
                     )
@@ -249,7 +266,11 @@ pub(super) async fn handle_message_password(
                 .edit_message_text(
                     msg.chat.id,
                     status_msg.id,
-                    t!("messages.profile.password.login_failed", error = e.to_string()),
+                    t!(
+                        "messages.profile.password.login_failed",
+                        locale = context.locale(),
+                        error = e.to_string()
+                    ),
                 )
                 .await?;
 
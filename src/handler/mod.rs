@@ -14,15 +14,19 @@ use teloxide::{
         dialogue::{self, ErasedStorage},
         UpdateFilterExt, UpdateHandler,
     },
+    dptree,
     types::Update,
 };
 
-use crate::service::dialogue::model::DialogueState;
+use crate::{context::UserContext, service::dialogue::model::DialogueState};
 
 pub fn get_handler() -> UpdateHandler<Box<dyn std::error::Error + Send + Sync + 'static>> {
-    dialogue::enter::<Update, ErasedStorage<DialogueState>, DialogueState, _>()
-        .branch(get_command_handler())
-        .branch(get_message_handler())
-        .branch(get_callback_handler())
-        .branch(Update::filter_message().endpoint(handle_message_unknown))
+    // Every handler below receives the `UserContext` of the update's sender
+    dptree::filter_map_async(UserContext::resolve).chain(
+        dialogue::enter::<Update, ErasedStorage<DialogueState>, DialogueState, _>()
+            .branch(get_command_handler())
+            .branch(get_message_handler())
+            .branch(get_callback_handler())
+            .branch(Update::filter_message().endpoint(handle_message_unknown)),
+    )
 }
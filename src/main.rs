@@ -1,6 +1,7 @@
 use bot::BotService;
 use config::AppConfig;
 use error::BotError;
+use runtime::Schedule;
 use state::AppState;
 use std::sync::Arc;
 
@@ -51,7 +52,6 @@ async fn shuttle_main(
 impl shuttle_runtime::Service for BotService {
     async fn bind(self, _addr: std::net::SocketAddr) -> Result<(), shuttle_runtime::Error> {
         let shared_self = Arc::new(self);
-        // let scheduler = shared_self.scheduler.clone();
 
         info!("Starting worker pool...");
         if let Err(e) = AppState::get()?.runtime.worker_pool.start_all().await {
@@ -59,6 +59,9 @@ impl shuttle_runtime::Service for BotService {
             return Err(shuttle_runtime::Error::Custom(anyhow::anyhow!(e)));
         }
 
+        info!("Starting background tasks...");
+        schedule_background_tasks(&AppState::get()?)?;
+
         shared_self
             .start()
             .await
@@ -70,58 +73,54 @@ impl shuttle_runtime::Service for BotService {
     }
 }
 
-// #[allow(dead_code)]
-// async fn run_background_tasks() {
-//     let state = match AppState::get() {
-//         Ok(state) => state,
-//         Err(e) => {
-//             error!("Failed to get AppState: {}", e);
-//             return;
-//         }
-//     };
-
-//     let config = match AppConfig::get() {
-//         Ok(config) => config,
-//         Err(e) => {
-//             error!("Failed to get AppConfig: {}", e);
-//             return;
-//         }
-//     };
-
-//     let BackgroundTasksConfig {
-//         cleanup_interaction_interval_secs,
-//         sync_interface_interval_secs,
-//         sync_language_interval_secs,
-//     } = config.background_tasks;
-
-//     let interaction_service = state.interaction;
-//     let language_service = state.language;
-
-//     let mut cleanup_interaction_interval =
-//         tokio::time::interval(Duration::from_secs(cleanup_interaction_interval_secs));
-//     let mut sync_interface_interval = tokio::time::interval(Duration::from_secs(sync_interface_interval_secs));
-//     let mut sync_language_interval = tokio::time::interval(Duration::from_secs(sync_language_interval_secs));
-
-//     info!("Background tasks initialized");
-
-//     loop {
-//         tokio::select! {
-//             _ = cleanup_interaction_interval.tick() => {
-//                 debug!("Background task: Cleaning up old interfaces & interactions ...");
-//                 interaction_service.cleanup_old_entries();
-//             }
-//             _ = sync_interface_interval.tick() => {
-//                 debug!("Background task: Syncing interfaces to database ...");
-//                 if let Err(e) = interaction_service.save_interfaces_to_database().await {
-//                     error!("Failed to save interfaces to database: {}", e);
-//                 }
-//             }
-//             _ = sync_language_interval.tick() => {
-//                 debug!("Background task: Syncing languages to database ...");
-//                 if let Err(e) = language_service.save_languages_to_database().await {
-//                     error!("Failed to save languages to database: {}", e);
-//                 }
-//             }
-//         }
-//     }
-// }
+/// Registers the periodic jobs with the runtime scheduler and starts them
+fn schedule_background_tasks(state: &AppState) -> Result<(), BotError> {
+    let config = AppConfig::get()?;
+    let scheduler = &state.runtime.scheduler;
+
+    scheduler.add_job(
+        "interaction_cleanup",
+        Schedule::every(config.service.interaction.cleanup_interval_secs)?,
+        || async {
+            AppState::get()?
+                .service_registry
+                .interaction
+                .cleanup_old_entries()
+                .await;
+            Ok(())
+        },
+    );
+
+    scheduler.add_job(
+        "language_sync",
+        Schedule::every(config.service.language.sync_interval_secs)?,
+        || async {
+            AppState::get()?
+                .service_registry
+                .language
+                .save_languages_to_database()
+                .await
+        },
+    );
+
+    scheduler.add_job(
+        "interface_sync",
+        Schedule::every(config.service.interaction.sync_interval_secs)?,
+        || async {
+            AppState::get()?
+                .service_registry
+                .interaction
+                .save_interfaces_to_database()
+                .await
+        },
+    );
+
+    scheduler.add_job("confirmation_sweep", Schedule::cron("* * * * *")?, || async {
+        AppState::get()?.runtime.sweep_expired_confirmations().await?;
+        Ok(())
+    });
+
+    scheduler.start();
+
+    Ok(())
+}
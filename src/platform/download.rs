@@ -1,13 +1,16 @@
-use crate::{error::BotError, platform::MediaFile, state::AppState};
+use crate::{error::BotError, platform::MediaFile, service::CacheLookup, state::AppState};
 
-use super::{DownloadState, Platform, PlatformCapability, PlatformError, PlatformRegistry};
+use super::{DownloadState, FailureReason, Platform, PlatformCapability, PlatformError, PlatformRegistry};
 
 impl PlatformRegistry {
+    /// Fetches the resource a URL points to, from the cache if possible. Unless `rate_limit` is false it counts
+    /// against the user's rate limit, bulk downloads are counted once for the whole job instead.
     pub async fn handle_download<P: PlatformCapability + 'static>(
         &self,
         platform: &Platform,
         url: &str,
         telegram_user_id: &str,
+        rate_limit: bool,
     ) -> Result<DownloadState, BotError> {
         info!("handle_download");
         let platform_service = self
@@ -18,31 +21,46 @@ impl PlatformRegistry {
         let identifier = self.generate_identifier(&resource); // <platform>:<identifier>
         info!("identifier: {:?}", identifier);
 
-        let ratelimit = AppState::get()?.service_registry.ratelimit;
-        info!("checking rate limit");
-        if !ratelimit.check_rate_limit(telegram_user_id, &identifier).await? {
-            info!("rate limited");
-            return Ok(DownloadState::RateLimited);
+        if rate_limit {
+            let ratelimit = AppState::get()?.service_registry.ratelimit;
+            info!("checking rate limit");
+            if !ratelimit.check_rate_limit(telegram_user_id, &identifier).await? {
+                info!("rate limited");
+                return Ok(DownloadState::RateLimited);
+            }
         }
 
         let cache_service = AppState::get()?.service_registry.cache;
 
-        if let Some(cached) = cache_service.get::<MediaFile>(&identifier).await? {
-            info!("cache hit");
-            return Ok(DownloadState::Success(cached));
+        match cache_service.lookup::<MediaFile>(&identifier).await? {
+            CacheLookup::Hit(cached) => {
+                info!("cache hit");
+                return Ok(DownloadState::Success(cached));
+            }
+            CacheLookup::Stale => info!("cached links expired, refreshing"),
+            CacheLookup::Miss => info!("cache missed"),
         }
 
-        info!("cache missed");
+        // Private media is only handed to the user whose session fetched it
+        let fetch = self.fetches.get_or_fetch(
+            &identifier,
+            |media_file| !media_file.is_private(),
+            || async {
+                info!("fetching resource");
+                platform_service.fetch_resource(&resource, telegram_user_id).await
+            },
+        );
 
-        info!("fetching resource");
-        match platform_service.fetch_resource(&resource).await {
+        match fetch.await {
             Ok(media_file) => {
                 info!("resource fetched");
                 Ok(DownloadState::Success(media_file))
             }
             Err(e) => {
-                info!("resource fetch failed: {:?}", e);
-                Ok(DownloadState::Error)
+                let reason = FailureReason::from_error(e.as_ref());
+                warn!("Failed to fetch {} ({:?}): {}", identifier, reason, e);
+                self.record_failure(reason);
+                Ok(DownloadState::Error(reason))
             }
         }
     }
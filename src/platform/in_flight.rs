@@ -0,0 +1,132 @@
+use std::{future::Future, sync::Arc};
+
+use dashmap::DashMap;
+use tokio::sync::OnceCell;
+
+/// Runs one fetch per key at a time, callers asking for the same key meanwhile wait for it and get the same
+/// value. Results may depend on the caller, e.g. on their Instagram session, so failures and values that are
+/// not `shareable` are not handed to the waiters, they fetch on their own instead.
+pub struct InFlight<T> {
+    fetches: DashMap<String, Arc<OnceCell<T>>>,
+}
+
+impl<T> Default for InFlight<T> {
+    fn default() -> Self {
+        Self {
+            fetches: DashMap::new(),
+        }
+    }
+}
+
+impl<T: Clone> InFlight<T> {
+    pub async fn get_or_fetch<S, F, Fut, E>(&self, key: &str, shareable: S, fetch: F) -> Result<T, E>
+    where
+        S: FnOnce(&T) -> bool,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let cell = self.fetches.entry(key.to_string()).or_default().clone();
+        let mut fetch = Some(fetch);
+        let result = cell
+            .get_or_try_init(|| fetch.take().expect("fetch runs at most once")())
+            .await
+            .cloned();
+
+        // Later callers fetch again, the value is cached by then or may have changed
+        self.fetches.remove_if(key, |_, other| Arc::ptr_eq(other, &cell));
+
+        // `fetch` is left when someone else fetched the value
+        match (result, fetch) {
+            (Ok(value), Some(fetch)) if !shareable(&value) => fetch().await,
+            (result, _) => result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_concurrent_callers_share_one_fetch() {
+        let in_flight = Arc::new(InFlight::<String>::default());
+        let fetches = Arc::new(AtomicUsize::new(0));
+
+        let callers = (0..5)
+            .map(|_| {
+                let in_flight = in_flight.clone();
+                let fetches = fetches.clone();
+                tokio::spawn(async move {
+                    in_flight
+                        .get_or_fetch(
+                            "reel",
+                            |_| true,
+                            || async move {
+                                fetches.fetch_add(1, Ordering::SeqCst);
+                                tokio::time::sleep(Duration::from_millis(50)).await;
+                                Ok::<_, ()>("media".to_string())
+                            },
+                        )
+                        .await
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for caller in callers {
+            assert_eq!(caller.await.unwrap(), Ok("media".to_string()));
+        }
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+        // A failed fetch is retried by the next caller instead of being handed out
+        assert_eq!(
+            in_flight
+                .get_or_fetch("post", |_| true, || async { Err::<String, _>("boom") })
+                .await,
+            Err("boom")
+        );
+        assert_eq!(
+            in_flight
+                .get_or_fetch("post", |_| true, || async { Ok::<_, &str>("media".to_string()) })
+                .await,
+            Ok("media".to_string())
+        );
+        assert!(in_flight.fetches.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_values_that_are_not_shareable_are_fetched_by_each_caller() {
+        let in_flight = Arc::new(InFlight::<String>::default());
+        let fetches = Arc::new(AtomicUsize::new(0));
+
+        let callers = (0..3)
+            .map(|caller| {
+                let in_flight = in_flight.clone();
+                let fetches = fetches.clone();
+                tokio::spawn(async move {
+                    in_flight
+                        .get_or_fetch(
+                            "private",
+                            |media| !media.starts_with("private"),
+                            || async move {
+                                fetches.fetch_add(1, Ordering::SeqCst);
+                                tokio::time::sleep(Duration::from_millis(50)).await;
+                                Ok::<_, ()>(format!("private media of {}", caller))
+                            },
+                        )
+                        .await
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // Everyone gets the media as seen with their own session
+        for (caller, result) in callers.into_iter().enumerate() {
+            assert_eq!(result.await.unwrap(), Ok(format!("private media of {}", caller)));
+        }
+        assert_eq!(fetches.load(Ordering::SeqCst), 3);
+    }
+}
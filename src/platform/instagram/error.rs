@@ -1,3 +1,6 @@
+use reqwest::StatusCode;
+use serde_json::Value;
+
 #[derive(Debug, thiserror::Error)]
 pub enum AuthenticationError {
     #[error("Login failed: Bad credentials")]
@@ -20,4 +23,197 @@ pub enum InstagramError {
     InvalidUsername(String),
     #[error("Invalid URL: {0}")]
     InvalidUrl(String),
+    #[error("Invalid response: {0}")]
+    InvalidResponse(String),
+    #[error("Login required")]
+    LoginRequired,
+    #[error("Checkpoint required")]
+    CheckpointRequired,
+    #[error("Rate limited by Instagram")]
+    RateLimited,
+    #[error("Private account")]
+    PrivateAccount,
+    #[error("Media not found or deleted")]
+    MediaNotFound,
+    #[error("Age restricted media")]
+    AgeRestricted,
+    #[error("Not available in this region")]
+    GeoBlocked,
+}
+
+impl InstagramError {
+    /// Classifies a response by its status, the path it ended up on after redirects and its payload.
+    /// Returns `None` for regular responses.
+    pub fn from_response(status: StatusCode, path: &str, body: &str) -> Option<Self> {
+        if path.starts_with("/accounts/login") {
+            return Some(InstagramError::LoginRequired);
+        }
+
+        if path.starts_with("/challenge") || path.starts_with("/accounts/suspended") {
+            return Some(InstagramError::CheckpointRequired);
+        }
+
+        match status {
+            StatusCode::TOO_MANY_REQUESTS => return Some(InstagramError::RateLimited),
+            StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS => return Some(InstagramError::GeoBlocked),
+            _ => {}
+        }
+
+        // Successful responses are only inspected when the payload itself reports a failure,
+        // otherwise captions could trigger false positives
+        let message = match serde_json::from_str::<Value>(body) {
+            Ok(payload) if payload.get("status").and_then(|s| s.as_str()) == Some("fail") || !status.is_success() => {
+                payload.to_string().to_lowercase()
+            }
+            Ok(_) => return None,
+            Err(_) if !status.is_success() => body.to_lowercase(),
+            Err(_) => return None,
+        };
+
+        let contains_any = |markers: &[&str]| markers.iter().any(|marker| message.contains(marker));
+
+        if contains_any(&["checkpoint_required", "challenge_required"]) {
+            Some(InstagramError::CheckpointRequired)
+        } else if contains_any(&["login_required", "\"require_login\":true"]) {
+            Some(InstagramError::LoginRequired)
+        } else if contains_any(&["please wait a few minutes", "rate_limit"]) {
+            Some(InstagramError::RateLimited)
+        } else if contains_any(&["not authorized to view user", "this account is private"]) {
+            Some(InstagramError::PrivateAccount)
+        } else if contains_any(&["age_restricted", "age-restricted", "restricted_by_age"]) {
+            Some(InstagramError::AgeRestricted)
+        } else if contains_any(&["not available in your country", "geo_blocked", "geoblocked"]) {
+            Some(InstagramError::GeoBlocked)
+        } else if contains_any(&["media not found", "page isn't available", "media_not_found"]) {
+            Some(InstagramError::MediaNotFound)
+        } else {
+            match status {
+                StatusCode::NOT_FOUND => Some(InstagramError::MediaNotFound),
+                StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Some(InstagramError::LoginRequired),
+                _ if !status.is_success() => Some(InstagramError::InvalidResponse(format!("Status {}", status))),
+                _ => Some(InstagramError::InvalidResponse("Request failed".into())),
+            }
+        }
+    }
+
+    /// Whether the error says something definitive about the resource, rather than a transient or parsing problem
+    pub fn is_definitive(&self) -> bool {
+        matches!(
+            self,
+            InstagramError::LoginRequired
+                | InstagramError::CheckpointRequired
+                | InstagramError::RateLimited
+                | InstagramError::PrivateAccount
+                | InstagramError::MediaNotFound
+                | InstagramError::AgeRestricted
+                | InstagramError::GeoBlocked
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_response() {
+        let cases = [
+            (
+                StatusCode::OK,
+                "/accounts/login/",
+                "<html></html>",
+                Some("LoginRequired"),
+            ),
+            (
+                StatusCode::OK,
+                "/challenge/123/",
+                "<html></html>",
+                Some("CheckpointRequired"),
+            ),
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                "/graphql/query/",
+                "",
+                Some("RateLimited"),
+            ),
+            (
+                StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS,
+                "/p/ABC/",
+                "",
+                Some("GeoBlocked"),
+            ),
+            (
+                StatusCode::BAD_REQUEST,
+                "/api/v1/feed/user/1/",
+                r#"{"message":"checkpoint_required","status":"fail"}"#,
+                Some("CheckpointRequired"),
+            ),
+            (
+                StatusCode::UNAUTHORIZED,
+                "/api/v1/feed/user/1/",
+                r#"{"message":"login_required","require_login":true,"status":"fail"}"#,
+                Some("LoginRequired"),
+            ),
+            (
+                StatusCode::OK,
+                "/graphql/query/",
+                r#"{"message":"Please wait a few minutes before you try again.","status":"fail"}"#,
+                Some("RateLimited"),
+            ),
+            (
+                StatusCode::BAD_REQUEST,
+                "/api/v1/feed/user/1/",
+                r#"{"message":"Not authorized to view user","status":"fail"}"#,
+                Some("PrivateAccount"),
+            ),
+            (
+                StatusCode::OK,
+                "/p/ABC/",
+                r#"{"message":"age_restricted","status":"fail"}"#,
+                Some("AgeRestricted"),
+            ),
+            (
+                StatusCode::OK,
+                "/p/ABC/",
+                r#"{"message":"This content is not available in your country","status":"fail"}"#,
+                Some("GeoBlocked"),
+            ),
+            (
+                StatusCode::NOT_FOUND,
+                "/p/ABC/",
+                "<html>Sorry, this page isn't available.</html>",
+                Some("MediaNotFound"),
+            ),
+            (
+                StatusCode::NOT_FOUND,
+                "/api/v1/media/1/info/",
+                "",
+                Some("MediaNotFound"),
+            ),
+            (
+                StatusCode::FORBIDDEN,
+                "/api/v1/users/web_profile_info/",
+                "",
+                Some("LoginRequired"),
+            ),
+            (StatusCode::OK, "/graphql/query/", r#"{"data":{},"status":"ok"}"#, None),
+            (
+                StatusCode::OK,
+                "/graphql/query/",
+                r#"{"data":{"caption":"please wait a few minutes"},"status":"ok"}"#,
+                None,
+            ),
+            (
+                StatusCode::OK,
+                "/p/ABC/embed/captioned/",
+                "<html>age-restricted caption</html>",
+                None,
+            ),
+        ];
+
+        for (status, path, body, expected) in cases {
+            let actual = InstagramError::from_response(status, path, body).map(|e| format!("{:?}", e));
+            assert_eq!(actual.as_deref(), expected, "{} {} {}", status, path, body);
+        }
+    }
 }
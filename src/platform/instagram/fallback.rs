@@ -0,0 +1,258 @@
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+
+use crate::platform::{MediaFile, MediaType, PlatformError};
+
+use super::{
+    error::InstagramError,
+    model::{InstagramAuthor, InstagramContent, InstagramMedia, InstagramMediaItem, XDTGraphMedia},
+};
+
+/// Converts the `shortcode_media` object shared by the GraphQL API and the embed page
+pub(super) fn parse_shortcode_media(media_value: &Value) -> Result<MediaFile, PlatformError> {
+    let media_data = serde_json::from_value::<XDTGraphMedia>(media_value.clone()).map_err(|e| {
+        error!("Failed to deserialize media: {}", e);
+        error!(
+            "Raw media data: {}",
+            serde_json::to_string_pretty(&media_value).unwrap_or_default()
+        );
+        PlatformError::ParsingError(format!("Failed to deserialize media: {}", e))
+    })?;
+
+    let media = match media_data {
+        XDTGraphMedia::Image(image) => TryInto::<InstagramMedia>::try_into(image)?,
+        XDTGraphMedia::Video(video) => TryInto::<InstagramMedia>::try_into(video)?,
+        XDTGraphMedia::Sidecar(sidecar) => TryInto::<InstagramMedia>::try_into(sidecar)?,
+    };
+
+    Ok(media.try_into()?)
+}
+
+/// Parses the GraphQL `xdt_shortcode_media` response, `None` means Instagram answered without the media
+pub(super) fn parse_graphql_response(response: &Value) -> Result<Option<MediaFile>, PlatformError> {
+    let media_value = response
+        .get("data")
+        .and_then(|d| d.get("xdt_shortcode_media"))
+        .ok_or_else(|| PlatformError::ParsingError("Missing xdt_shortcode_media".to_string()))?;
+
+    if media_value.is_null() {
+        return Ok(None);
+    }
+
+    parse_shortcode_media(media_value).map(Some)
+}
+
+/// Parses the `?__a=1&__d=dis` JSON, which uses the private v1 API format
+pub(super) fn parse_a1_response(response: &Value) -> Result<MediaFile, PlatformError> {
+    let item = response
+        .get("items")
+        .and_then(|items| items.get(0))
+        .ok_or_else(|| PlatformError::ParsingError("Missing items".to_string()))?;
+
+    let timestamp = item
+        .get("taken_at")
+        .and_then(|t| t.as_i64())
+        .and_then(|t| DateTime::<Utc>::from_timestamp(t, 0))
+        .ok_or_else(|| PlatformError::ParsingError("Missing taken_at".to_string()))?;
+
+    let content = match item.get("carousel_media").and_then(|c| c.as_array()) {
+        Some(children) => InstagramContent::Multiple(
+            children
+                .iter()
+                .map(|child| parse_v1_item(child, timestamp))
+                .collect::<Result<Vec<_>, PlatformError>>()?,
+        ),
+        None => InstagramContent::Single(parse_v1_item(item, timestamp)?),
+    };
+
+    let thumbnail_url = match &content {
+        InstagramContent::Single(item) => item.thumbnail_url.clone().unwrap_or_else(|| item.url.clone()),
+        InstagramContent::Multiple(items) => items
+            .first()
+            .map(|item| item.thumbnail_url.clone().unwrap_or_else(|| item.url.clone()))
+            .unwrap_or_default(),
+        _ => String::new(),
+    };
+
+    let user = item
+        .get("user")
+        .ok_or_else(|| PlatformError::ParsingError("Missing user".to_string()))?;
+
+    let media = InstagramMedia {
+        id: value_to_string(item.get("id")).unwrap_or_default(),
+        shortcode: value_to_string(item.get("code"))
+            .ok_or_else(|| PlatformError::ParsingError("Missing code".to_string()))?,
+        author: InstagramAuthor {
+            id: value_to_string(user.get("pk")).unwrap_or_default(),
+            username: value_to_string(user.get("username")).unwrap_or_default(),
+            is_private: user.get("is_private").and_then(|p| p.as_bool()).unwrap_or_default(),
+        },
+        caption: item
+            .get("caption")
+            .and_then(|c| c.get("text"))
+            .and_then(|t| t.as_str())
+            .map(|t| t.to_string()),
+        content,
+        thumbnail_url,
+        timestamp,
+    };
+
+    Ok(media.try_into()?)
+}
+
+fn parse_v1_item(item: &Value, timestamp: DateTime<Utc>) -> Result<InstagramMediaItem, PlatformError> {
+    let image_url = item
+        .get("image_versions2")
+        .and_then(|i| i.get("candidates"))
+        .and_then(|c| c.get(0))
+        .and_then(|c| c.get("url"))
+        .and_then(|u| u.as_str())
+        .map(|u| u.to_string());
+
+    let video_url = item
+        .get("video_versions")
+        .and_then(|v| v.get(0))
+        .and_then(|v| v.get("url"))
+        .and_then(|u| u.as_str())
+        .map(|u| u.to_string());
+
+    let id = value_to_string(item.get("id")).unwrap_or_default();
+
+    match (video_url, image_url) {
+        (Some(url), thumbnail_url) => Ok(InstagramMediaItem {
+            id,
+            media_type: MediaType::Video,
+            url,
+            thumbnail_url,
+            timestamp,
+        }),
+        (None, Some(url)) => Ok(InstagramMediaItem {
+            id,
+            media_type: MediaType::Image,
+            url: url.clone(),
+            thumbnail_url: Some(url),
+            timestamp,
+        }),
+        (None, None) => Err(PlatformError::ParsingError("Missing media url".to_string())),
+    }
+}
+
+/// The embed page ships the media as an escaped JSON string in `contextJSON`
+pub(super) fn parse_embed_page(html: &str) -> Result<MediaFile, PlatformError> {
+    let start = html
+        .find("\"contextJSON\":")
+        .ok_or_else(|| PlatformError::ParsingError("Missing contextJSON".to_string()))?;
+
+    let context_json = serde_json::Deserializer::from_str(&html[start + "\"contextJSON\":".len()..])
+        .into_iter::<String>()
+        .next()
+        .and_then(|r| r.ok())
+        .ok_or_else(|| PlatformError::ParsingError("Invalid contextJSON".to_string()))?;
+
+    let context = serde_json::from_str::<Value>(&context_json)
+        .map_err(|e| PlatformError::ParsingError(format!("Invalid contextJSON: {}", e)))?;
+
+    let media_value = context
+        .get("gql_data")
+        .and_then(|d| d.get("shortcode_media"))
+        .filter(|m| !m.is_null())
+        .ok_or(InstagramError::MediaNotFound)?;
+
+    parse_shortcode_media(media_value)
+}
+
+fn value_to_string(value: Option<&Value>) -> Option<String> {
+    match value? {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::MediaContentType;
+
+    #[test]
+    fn test_parse_graphql_null_media() {
+        let response = serde_json::json!({
+            "data": { "xdt_shortcode_media": null },
+            "extensions": { "is_final": true },
+            "status": "ok",
+        });
+
+        assert!(parse_graphql_response(&response).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_parse_a1_carousel() {
+        let response = serde_json::json!({
+            "items": [{
+                "id": "123_456",
+                "code": "ABC123",
+                "taken_at": 1_700_000_000,
+                "user": { "pk": 456, "username": "someone", "is_private": true },
+                "caption": { "text": "hello" },
+                "carousel_media": [
+                    { "id": "1", "image_versions2": { "candidates": [{ "url": "https://cdn.example.com/1.jpg" }] } },
+                    {
+                        "id": "2",
+                        "image_versions2": { "candidates": [{ "url": "https://cdn.example.com/2.jpg" }] },
+                        "video_versions": [{ "url": "https://cdn.example.com/2.mp4" }],
+                    },
+                ],
+            }]
+        });
+
+        let media_file = parse_a1_response(&response).unwrap();
+
+        assert_eq!(media_file.id, "ABC123");
+        assert_eq!(media_file.content_type, MediaContentType::Multiple);
+        assert!(media_file.is_private());
+        assert_eq!(media_file.author.unwrap().id, "456");
+        assert_eq!(media_file.description.as_deref(), Some("hello"));
+        assert_eq!(media_file.items[0].media_type, MediaType::Image);
+        assert_eq!(media_file.items[1].media_type, MediaType::Video);
+        assert_eq!(media_file.items[1].url.as_str(), "https://cdn.example.com/2.mp4");
+    }
+
+    #[test]
+    fn test_parse_embed_page() {
+        let context = serde_json::json!({
+            "context": {},
+            "gql_data": {
+                "shortcode_media": {
+                    "__typename": "GraphImage",
+                    "id": "1",
+                    "shortcode": "ABC123",
+                    "display_url": "https://cdn.example.com/1.jpg",
+                    "owner": { "id": "456", "username": "someone" },
+                    "edge_media_to_caption": { "edges": [{ "node": { "text": "hello" } }] },
+                    "taken_at_timestamp": 1_700_000_000,
+                }
+            }
+        });
+        let html = format!(
+            "<script>s.handle({{\"contextJSON\":{}}});</script>",
+            serde_json::to_string(&context.to_string()).unwrap()
+        );
+
+        let media_file = parse_embed_page(&html).unwrap();
+
+        assert_eq!(media_file.id, "ABC123");
+        assert_eq!(media_file.content_type, MediaContentType::Single);
+        assert_eq!(media_file.items[0].url.as_str(), "https://cdn.example.com/1.jpg");
+    }
+
+    #[test]
+    fn test_parse_embed_page_without_media() {
+        assert!(parse_embed_page("<html></html>").is_err());
+
+        let html = r#"{"contextJSON":"{\"gql_data\":null}"}"#;
+        assert!(matches!(
+            parse_embed_page(html),
+            Err(PlatformError::Instagram(InstagramError::MediaNotFound))
+        ));
+    }
+}
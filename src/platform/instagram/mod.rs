@@ -1,21 +1,22 @@
 mod error;
+mod fallback;
 pub mod model;
 mod util;
 
-use std::{any::Any, collections::HashMap, time::Duration};
+use std::{any::Any, collections::HashMap, sync::RwLock, time::Duration};
 
 use anyhow::Context;
 use async_trait::async_trait;
 use axum::http::{HeaderMap, HeaderValue};
-use chrono::Utc;
-use model::{InstagramIdentifier, InstagramMedia, XDTGraphMedia};
+use chrono::{DateTime, Utc};
+use model::{GraphReelsMedia, InstagramIdentifier, InstagramMedia, ProfilePost, ProfileRange};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use teloxide::{
     adaptors::Throttle,
     prelude::Requester,
-    types::{ChatId, InputFile, InputMedia, InputMediaAudio, InputMediaPhoto, InputMediaVideo},
-    Bot,
+    types::{ChatId, InputMedia, InputMediaAudio, InputMediaPhoto, InputMediaVideo, Message},
+    Bot, RequestError,
 };
 use url::Url;
 
@@ -23,7 +24,7 @@ pub use error::*;
 pub use util::*;
 
 use crate::{
-    config::AppConfig,
+    config::{AppConfig, InstagramConfig},
     error::HandlerResult,
     service::{
         http::{HttpClient, HttpService},
@@ -33,10 +34,14 @@ use crate::{
     state::AppState,
 };
 
-use super::{model::PlatformIdentifier, MediaFile, Platform, PlatformCapability, PlatformError};
+use super::{
+    is_stale_file_id, model::PlatformIdentifier, sent_file_id, telegram_input_file, MediaFile, MediaFileItem,
+    MediaType, Platform, PlatformCapability, PlatformError,
+};
 
 pub struct PlatformInstagram {
     http_service: HttpService,
+    api_config: RwLock<InstagramConfig>,
 }
 
 #[async_trait]
@@ -54,88 +59,65 @@ impl PlatformCapability for PlatformInstagram {
     }
 
     async fn parse_url(&self, url_str: &str) -> Result<PlatformIdentifier, PlatformError> {
-        let url = self.validate_url(url_str).await?;
-
-        let path_segments: Vec<_> = url
-            .path_segments()
-            .ok_or_else(|| PlatformError::ParsingError("No path segments".into()))?
-            .collect();
-
-        match path_segments.as_slice() {
-            ["stories", username, story_id] => Ok(PlatformIdentifier::Instagram(InstagramIdentifier::Story {
-                username: username.to_string(),
-                story_id: story_id.to_string(),
-            })),
-
-            ["p", post_id, ..] => Ok(PlatformIdentifier::Instagram(InstagramIdentifier::Post {
-                shortcode: post_id.to_string(),
-            })),
-
-            ["reel", shortcode, ..] => Ok(PlatformIdentifier::Instagram(InstagramIdentifier::Reel {
-                shortcode: shortcode.to_string(),
-            })),
+        let identifier = normalize_instagram_url(&self.http_service, url_str).await?;
 
-            _ => Err(PlatformError::ParsingError("Invalid URL format".into())),
-        }
+        Ok(PlatformIdentifier::Instagram(identifier))
     }
 
-    async fn fetch_resource(&self, identifier: &PlatformIdentifier) -> HandlerResult<MediaFile> {
+    async fn fetch_resource(
+        &self,
+        identifier: &PlatformIdentifier,
+        telegram_user_id: &str,
+    ) -> HandlerResult<MediaFile> {
         match identifier {
-            PlatformIdentifier::Instagram(InstagramIdentifier::Story {
-                username: _,
-                story_id: _,
-            }) => {
-                error!("Not implemented yet");
-                Err(Box::new(PlatformError::NotImplemented))
-            }
-            PlatformIdentifier::Instagram(
-                InstagramIdentifier::Post { shortcode } | InstagramIdentifier::Reel { shortcode },
-            ) => {
-                let doc_id = "8845758582119845";
+            PlatformIdentifier::Instagram(InstagramIdentifier::Story { username, story_id }) => {
+                let http_service = self.get_session_http_service(telegram_user_id).await?;
+
+                let user_id = self.fetch_user_id(&http_service, username).await?;
 
                 let variables = serde_json::json!({
-                    "shortcode": shortcode
+                    "reel_ids": [user_id],
+                    "precomposed_overlay": false,
                 });
 
                 let params = serde_json::json!({
-                    "doc_id": doc_id,
+                    "query_hash": Self::STORIES_QUERY_HASH,
                     "variables": variables.to_string(),
-                    "server_timestamps": "true",
                 });
 
-                let response = self
-                    .http_service
-                    .get_json("https://www.instagram.com/graphql/query/", Some(params))
-                    .await?;
+                let response = request_json(&http_service, &self.api_config().api_endpoint, Some(params)).await?;
 
-                // TODO: sometimes response is null when status is ok
-                // {"data": Object {"xdt_shortcode_media": Null}, "extensions": Object {"is_final": Bool(true)}, "status": String("ok")}
+                Ok(parse_story_response(&response, story_id)?)
+            }
+            PlatformIdentifier::Instagram(InstagramIdentifier::Highlight { highlight_id }) => {
+                let http_service = self.get_session_http_service(telegram_user_id).await?;
+
+                let variables = serde_json::json!({
+                    "reel_ids": [],
+                    "tag_names": [],
+                    "location_ids": [],
+                    "highlight_reel_ids": [highlight_id],
+                    "precomposed_overlay": false,
+                });
 
-                let media_value = response
-                    .get("data")
-                    .and_then(|d| d.get("xdt_shortcode_media"))
-                    .ok_or_else(|| PlatformError::ParsingError("Missing xdt_shortcode_media".to_string()))?;
+                let params = serde_json::json!({
+                    "query_hash": Self::STORIES_QUERY_HASH,
+                    "variables": variables.to_string(),
+                });
 
-                if media_value.is_null() {
-                    return Err(PlatformError::ResourceError("Resource not found or deleted".into()).into());
-                }
+                let response = request_json(&http_service, &self.api_config().api_endpoint, Some(params)).await?;
 
-                let media_data = serde_json::from_value::<XDTGraphMedia>(media_value.clone()).map_err(|e| {
-                    error!("Failed to deserialize media: {}", e);
-                    error!(
-                        "Raw media data: {}",
-                        serde_json::to_string_pretty(&media_value).unwrap_or_default()
-                    );
-                    PlatformError::ParsingError(format!("Failed to deserialize media: {}", e))
-                })?;
-
-                let media = match media_data {
-                    XDTGraphMedia::Image(image) => TryInto::<InstagramMedia>::try_into(image)?,
-                    XDTGraphMedia::Video(video) => TryInto::<InstagramMedia>::try_into(video)?,
-                    XDTGraphMedia::Sidecar(sidecar) => TryInto::<InstagramMedia>::try_into(sidecar)?,
-                };
-
-                return Ok(media.try_into()?);
+                Ok(parse_highlight_response(&response)?)
+            }
+            PlatformIdentifier::Instagram(InstagramIdentifier::Profile { .. }) => {
+                // Profiles are expanded into one download task per post, see `fetch_profile_posts`
+                Err(PlatformError::ResourceError("Profiles can only be downloaded in bulk".into()).into())
+            }
+            PlatformIdentifier::Instagram(
+                InstagramIdentifier::Post { shortcode } | InstagramIdentifier::Reel { shortcode },
+            ) => {
+                let http_service = self.get_user_http_service(telegram_user_id).await;
+                self.fetch_post(&http_service, shortcode).await
             }
         }
     }
@@ -159,42 +141,46 @@ impl PlatformCapability for PlatformInstagram {
         &self,
         bot: &Throttle<Bot>,
         chat_id: ChatId,
+        telegram_user_id: &str,
         media_file: &MediaFile,
+        progress: &(dyn Fn(usize, usize) + Send + Sync),
     ) -> HandlerResult<()> {
-        let cache_service = AppState::get()?.service_registry.cache;
-
-        let secs = AppConfig::get()?.service.cache.ttl;
-
-        let ttl = Duration::from_secs(secs);
-
-        cache_service.set::<MediaFile>(media_file.clone(), ttl).await?;
+        let total = media_file.items.len();
+        let mut sent = 0;
+        let mut delivered = media_file.clone();
+
+        // Telegram only accepts 2-10 items per media group, albums such as highlights are sent in chunks
+        for (index, chunk) in media_file.items.chunks(Self::MAX_MEDIA_GROUP_SIZE).enumerate() {
+            let items = &mut delivered.items[index * Self::MAX_MEDIA_GROUP_SIZE..];
+
+            let messages = match Self::send_chunk(bot, chat_id, chunk, true).await {
+                // Telegram may forget a file, the links cached next to its id have likely expired by then so
+                // the chunk is sent from a fresh fetch
+                Err(e) if is_stale_file_id(&e) && chunk.iter().any(|item| item.telegram_file_id.is_some()) => {
+                    warn!("Telegram rejected a cached file id of {}: {}", media_file.id, e);
+                    let refreshed = self.refetch_items(media_file, chunk, telegram_user_id).await?;
+                    let messages = Self::send_chunk(bot, chat_id, &refreshed, false).await?;
+                    for (item, fresh) in items.iter_mut().zip(refreshed) {
+                        item.url = fresh.url;
+                    }
+                    messages
+                }
+                result => result?,
+            };
 
-        if media_file.items.len() == 1 {
-            let item = media_file.items.first().unwrap();
+            for (item, message) in items.iter_mut().zip(&messages) {
+                item.telegram_file_id = sent_file_id(message);
+            }
 
-            let _ = match item.media_type {
-                super::MediaType::Image => bot.send_photo(chat_id, InputFile::url(item.url.clone())).await?,
-                super::MediaType::Video => bot.send_video(chat_id, InputFile::url(item.url.clone())).await?,
-                super::MediaType::Audio => bot.send_audio(chat_id, InputFile::url(item.url.clone())).await?,
-            };
-        } else {
-            let media_group = media_file
-                .items
-                .iter()
-                .map(|item| match item.media_type {
-                    super::MediaType::Image => {
-                        InputMedia::Photo(InputMediaPhoto::new(InputFile::url(item.url.clone())))
-                    }
-                    super::MediaType::Video => {
-                        InputMedia::Video(InputMediaVideo::new(InputFile::url(item.url.clone())))
-                    }
-                    super::MediaType::Audio => {
-                        InputMedia::Audio(InputMediaAudio::new(InputFile::url(item.url.clone())))
-                    }
-                })
-                .collect::<Vec<_>>();
+            sent += chunk.len();
+            progress(sent, total);
+        }
 
-            bot.send_media_group(chat_id, media_group).await?;
+        // Private media must not be served to users who don't follow the account
+        if !media_file.is_private() {
+            let cache_service = AppState::get()?.service_registry.cache;
+            let ttl = Duration::from_secs(AppConfig::get()?.service.cache.ttl);
+            cache_service.set::<MediaFile>(delivered, ttl).await?;
         }
 
         Ok(())
@@ -202,9 +188,100 @@ impl PlatformCapability for PlatformInstagram {
 }
 
 impl PlatformInstagram {
-    pub fn new() -> Result<Self, InstagramError> {
+    /// Fetches the media again, bypassing the cache, and returns the fresh version of each item of the chunk
+    async fn refetch_items(
+        &self,
+        media_file: &MediaFile,
+        chunk: &[MediaFileItem],
+        telegram_user_id: &str,
+    ) -> HandlerResult<Vec<MediaFileItem>> {
+        let identifier = InstagramIdentifier::of(media_file)
+            .ok_or_else(|| PlatformError::ResourceError(format!("Can't fetch {} again", media_file.id)))?;
+        let fresh = self
+            .fetch_resource(&PlatformIdentifier::Instagram(identifier), telegram_user_id)
+            .await?;
+
+        let items = chunk
+            .iter()
+            .map(|item| {
+                fresh
+                    .items
+                    .iter()
+                    .find(|fresh| fresh.id == item.id)
+                    .cloned()
+                    .ok_or(InstagramError::MediaNotFound)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(items)
+    }
+
+    /// Sends one chunk of items, a single item on its own as media groups need at least two
+    async fn send_chunk(
+        bot: &Throttle<Bot>,
+        chat_id: ChatId,
+        chunk: &[MediaFileItem],
+        reuse_file_ids: bool,
+    ) -> Result<Vec<Message>, RequestError> {
+        if let [item] = chunk {
+            let file = telegram_input_file(item, reuse_file_ids);
+            let message = match item.media_type {
+                MediaType::Image => bot.send_photo(chat_id, file).await?,
+                MediaType::Video => bot.send_video(chat_id, file).await?,
+                MediaType::Audio => bot.send_audio(chat_id, file).await?,
+            };
+            return Ok(vec![message]);
+        }
+
+        let media_group = chunk
+            .iter()
+            .map(|item| {
+                let file = telegram_input_file(item, reuse_file_ids);
+                match item.media_type {
+                    MediaType::Image => InputMedia::Photo(InputMediaPhoto::new(file)),
+                    MediaType::Video => InputMedia::Video(InputMediaVideo::new(file)),
+                    MediaType::Audio => InputMedia::Audio(InputMediaAudio::new(file)),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        bot.send_media_group(chat_id, media_group).await
+    }
+
+    pub fn new(api_config: InstagramConfig) -> Result<Self, InstagramError> {
         let http_service = HttpService::new(Platform::Instagram)?;
-        Ok(Self { http_service })
+        Ok(Self {
+            http_service,
+            api_config: RwLock::new(api_config),
+        })
+    }
+
+    pub fn api_config(&self) -> InstagramConfig {
+        self.api_config
+            .read()
+            .expect("Instagram api config lock poisoned")
+            .clone()
+    }
+
+    /// Swaps the GraphQL doc_id at runtime, e.g. after Meta rotated it
+    pub fn set_doc_id(&self, doc_id: &str) {
+        info!("Instagram doc_id set to {}", doc_id);
+        self.api_config
+            .write()
+            .expect("Instagram api config lock poisoned")
+            .doc_id = doc_id.to_string();
+    }
+
+    pub fn set_api_endpoint(&self, api_endpoint: &str) -> Result<(), InstagramError> {
+        Url::parse(api_endpoint).map_err(|_| InstagramError::InvalidUrl(api_endpoint.to_string()))?;
+
+        info!("Instagram api endpoint set to {}", api_endpoint);
+        self.api_config
+            .write()
+            .expect("Instagram api config lock poisoned")
+            .api_endpoint = api_endpoint.to_string();
+
+        Ok(())
     }
 
     const REQUIRED_COOKIES: [(&'static str, Duration); 5] = [
@@ -215,19 +292,182 @@ impl PlatformInstagram {
         ("mid", Duration::from_secs(365 * 24 * 60 * 60)),       // Machine ID
     ];
 
-    async fn validate_url(&self, url: &str) -> Result<Url, PlatformError> {
-        let parsed_url =
-            Url::parse(url).map_err(|_| PlatformError::Instagram(InstagramError::InvalidUrl(url.to_string())))?;
+    const MAX_MEDIA_GROUP_SIZE: usize = 10;
 
-        if parsed_url.host_str() == Some("instagram.com") || parsed_url.host_str() == Some("www.instagram.com") {
-            Ok(parsed_url)
-        } else {
-            Err(PlatformError::Instagram(InstagramError::InvalidUrl(
-                "Not an Instagram URL".into(),
-            )))
+    const MAX_PROFILE_PAGES: usize = 10;
+
+    const PROFILE_PAGE_SIZE: usize = 12;
+
+    const STORIES_QUERY_HASH: &'static str = "303a4ae99711322310f25250d988f3b7";
+
+    /// Builds an isolated http service carrying the cookies of the user's Instagram session,
+    /// stories are only visible to logged in accounts.
+    async fn get_session_http_service(&self, telegram_user_id: &str) -> HandlerResult<HttpService> {
+        let session = AppState::get()?
+            .service_registry
+            .session
+            .get_valid_session(telegram_user_id, &Platform::Instagram)
+            .await?
+            .ok_or(AuthError::AuthenticationRequired)?;
+
+        let session_data = session.session_data.ok_or(AuthError::AuthenticationRequired)?;
+
+        Ok(self.build_session_http_service(&session_data)?)
+    }
+
+    /// Requests on behalf of a user run with their own session cookies,
+    /// the shared anonymous client is only used when they have no valid session.
+    async fn get_user_http_service(&self, telegram_user_id: &str) -> HttpService {
+        match self.get_session_http_service(telegram_user_id).await {
+            Ok(http_service) => http_service,
+            Err(e) => {
+                info!("Using anonymous access for {}: {}", telegram_user_id, e);
+                self.http_service.clone()
+            }
         }
     }
 
+    fn build_session_http_service(&self, session_data: &SessionData) -> Result<HttpService, InstagramError> {
+        let base_url = Url::parse("https://www.instagram.com").expect("Invalid Instagram base URL");
+        let http_service = HttpService::with_cookies(Platform::Instagram, &base_url, &session_data.auth_data.cookies)?;
+        Ok(http_service)
+    }
+
+    async fn fetch_user_id(&self, http_service: &HttpService, username: &str) -> HandlerResult<String> {
+        let response = request_json(
+            http_service,
+            "https://www.instagram.com/api/v1/users/web_profile_info/",
+            Some(serde_json::json!({ "username": username })),
+        )
+        .await?;
+
+        let user_id = response
+            .get("data")
+            .and_then(|d| d.get("user"))
+            .and_then(|u| u.get("id"))
+            .and_then(|id| id.as_str())
+            .ok_or_else(|| PlatformError::ParsingError(format!("Missing user id for {}", username)))?;
+
+        Ok(user_id.to_string())
+    }
+
+    /// Pages through the profile's timeline until the requested range is covered, newest posts first
+    pub async fn fetch_profile_posts(
+        &self,
+        username: &str,
+        range: &ProfileRange,
+        telegram_user_id: &str,
+    ) -> HandlerResult<Vec<ProfilePost>> {
+        let http_service = self.get_session_http_service(telegram_user_id).await?;
+
+        let user_id = self.fetch_user_id(&http_service, username).await?;
+
+        let now = Utc::now();
+        let mut posts = Vec::new();
+        let mut max_id: Option<String> = None;
+
+        for _ in 0..Self::MAX_PROFILE_PAGES {
+            let mut params = serde_json::json!({ "count": Self::PROFILE_PAGE_SIZE });
+            if let Some(max_id) = &max_id {
+                params["max_id"] = max_id.clone().into();
+            }
+
+            let response = request_json(
+                &http_service,
+                &format!("https://www.instagram.com/api/v1/feed/user/{}/", user_id),
+                Some(params),
+            )
+            .await?;
+
+            let (page, next_max_id) = parse_profile_feed(&response)?;
+            posts.extend(page);
+
+            if range.is_covered_by(&posts, now) || next_max_id.is_none() {
+                break;
+            }
+
+            max_id = next_max_id;
+        }
+
+        Ok(range.select(posts, now))
+    }
+
+    /// Tries the GraphQL API first, then the `?__a=1` JSON and finally the embed page.
+    /// The first definitive failure (private, deleted, login required...) is reported if all of them fail.
+    async fn fetch_post(&self, http_service: &HttpService, shortcode: &str) -> HandlerResult<MediaFile> {
+        let mut failure: Option<InstagramError> = None;
+
+        match self.fetch_post_graphql(http_service, shortcode).await {
+            Ok(Some(media_file)) => return Ok(media_file),
+            Ok(None) => warn!("xdt_shortcode_media is null for {}, trying fallbacks", shortcode),
+            Err(e) => {
+                warn!("GraphQL query failed for {}: {}, trying fallbacks", shortcode, e);
+                failure = failure.or(definitive_failure(e));
+            }
+        }
+
+        match self.fetch_post_a1(http_service, shortcode).await {
+            Ok(media_file) => return Ok(media_file),
+            Err(e) => {
+                warn!("__a=1 fallback failed for {}: {}", shortcode, e);
+                failure = failure.or(definitive_failure(e));
+            }
+        }
+
+        match self.fetch_post_embed(http_service, shortcode).await {
+            Ok(media_file) => Ok(media_file),
+            Err(e) => {
+                warn!("Embed fallback failed for {}: {}", shortcode, e);
+                failure = failure.or(definitive_failure(e));
+                Err(PlatformError::from(failure.unwrap_or(InstagramError::MediaNotFound)).into())
+            }
+        }
+    }
+
+    async fn fetch_post_graphql(
+        &self,
+        http_service: &HttpService,
+        shortcode: &str,
+    ) -> Result<Option<MediaFile>, PlatformError> {
+        let api_config = self.api_config();
+
+        let variables = serde_json::json!({
+            "shortcode": shortcode
+        });
+
+        let params = serde_json::json!({
+            "doc_id": api_config.doc_id,
+            "variables": variables.to_string(),
+            "server_timestamps": "true",
+        });
+
+        let response = request_json(http_service, &api_config.api_endpoint, Some(params)).await?;
+
+        fallback::parse_graphql_response(&response)
+    }
+
+    async fn fetch_post_a1(&self, http_service: &HttpService, shortcode: &str) -> Result<MediaFile, PlatformError> {
+        let response = request_json(
+            http_service,
+            &format!("https://www.instagram.com/p/{}/", shortcode),
+            Some(serde_json::json!({ "__a": "1", "__d": "dis" })),
+        )
+        .await?;
+
+        fallback::parse_a1_response(&response)
+    }
+
+    async fn fetch_post_embed(&self, http_service: &HttpService, shortcode: &str) -> Result<MediaFile, PlatformError> {
+        let html = request_text(
+            http_service,
+            &format!("https://www.instagram.com/p/{}/embed/captioned/", shortcode),
+            None,
+        )
+        .await?;
+
+        fallback::parse_embed_page(&html)
+    }
+
     async fn perform_login(&self, username: &str, password: &str, csrf_token: &str) -> Result<Value, InstagramError> {
         let enc_password = format!("#PWD_INSTAGRAM_BROWSER:0:{}:{}", Utc::now().timestamp(), password);
 
@@ -384,10 +624,11 @@ impl PlatformAuth for PlatformInstagram {
                 }
             }
 
-            let response = self
-                .http_service
-                .get("https://www.instagram.com/accounts/edit/")
-                .await?;
+            let http_service = self
+                .build_session_http_service(session_data)
+                .map_err(|e| AuthError::Other(e.to_string()))?;
+
+            let response = http_service.get("https://www.instagram.com/accounts/edit/").await?;
 
             Ok(response.status().is_success())
         } else {
@@ -421,3 +662,288 @@ pub struct InstagramSessionData {
     pub username: String,
     pub authenticated: bool,
 }
+
+/// Sends a GET request and turns Instagram's failure responses into a classified error
+async fn request_text(http_service: &HttpService, url: &str, params: Option<Value>) -> Result<String, InstagramError> {
+    let response = http_service.get_with_params(url, params).await?;
+
+    let status = response.status();
+    let path = response.url().path().to_string();
+    let body = response.text().await?;
+
+    match InstagramError::from_response(status, &path, &body) {
+        Some(error) => Err(error),
+        None => Ok(body),
+    }
+}
+
+async fn request_json(http_service: &HttpService, url: &str, params: Option<Value>) -> Result<Value, InstagramError> {
+    let body = request_text(http_service, url, params).await?;
+
+    serde_json::from_str(&body).map_err(|e| InstagramError::InvalidResponse(format!("Invalid JSON: {}", e)))
+}
+
+fn definitive_failure(error: PlatformError) -> Option<InstagramError> {
+    match error {
+        PlatformError::Instagram(error) if error.is_definitive() => Some(error),
+        _ => None,
+    }
+}
+
+fn parse_story_response(response: &Value, story_id: &str) -> Result<MediaFile, PlatformError> {
+    let data = response
+        .get("data")
+        .ok_or_else(|| PlatformError::ParsingError("Missing data".to_string()))?;
+
+    let reels_media = serde_json::from_value::<GraphReelsMedia>(data.clone())
+        .map_err(|e| PlatformError::ParsingError(format!("Failed to deserialize stories: {}", e)))?;
+
+    let story = reels_media
+        .reels_media
+        .into_iter()
+        .flat_map(|reel| reel.items)
+        .find(|item| item.id() == story_id)
+        .ok_or(InstagramError::MediaNotFound)?;
+
+    let media = TryInto::<InstagramMedia>::try_into(story)?;
+
+    Ok(media.try_into()?)
+}
+
+fn parse_highlight_response(response: &Value) -> Result<MediaFile, PlatformError> {
+    let data = response
+        .get("data")
+        .ok_or_else(|| PlatformError::ParsingError("Missing data".to_string()))?;
+
+    let reels_media = serde_json::from_value::<GraphReelsMedia>(data.clone())
+        .map_err(|e| PlatformError::ParsingError(format!("Failed to deserialize highlight: {}", e)))?;
+
+    let highlight = reels_media
+        .reels_media
+        .into_iter()
+        .next()
+        .filter(|reel| !reel.items.is_empty())
+        .ok_or(InstagramError::MediaNotFound)?;
+
+    let media = TryInto::<InstagramMedia>::try_into(highlight)?;
+
+    Ok(media.try_into()?)
+}
+
+/// Returns the posts of a timeline page and the cursor of the next page, if any
+fn parse_profile_feed(response: &Value) -> Result<(Vec<ProfilePost>, Option<String>), PlatformError> {
+    let items = response
+        .get("items")
+        .and_then(|items| items.as_array())
+        .ok_or_else(|| PlatformError::ParsingError("Missing items".to_string()))?;
+
+    let posts = items
+        .iter()
+        .map(|item| {
+            let shortcode = item
+                .get("code")
+                .and_then(|code| code.as_str())
+                .ok_or_else(|| PlatformError::ParsingError("Missing post code".to_string()))?;
+
+            let taken_at = item
+                .get("taken_at")
+                .and_then(|taken_at| taken_at.as_i64())
+                .and_then(|taken_at| DateTime::from_timestamp(taken_at, 0))
+                .ok_or_else(|| PlatformError::ParsingError("Missing post timestamp".to_string()))?;
+
+            let is_reel = item.get("product_type").and_then(|p| p.as_str()) == Some("clips");
+
+            Ok(ProfilePost {
+                shortcode: shortcode.to_string(),
+                taken_at,
+                is_reel,
+            })
+        })
+        .collect::<Result<Vec<_>, PlatformError>>()?;
+
+    let more_available = response
+        .get("more_available")
+        .and_then(|more| more.as_bool())
+        .unwrap_or(false);
+
+    let next_max_id = response
+        .get("next_max_id")
+        .and_then(|id| id.as_str())
+        .filter(|_| more_available)
+        .map(|id| id.to_string());
+
+    Ok((posts, next_max_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::{MediaContentType, MediaType};
+    use model::GraphReel;
+
+    const STORIES_JSON: &str = include_str!("../../../tests/data/instagram/stories.json");
+
+    fn stories_response() -> Value {
+        serde_json::from_str(STORIES_JSON).unwrap()
+    }
+
+    #[test]
+    fn test_parse_story_image() {
+        let media_file = parse_story_response(&stories_response(), "3548480262179807470").unwrap();
+
+        assert_eq!(media_file.id, "3548480262179807470");
+        assert_eq!(
+            InstagramIdentifier::of(&media_file),
+            Some(InstagramIdentifier::Story {
+                username: "st.einberg".to_string(),
+                story_id: "3548480262179807470".to_string()
+            })
+        );
+        assert_eq!(media_file.content_type, MediaContentType::Story);
+        assert_eq!(media_file.platform, Platform::Instagram);
+        assert_eq!(media_file.author.unwrap().username, "st.einberg");
+        assert!(media_file.thumbnail.is_some());
+        assert_eq!(media_file.items.len(), 1);
+        assert_eq!(media_file.items[0].media_type, MediaType::Image);
+    }
+
+    #[test]
+    fn test_parse_story_video() {
+        let media_file = parse_story_response(&stories_response(), "3548702136257550949").unwrap();
+
+        assert_eq!(media_file.content_type, MediaContentType::Story);
+        assert_eq!(media_file.author.unwrap().username, "edward.z.lin");
+        assert_eq!(media_file.items.len(), 1);
+        assert_eq!(media_file.items[0].media_type, MediaType::Video);
+        assert!(media_file.items[0].url.path().ends_with(".mp4"));
+    }
+
+    #[test]
+    fn test_parse_story_not_found() {
+        let result = parse_story_response(&stories_response(), "0");
+        assert!(matches!(
+            result,
+            Err(PlatformError::Instagram(InstagramError::MediaNotFound))
+        ));
+    }
+
+    fn highlight_response() -> Value {
+        let mut response = stories_response();
+        let reel = response["data"]["reels_media"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|reel| reel["id"] == "1545716472")
+            .cloned()
+            .unwrap();
+
+        let mut highlight = reel.clone();
+        let mut items = reel["items"].as_array().unwrap().clone();
+        while items.len() < 12 {
+            items.extend(reel["items"].as_array().unwrap().iter().cloned());
+        }
+        highlight["__typename"] = "GraphHighlightReel".into();
+        highlight["id"] = "highlight:17900000000000000".into();
+        highlight["title"] = "Travel".into();
+        highlight["items"] = items.into();
+
+        response["data"]["reels_media"] = vec![highlight].into();
+        response
+    }
+
+    #[test]
+    fn test_parse_highlight() {
+        let media_file = parse_highlight_response(&highlight_response()).unwrap();
+
+        assert_eq!(media_file.id, "highlight:17900000000000000");
+        assert_eq!(
+            InstagramIdentifier::of(&media_file),
+            Some(InstagramIdentifier::Highlight {
+                highlight_id: "17900000000000000".to_string()
+            })
+        );
+        assert_eq!(media_file.content_type, MediaContentType::Album);
+        assert_eq!(media_file.description.as_deref(), Some("Travel"));
+        assert_eq!(media_file.author.unwrap().username, "edward.z.lin");
+        assert!(media_file.items.len() > PlatformInstagram::MAX_MEDIA_GROUP_SIZE);
+        assert!(media_file.items.iter().any(|item| item.media_type == MediaType::Video));
+        assert!(media_file.items.iter().any(|item| item.media_type == MediaType::Image));
+    }
+
+    #[test]
+    fn test_parse_highlight_empty() {
+        let result = parse_highlight_response(&serde_json::json!({ "data": { "reels_media": [] } }));
+        assert!(matches!(
+            result,
+            Err(PlatformError::Instagram(InstagramError::MediaNotFound))
+        ));
+
+        let mut reel = highlight_response()["data"]["reels_media"][0].clone();
+        reel["items"] = serde_json::json!([]);
+        let result = InstagramMedia::try_from(serde_json::from_value::<GraphReel>(reel).unwrap());
+        assert!(matches!(result, Err(InstagramError::MediaNotFound)));
+    }
+
+    #[test]
+    fn test_parse_profile_feed() {
+        let response = serde_json::json!({
+            "items": [
+                { "code": "C1", "taken_at": 1_700_000_200, "product_type": "clips" },
+                { "code": "C2", "taken_at": 1_700_000_100, "product_type": "feed" },
+                { "code": "C3", "taken_at": 1_700_000_000, "product_type": "carousel_container" },
+            ],
+            "more_available": true,
+            "next_max_id": "3200_123",
+        });
+
+        let (posts, next_max_id) = parse_profile_feed(&response).unwrap();
+
+        assert_eq!(next_max_id.as_deref(), Some("3200_123"));
+        assert_eq!(posts.len(), 3);
+        assert!(posts[0].is_reel);
+        assert_eq!(posts[0].url(), "https://www.instagram.com/reel/C1/");
+        assert_eq!(posts[1].url(), "https://www.instagram.com/p/C2/");
+
+        let last_page = serde_json::json!({ "items": [], "more_available": false, "next_max_id": "3200_123" });
+        assert_eq!(parse_profile_feed(&last_page).unwrap().1, None);
+    }
+
+    #[test]
+    fn test_profile_range_select() {
+        let now = Utc::now();
+        let post = |shortcode: &str, days_ago: i64, is_reel: bool| ProfilePost {
+            shortcode: shortcode.to_string(),
+            taken_at: now - chrono::Duration::days(days_ago),
+            is_reel,
+        };
+        let posts = vec![
+            post("a", 1, false),
+            post("b", 3, true),
+            post("c", 10, false),
+            post("d", 20, true),
+        ];
+
+        let latest = ProfileRange::Latest(2).select(posts.clone(), now);
+        assert_eq!(latest, vec![post("a", 1, false), post("b", 3, true)]);
+
+        let days = ProfileRange::Days(7).select(posts.clone(), now);
+        assert_eq!(days.len(), 2);
+        assert!(ProfileRange::Days(7).is_covered_by(&posts, now));
+        assert!(!ProfileRange::Days(30).is_covered_by(&posts, now));
+
+        let reels = ProfileRange::Reels(5).select(posts.clone(), now);
+        assert!(reels.iter().all(|post| post.is_reel));
+        assert_eq!(reels.len(), 2);
+        assert!(!ProfileRange::Reels(5).is_covered_by(&posts, now));
+
+        assert_eq!("reels:10".parse::<ProfileRange>().unwrap(), ProfileRange::Reels(10));
+        assert_eq!(ProfileRange::Days(7).to_string(), "days:7");
+        assert!("weeks:1".parse::<ProfileRange>().is_err());
+    }
+
+    #[test]
+    fn test_parse_story_missing_data() {
+        let result = parse_story_response(&serde_json::json!({ "status": "fail" }), "3548480262179807470");
+        assert!(matches!(result, Err(PlatformError::ParsingError(_))));
+    }
+}
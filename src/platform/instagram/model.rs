@@ -1,3 +1,5 @@
+use std::{fmt, str::FromStr};
+
 use anyhow::Context;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
@@ -12,6 +14,27 @@ pub enum InstagramIdentifier {
     Story { username: String, story_id: String },
     Post { shortcode: String },
     Reel { shortcode: String },
+    Highlight { highlight_id: String },
+    Profile { username: String },
+}
+
+impl InstagramIdentifier {
+    /// The identifier the media was fetched with, reels come back as posts which resolve the same way
+    pub fn of(media_file: &MediaFile) -> Option<Self> {
+        match media_file.content_type {
+            MediaContentType::Single | MediaContentType::Multiple => Some(Self::Post {
+                shortcode: media_file.id.clone(),
+            }),
+            MediaContentType::Story => Some(Self::Story {
+                username: media_file.author.as_ref()?.username.clone(),
+                story_id: media_file.id.clone(),
+            }),
+            MediaContentType::Album => Some(Self::Highlight {
+                highlight_id: media_file.id.strip_prefix("highlight:")?.to_string(),
+            }),
+            MediaContentType::Playlist => None,
+        }
+    }
 }
 
 // --- ---
@@ -29,6 +52,7 @@ impl TryFrom<InstagramMedia> for MediaFile {
                     url: Url::parse(&item.url).map_err(|e| InstagramError::InvalidUrl(e.to_string()))?,
                     duration: None,
                     created_at: item.timestamp,
+                    telegram_file_id: None,
                 };
                 (MediaContentType::Single, vec![media_item])
             }
@@ -42,6 +66,7 @@ impl TryFrom<InstagramMedia> for MediaFile {
                             url: Url::parse(&item.url).map_err(|e| InstagramError::InvalidUrl(e.to_string()))?,
                             duration: None,
                             created_at: item.timestamp,
+                            telegram_file_id: None,
                         })
                     })
                     .collect::<Result<Vec<_>, InstagramError>>()?;
@@ -54,9 +79,26 @@ impl TryFrom<InstagramMedia> for MediaFile {
                     url: Url::parse(&item.url).map_err(|e| InstagramError::InvalidUrl(e.to_string()))?,
                     duration: None,
                     created_at: item.timestamp,
+                    telegram_file_id: None,
                 };
                 (MediaContentType::Story, vec![media_item])
             }
+            InstagramContent::Album(items) => {
+                let media_items = items
+                    .into_iter()
+                    .map(|item| {
+                        Ok(MediaFileItem {
+                            id: item.id,
+                            media_type: item.media_type,
+                            url: Url::parse(&item.url).map_err(|e| InstagramError::InvalidUrl(e.to_string()))?,
+                            duration: None,
+                            created_at: item.timestamp,
+                            telegram_file_id: None,
+                        })
+                    })
+                    .collect::<Result<Vec<_>, InstagramError>>()?;
+                (MediaContentType::Album, media_items)
+            }
         };
 
         Ok(MediaFile {
@@ -67,6 +109,7 @@ impl TryFrom<InstagramMedia> for MediaFile {
             author: Some(MediaAuthor {
                 id: media.author.id,
                 username: media.author.username,
+                is_private: media.author.is_private,
             }),
             content_type,
             thumbnail: Url::parse(&media.thumbnail_url).ok(),
@@ -91,6 +134,8 @@ pub struct InstagramMedia {
 pub struct InstagramAuthor {
     pub id: String,
     pub username: String,
+    #[serde(default)]
+    pub is_private: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -98,6 +143,7 @@ pub enum InstagramContent {
     Single(InstagramMediaItem),
     Multiple(Vec<InstagramMediaItem>),
     Story(InstagramMediaItem),
+    Album(Vec<InstagramMediaItem>),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -119,6 +165,7 @@ impl TryFrom<XDTGraphImage> for InstagramMedia {
             author: InstagramAuthor {
                 id: image.owner.id,
                 username: image.owner.username,
+                is_private: image.owner.is_private,
             },
             thumbnail_url: image.display_url.clone(),
             timestamp: DateTime::from_timestamp(image.taken_at_timestamp, 0)
@@ -152,6 +199,7 @@ impl TryFrom<XDTGraphVideo> for InstagramMedia {
             author: InstagramAuthor {
                 id: video.owner.id,
                 username: video.owner.username,
+                is_private: video.owner.is_private,
             },
             timestamp: DateTime::from_timestamp(video.taken_at_timestamp, 0)
                 .context("Failed to parse timestamp")
@@ -220,6 +268,7 @@ impl TryFrom<XDTGraphSidecar> for InstagramMedia {
             author: InstagramAuthor {
                 id: sidecar.owner.id,
                 username: sidecar.owner.username,
+                is_private: sidecar.owner.is_private,
             },
             thumbnail_url,
             timestamp: DateTime::from_timestamp(sidecar.taken_at_timestamp, 0)
@@ -236,30 +285,27 @@ impl TryFrom<XDTGraphSidecar> for InstagramMedia {
     }
 }
 
-impl TryFrom<GraphStoryItem> for InstagramMedia {
+impl TryFrom<GraphStoryItem> for InstagramMediaItem {
     type Error = InstagramError;
 
     fn try_from(story: GraphStoryItem) -> Result<Self, Self::Error> {
-        let (id, media_type, url, timestamp, owner, thumbnail_url) = match story {
+        let (id, media_type, url, timestamp, thumbnail_url) = match story {
             GraphStoryItem::Image(GraphStoryItemImage {
                 id,
                 display_url,
                 taken_at_timestamp,
-                owner,
                 ..
             }) => (
                 id,
                 MediaType::Image,
                 display_url.clone(),
                 taken_at_timestamp,
-                owner,
                 display_url,
             ),
             GraphStoryItem::Video(GraphStoryItemVideo {
                 id,
                 video_resources,
                 taken_at_timestamp,
-                owner,
                 display_url,
                 ..
             }) => {
@@ -268,52 +314,100 @@ impl TryFrom<GraphStoryItem> for InstagramMedia {
                     .ok_or_else(|| InstagramError::InvalidUrl("No video resources found".into()))?
                     .src
                     .clone();
-                (id, MediaType::Video, video_url, taken_at_timestamp, owner, display_url)
+                (id, MediaType::Video, video_url, taken_at_timestamp, display_url)
             }
         };
 
+        Ok(InstagramMediaItem {
+            id,
+            media_type,
+            url,
+            thumbnail_url: Some(thumbnail_url),
+            timestamp: DateTime::from_timestamp(timestamp, 0)
+                .context("Failed to parse timestamp")
+                .unwrap(),
+        })
+    }
+}
+
+impl TryFrom<GraphStoryItem> for InstagramMedia {
+    type Error = InstagramError;
+
+    fn try_from(story: GraphStoryItem) -> Result<Self, Self::Error> {
+        let owner = match &story {
+            GraphStoryItem::Image(image) => image.owner.clone(),
+            GraphStoryItem::Video(video) => video.owner.clone(),
+        };
+
+        let item = InstagramMediaItem::try_from(story)?;
+
         Ok(InstagramMedia {
-            id: id.clone(),
-            shortcode: id.clone(),
+            id: item.id.clone(),
+            shortcode: item.id.clone(),
             author: InstagramAuthor {
                 id: owner.id,
                 username: owner.username,
+                is_private: owner.is_private,
             },
             caption: None,
-            thumbnail_url: thumbnail_url.clone(),
-            timestamp: DateTime::from_timestamp(timestamp, 0)
-                .context("Failed to parse timestamp")
-                .unwrap(),
+            thumbnail_url: item.thumbnail_url.clone().unwrap_or_else(|| item.url.clone()),
+            timestamp: item.timestamp,
             content: InstagramContent::Story(InstagramMediaItem {
-                id,
-                media_type,
-                url,
                 thumbnail_url: None,
-                timestamp: DateTime::from_timestamp(timestamp, 0)
-                    .context("Failed to parse timestamp")
-                    .unwrap(),
+                ..item
             }),
         })
     }
 }
 
+impl TryFrom<GraphReel> for InstagramMedia {
+    type Error = InstagramError;
+
+    fn try_from(reel: GraphReel) -> Result<Self, Self::Error> {
+        let items = reel
+            .items
+            .into_iter()
+            .map(InstagramMediaItem::try_from)
+            .collect::<Result<Vec<_>, InstagramError>>()?;
+
+        let first = items.first().ok_or(InstagramError::MediaNotFound)?;
+
+        Ok(InstagramMedia {
+            id: reel.id.clone(),
+            shortcode: reel.id,
+            author: InstagramAuthor {
+                id: reel.owner.id,
+                username: reel.owner.username,
+                is_private: reel.owner.is_private,
+            },
+            caption: reel.title,
+            thumbnail_url: first.thumbnail_url.clone().unwrap_or_else(|| first.url.clone()),
+            timestamp: first.timestamp,
+            content: InstagramContent::Album(items),
+        })
+    }
+}
+
 // --- Common ---
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Owner {
     pub id: String,
     pub username: String,
+    #[serde(default)]
+    pub is_private: bool,
 }
 
 // --- XDTGraphMedia ---
+// The embed page still serves the legacy `Graph*` typenames with the same shape, hence the aliases
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "__typename")]
 pub enum XDTGraphMedia {
-    #[serde(rename = "XDTGraphImage")]
+    #[serde(rename = "XDTGraphImage", alias = "GraphImage")]
     Image(XDTGraphImage),
-    #[serde(rename = "XDTGraphVideo")]
+    #[serde(rename = "XDTGraphVideo", alias = "GraphVideo")]
     Video(XDTGraphVideo),
-    #[serde(rename = "XDTGraphSidecar")]
+    #[serde(rename = "XDTGraphSidecar", alias = "GraphSidecar")]
     Sidecar(XDTGraphSidecar),
 }
 
@@ -360,7 +454,9 @@ pub struct EdgeMediaToCaptionEdge {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EdgeMediaToCaptionNode {
+    #[serde(default)]
     pub id: String,
+    #[serde(default)]
     pub created_at: String,
     pub text: String,
 }
@@ -378,7 +474,7 @@ pub struct SidecarEdge {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "__typename")]
 pub enum SidecarNode {
-    #[serde(rename = "XDTGraphImage")]
+    #[serde(rename = "XDTGraphImage", alias = "GraphImage")]
     Image {
         id: String,
         shortcode: String,
@@ -386,7 +482,7 @@ pub enum SidecarNode {
         #[serde(default)]
         is_video: bool,
     },
-    #[serde(rename = "XDTGraphVideo")]
+    #[serde(rename = "XDTGraphVideo", alias = "GraphVideo")]
     Video {
         id: String,
         shortcode: String,
@@ -399,6 +495,11 @@ pub enum SidecarNode {
 
 // ---- Story ---
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GraphReelsMedia {
+    pub reels_media: Vec<GraphReel>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GraphReel {
     #[serde(rename = "__typename")]
@@ -406,6 +507,8 @@ pub struct GraphReel {
     pub id: String,
     pub items: Vec<GraphStoryItem>,
     pub owner: Owner,
+    #[serde(default)]
+    pub title: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -417,6 +520,15 @@ pub enum GraphStoryItem {
     Video(GraphStoryItemVideo),
 }
 
+impl GraphStoryItem {
+    pub fn id(&self) -> &str {
+        match self {
+            GraphStoryItem::Image(image) => &image.id,
+            GraphStoryItem::Video(video) => &video.id,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GraphStoryItemImage {
     pub id: String,
@@ -441,3 +553,89 @@ pub struct VideoResource {
     pub config_width: Option<i32>,
     pub config_height: Option<i32>,
 }
+
+// --- Profile ---
+
+/// Which part of a profile's timeline a bulk download covers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProfileRange {
+    /// The latest N posts
+    Latest(usize),
+    /// Posts published within the last N days
+    Days(i64),
+    /// The latest N reels
+    Reels(usize),
+}
+
+impl ProfileRange {
+    /// Upper bound of posts enqueued by a single bulk download
+    pub const MAX_POSTS: usize = 50;
+
+    /// Whether the posts fetched so far (newest first) are enough to cover the range
+    pub fn is_covered_by(&self, posts: &[ProfilePost], now: DateTime<Utc>) -> bool {
+        match self {
+            ProfileRange::Latest(count) => posts.len() >= *count,
+            ProfileRange::Days(days) => posts
+                .last()
+                .is_some_and(|post| post.taken_at < now - chrono::Duration::days(*days)),
+            ProfileRange::Reels(count) => posts.iter().filter(|post| post.is_reel).count() >= *count,
+        }
+    }
+
+    pub fn select(&self, posts: Vec<ProfilePost>, now: DateTime<Utc>) -> Vec<ProfilePost> {
+        let selected: Vec<_> = match self {
+            ProfileRange::Latest(count) => posts.into_iter().take(*count).collect(),
+            ProfileRange::Days(days) => posts
+                .into_iter()
+                .filter(|post| post.taken_at >= now - chrono::Duration::days(*days))
+                .collect(),
+            ProfileRange::Reels(count) => posts.into_iter().filter(|post| post.is_reel).take(*count).collect(),
+        };
+
+        selected.into_iter().take(Self::MAX_POSTS).collect()
+    }
+}
+
+impl fmt::Display for ProfileRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProfileRange::Latest(count) => write!(f, "latest:{}", count),
+            ProfileRange::Days(days) => write!(f, "days:{}", days),
+            ProfileRange::Reels(count) => write!(f, "reels:{}", count),
+        }
+    }
+}
+
+impl FromStr for ProfileRange {
+    type Err = InstagramError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InstagramError::InvalidUrl(format!("Invalid profile range: {}", s));
+
+        let (kind, value) = s.split_once(':').ok_or_else(invalid)?;
+
+        match kind {
+            "latest" => Ok(ProfileRange::Latest(value.parse().map_err(|_| invalid())?)),
+            "days" => Ok(ProfileRange::Days(value.parse().map_err(|_| invalid())?)),
+            "reels" => Ok(ProfileRange::Reels(value.parse().map_err(|_| invalid())?)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProfilePost {
+    pub shortcode: String,
+    pub taken_at: DateTime<Utc>,
+    pub is_reel: bool,
+}
+
+impl ProfilePost {
+    pub fn url(&self) -> String {
+        if self.is_reel {
+            format!("https://www.instagram.com/reel/{}/", self.shortcode)
+        } else {
+            format!("https://www.instagram.com/p/{}/", self.shortcode)
+        }
+    }
+}
@@ -2,11 +2,14 @@ use std::sync::LazyLock;
 
 use anyhow::Context;
 use regex::Regex;
+use url::Url;
 
-use super::InstagramError;
+use crate::service::http::{HttpClient, HttpService};
+
+use super::{model::InstagramIdentifier, InstagramError};
 
 static INSTAGRAM_URL_REGEX: LazyLock<Regex> = LazyLock::new(|| {
-    Regex::new(r"https?://(?:www\.)?instagram\.com/[^\s]+")
+    Regex::new(r"https?://(?:(?:www|m)\.)?(?:instagram\.com|instagr\.am)/[^\s]+")
         .context("Failed to create Instagram URL regex")
         .unwrap()
 });
@@ -17,6 +20,12 @@ static INSTAGRAM_USERNAME_REGEX: LazyLock<Regex> = LazyLock::new(|| {
         .unwrap()
 });
 
+static INSTAGRAM_SHORTCODE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^[A-Za-z0-9_-]+$")
+        .context("Failed to create Instagram shortcode regex")
+        .unwrap()
+});
+
 static INSTAGRAM_PASSWORD_REGEX: LazyLock<Regex> = LazyLock::new(|| {
     Regex::new(r"^.{8,}$")
         .context("Failed to create Instagram password regex")
@@ -27,6 +36,167 @@ pub fn extract_instagram_url(text: &str) -> Option<String> {
     INSTAGRAM_URL_REGEX.find(text).map(|m| m.as_str().to_string())
 }
 
+const INSTAGRAM_HOSTS: [&str; 5] = [
+    "instagram.com",
+    "www.instagram.com",
+    "m.instagram.com",
+    "instagr.am",
+    "www.instagr.am",
+];
+
+/// Top level paths which are not profiles
+const RESERVED_PATHS: [&str; 13] = [
+    "p",
+    "reel",
+    "reels",
+    "tv",
+    "stories",
+    "share",
+    "s",
+    "explore",
+    "accounts",
+    "direct",
+    "about",
+    "legal",
+    "developer",
+];
+
+/// Returns the username if the message points to a profile, either as `@username` or as a profile URL
+pub fn extract_instagram_profile(text: &str) -> Option<String> {
+    if let Some(url_str) = extract_instagram_url(text) {
+        let url = Url::parse(&url_str).ok()?;
+
+        return match parse_instagram_url(&url) {
+            Ok(InstagramIdentifier::Profile { username }) => Some(username),
+            _ => None,
+        };
+    }
+
+    let trimmed = text.trim();
+
+    if trimmed.starts_with('@') {
+        process_instagram_username(trimmed).ok()
+    } else {
+        None
+    }
+}
+
+pub fn is_instagram_url(url: &Url) -> bool {
+    url.host_str().is_some_and(|host| INSTAGRAM_HOSTS.contains(&host))
+}
+
+/// Share links (`/share/<token>`) only reveal what they point to by redirecting
+pub fn is_instagram_share_url(url: &Url) -> bool {
+    is_instagram_url(url) && url.path_segments().and_then(|mut s| s.next()) == Some("share")
+}
+
+/// Resolves share links and maps the URL to an identifier, see `parse_instagram_url`
+pub async fn normalize_instagram_url(
+    http_service: &HttpService,
+    url_str: &str,
+) -> Result<InstagramIdentifier, InstagramError> {
+    let url = Url::parse(url_str.trim()).map_err(|_| InstagramError::InvalidUrl(url_str.to_string()))?;
+
+    if !is_instagram_url(&url) {
+        return Err(InstagramError::InvalidUrl("Not an Instagram URL".into()));
+    }
+
+    let url = if is_instagram_share_url(&url) {
+        resolve_instagram_share_url(http_service, &url).await?
+    } else {
+        url
+    };
+
+    parse_instagram_url(&url)
+}
+
+async fn resolve_instagram_share_url(http_service: &HttpService, url: &Url) -> Result<Url, InstagramError> {
+    let response = http_service.get(url.as_str()).await?;
+    let resolved = unwrap_login_redirect(response.url().clone());
+
+    if !is_instagram_url(&resolved) || is_instagram_share_url(&resolved) {
+        return Err(InstagramError::InvalidUrl(format!(
+            "Share link did not resolve: {}",
+            url
+        )));
+    }
+
+    info!("Resolved share link {} to {}", url, resolved);
+    Ok(resolved)
+}
+
+/// Logged out requests end on the login page, with the actual target in `next`
+fn unwrap_login_redirect(url: Url) -> Url {
+    if !url.path().starts_with("/accounts/login") {
+        return url;
+    }
+
+    url.query_pairs()
+        .find(|(key, _)| key == "next")
+        .and_then(|(_, next)| url.join(&next).ok())
+        .unwrap_or(url)
+}
+
+/// Maps every known Instagram URL shape to an identifier, query params and fragments are ignored
+pub fn parse_instagram_url(url: &Url) -> Result<InstagramIdentifier, InstagramError> {
+    if !is_instagram_url(url) {
+        return Err(InstagramError::InvalidUrl("Not an Instagram URL".into()));
+    }
+
+    let segments: Vec<_> = url
+        .path_segments()
+        .map(|segments| segments.filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default();
+
+    let is_shortcode = |code: &str| INSTAGRAM_SHORTCODE_REGEX.is_match(code);
+    let is_username = |name: &str| !RESERVED_PATHS.contains(&name) && validate_instagram_username(name);
+
+    let identifier = match segments.as_slice() {
+        ["stories", "highlights", highlight_id, ..] if highlight_id.chars().all(|c| c.is_ascii_digit()) => {
+            InstagramIdentifier::Highlight {
+                highlight_id: highlight_id.to_string(),
+            }
+        }
+        ["stories", username, story_id, ..]
+            if is_username(username) && story_id.chars().all(|c| c.is_ascii_digit()) =>
+        {
+            InstagramIdentifier::Story {
+                username: username.to_string(),
+                story_id: story_id.to_string(),
+            }
+        }
+        ["p" | "tv", shortcode, ..] if is_shortcode(shortcode) => InstagramIdentifier::Post {
+            shortcode: shortcode.to_string(),
+        },
+        ["reel" | "reels", shortcode, ..] if is_shortcode(shortcode) && *shortcode != "audio" => {
+            InstagramIdentifier::Reel {
+                shortcode: shortcode.to_string(),
+            }
+        }
+        [username, "p" | "tv", shortcode, ..] if is_username(username) && is_shortcode(shortcode) => {
+            InstagramIdentifier::Post {
+                shortcode: shortcode.to_string(),
+            }
+        }
+        [username, "reel", shortcode, ..] if is_username(username) && is_shortcode(shortcode) => {
+            InstagramIdentifier::Reel {
+                shortcode: shortcode.to_string(),
+            }
+        }
+        [username] if is_username(username) => InstagramIdentifier::Profile {
+            username: username.to_string(),
+        },
+        _ => {
+            return Err(InstagramError::InvalidUrl(format!(
+                "Unsupported Instagram URL: {}",
+                url
+            )))
+        }
+    };
+
+    Ok(identifier)
+}
+
 pub fn validate_instagram_username(username: &str) -> bool {
     INSTAGRAM_USERNAME_REGEX.is_match(username)
 }
@@ -118,6 +288,8 @@ mod tests {
         assert!(INSTAGRAM_URL_REGEX.is_match("https://instagram.com/user_name"));
         assert!(INSTAGRAM_URL_REGEX.is_match("https://instagram.com/p/ABC123"));
         assert!(INSTAGRAM_URL_REGEX.is_match("https://www.instagram.com/reel/ABC123"));
+        assert!(INSTAGRAM_URL_REGEX.is_match("https://m.instagram.com/p/ABC123"));
+        assert!(INSTAGRAM_URL_REGEX.is_match("https://instagr.am/p/ABC123"));
 
         // Invalid URLs
         assert!(!INSTAGRAM_URL_REGEX.is_match("instagram.com/username")); // Missing protocol
@@ -150,6 +322,126 @@ mod tests {
         assert_eq!(normalize_instagram_username("  user_name  "), "user_name"); // Should trim spaces
         assert_eq!(normalize_instagram_username("user__name"), "user__name"); // Should keep double underscores
     }
+    #[test]
+    fn test_extract_instagram_profile() {
+        assert_eq!(extract_instagram_profile("@user123"), Some("user123".into()));
+        assert_eq!(extract_instagram_profile("  @user.name  "), Some("user.name".into()));
+        assert_eq!(
+            extract_instagram_profile("https://www.instagram.com/user123/"),
+            Some("user123".into())
+        );
+        assert_eq!(
+            extract_instagram_profile("check this https://instagram.com/user_name?igsh=abc"),
+            Some("user_name".into())
+        );
+
+        assert_eq!(extract_instagram_profile("user123"), None);
+        assert_eq!(extract_instagram_profile("https://www.instagram.com/p/ABC123/"), None);
+        assert_eq!(extract_instagram_profile("https://www.instagram.com/reels/"), None);
+        assert_eq!(
+            extract_instagram_profile("https://www.instagram.com/stories/user123/123/"),
+            None
+        );
+        assert_eq!(extract_instagram_profile("@user@123"), None);
+    }
+
+    #[test]
+    fn test_parse_instagram_url() {
+        let post = |code: &str| {
+            Some(InstagramIdentifier::Post {
+                shortcode: code.to_string(),
+            })
+        };
+        let reel = |code: &str| {
+            Some(InstagramIdentifier::Reel {
+                shortcode: code.to_string(),
+            })
+        };
+
+        let cases = [
+            ("https://www.instagram.com/p/ABC123/", post("ABC123")),
+            ("https://instagram.com/p/ABC123", post("ABC123")),
+            (
+                "https://m.instagram.com/p/ABC123/?igsh=MWQ1ZGUxMzBkMA==",
+                post("ABC123"),
+            ),
+            ("https://instagr.am/p/ABC123/", post("ABC123")),
+            ("http://www.instagr.am/p/ABC123", post("ABC123")),
+            (
+                "https://www.instagram.com/p/ABC-12_3/?img_index=2#comments",
+                post("ABC-12_3"),
+            ),
+            ("https://www.instagram.com/tv/ABC123/", post("ABC123")),
+            ("https://www.instagram.com/user.name/p/ABC123/", post("ABC123")),
+            (
+                "https://www.instagram.com/user_name/tv/ABC123/?utm_source=ig_web_copy_link",
+                post("ABC123"),
+            ),
+            ("https://www.instagram.com/reel/ABC123/", reel("ABC123")),
+            ("https://www.instagram.com/reels/ABC123/", reel("ABC123")),
+            (
+                "https://www.instagram.com/reel/ABC123/?igsh=abc&utm_source=share",
+                reel("ABC123"),
+            ),
+            ("https://www.instagram.com/user.name/reel/ABC123/", reel("ABC123")),
+            (
+                "https://www.instagram.com/stories/user.name/3548702136257550949/?utm_source=ig_story_item_share",
+                Some(InstagramIdentifier::Story {
+                    username: "user.name".into(),
+                    story_id: "3548702136257550949".into(),
+                }),
+            ),
+            (
+                "https://www.instagram.com/stories/highlights/17900000000000000/",
+                Some(InstagramIdentifier::Highlight {
+                    highlight_id: "17900000000000000".into(),
+                }),
+            ),
+            (
+                "https://www.instagram.com/user_name/?hl=en",
+                Some(InstagramIdentifier::Profile {
+                    username: "user_name".into(),
+                }),
+            ),
+            ("https://www.instagram.com/", None),
+            ("https://www.instagram.com/reels/", None),
+            ("https://www.instagram.com/reels/audio/123/", None),
+            ("https://www.instagram.com/explore/tags/rust/", None),
+            ("https://www.instagram.com/stories/user.name/", None),
+            ("https://www.instagram.com/share/AbCdEf/", None),
+            ("https://www.instagram.com/p/ABC$123/", None),
+            ("https://www.instagramm.com/p/ABC123/", None),
+            ("https://example.com/p/ABC123/", None),
+        ];
+
+        for (url, expected) in cases {
+            let actual = parse_instagram_url(&Url::parse(url).unwrap()).ok();
+            assert_eq!(actual, expected, "{}", url);
+        }
+    }
+
+    #[test]
+    fn test_share_url() {
+        let share = Url::parse("https://www.instagram.com/share/reel/BAcDeF123/").unwrap();
+        assert!(is_instagram_share_url(&share));
+        assert!(!is_instagram_share_url(
+            &Url::parse("https://www.instagram.com/p/ABC123/").unwrap()
+        ));
+
+        let login =
+            Url::parse("https://www.instagram.com/accounts/login/?next=%2Freel%2FABC123%2F%3Figsh%3Dabc").unwrap();
+        let resolved = unwrap_login_redirect(login);
+        assert_eq!(
+            parse_instagram_url(&resolved).ok(),
+            Some(InstagramIdentifier::Reel {
+                shortcode: "ABC123".into()
+            })
+        );
+
+        let post = Url::parse("https://www.instagram.com/p/ABC123/").unwrap();
+        assert_eq!(unwrap_login_redirect(post.clone()), post);
+    }
+
     #[test]
     fn test_process_instagram_username() {
         // Test valid usernames
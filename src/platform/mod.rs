@@ -1,5 +1,6 @@
 mod download;
 mod error;
+mod in_flight;
 mod model;
 pub mod traits;
 mod util;
@@ -9,7 +10,10 @@ use std::sync::Arc;
 use dashmap::DashMap;
 use instagram::model::InstagramIdentifier;
 
+use crate::config::PlatformConfig;
+
 pub use error::*;
+use in_flight::InFlight;
 pub use model::*;
 use traits::PlatformCapability;
 pub use util::*;
@@ -21,18 +25,28 @@ pub mod instagram;
 #[derive(Clone)]
 pub struct PlatformRegistry {
     platforms: Arc<DashMap<Platform, Arc<dyn PlatformCapability>>>,
+    failures: Arc<DashMap<FailureReason, u64>>,
+    /// Resources being fetched, keyed by identifier so concurrent downloads of the same post share a fetch
+    fetches: Arc<InFlight<MediaFile>>,
 }
 
 impl PlatformRegistry {
-    pub fn new() -> Result<Self, PlatformError> {
+    pub fn new(config: &PlatformConfig) -> Result<Self, PlatformError> {
         info!("Initializing platform registry");
 
         let platforms = Arc::new(DashMap::<Platform, Arc<dyn PlatformCapability>>::new());
         info!("Registering Instagram platform");
-        platforms.insert(Platform::Instagram, Arc::new(PlatformInstagram::new()?));
+        platforms.insert(
+            Platform::Instagram,
+            Arc::new(PlatformInstagram::new(config.instagram.clone())?),
+        );
 
         info!("Platform registry initialized");
-        Ok(Self { platforms })
+        Ok(Self {
+            platforms,
+            failures: Arc::new(DashMap::new()),
+            fetches: Arc::new(InFlight::default()),
+        })
     }
 
     pub fn generate_identifier(&self, resource: &PlatformIdentifier) -> String {
@@ -45,9 +59,41 @@ impl PlatformRegistry {
             ) => {
                 format!("instagram:{}", shortcode)
             }
+            PlatformIdentifier::Instagram(InstagramIdentifier::Highlight { highlight_id }) => {
+                format!("instagram:highlight:{}", highlight_id)
+            }
+            PlatformIdentifier::Instagram(InstagramIdentifier::Profile { username }) => {
+                format!("instagram:profile:{}", username)
+            }
         }
     }
 
+    /// The identifier of the resource a URL points to, see [`Self::generate_identifier`]
+    pub async fn identify(&self, platform: &Platform, url: &str) -> Result<String, PlatformError> {
+        let platform_service = self
+            .platforms
+            .get(platform)
+            .map(|platform| platform.value().clone())
+            .ok_or_else(|| PlatformError::ResourceError("Platform not found".into()))?;
+        let resource = platform_service.parse_url(url).await?;
+        Ok(self.generate_identifier(&resource))
+    }
+
+    pub fn record_failure(&self, reason: FailureReason) {
+        *self.failures.entry(reason).or_insert(0) += 1;
+    }
+
+    /// Failure counts per reason since startup, most frequent first
+    pub fn failure_stats(&self) -> Vec<(FailureReason, u64)> {
+        let mut stats = self
+            .failures
+            .iter()
+            .map(|entry| (*entry.key(), *entry.value()))
+            .collect::<Vec<_>>();
+        stats.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        stats
+    }
+
     pub fn get_platform<T: PlatformCapability + 'static>(&self, platform: &Platform) -> Option<Arc<T>> {
         self.platforms.get(platform).and_then(|p| {
             let platform_ref = p.value();
@@ -7,9 +7,15 @@ use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
 use url::Url;
 
-use crate::service::Cacheable;
+use crate::{
+    error::BotError,
+    service::{AuthError, Cacheable},
+};
 
-use super::{instagram::model::InstagramIdentifier, PlatformError};
+use super::{
+    instagram::{model::InstagramIdentifier, InstagramError},
+    PlatformError,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Ord, PartialOrd)]
 pub enum MediaType {
@@ -73,6 +79,8 @@ pub enum MediaContentType {
 pub struct MediaAuthor {
     pub id: String,
     pub username: String,
+    #[serde(default)]
+    pub is_private: bool,
 }
 
 // ------------------------------------------------------------
@@ -102,6 +110,10 @@ pub struct MediaFileItem {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub duration: Option<Duration>,
     pub created_at: DateTime<Utc>,
+    /// Id Telegram gave the file when it was last sent, re-sending it this way skips the download from the
+    /// platform
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub telegram_file_id: Option<String>,
 }
 
 impl Cacheable for MediaFile {
@@ -112,23 +124,121 @@ impl Cacheable for MediaFile {
     fn cache_key(&self) -> String {
         format!("{}:{}", self.platform.to_string().to_lowercase(), self.id)
     }
+
+    /// When the first link still needed expires, so the file isn't handed out with links Telegram can't fetch.
+    /// Items Telegram already has are sent by file id, and once all of them are the thumbnail is only a
+    /// nicety of the preview.
+    fn expires_at(&self) -> Option<DateTime<Utc>> {
+        let pending = self
+            .items
+            .iter()
+            .filter(|item| item.telegram_file_id.is_none())
+            .map(|item| &item.url)
+            .collect::<Vec<_>>();
+        let thumbnail = self.thumbnail.as_ref().filter(|_| !pending.is_empty());
+
+        pending.into_iter().chain(thumbnail).filter_map(url_expiry).min()
+    }
+}
+
+/// Signed CDN links such as Instagram's carry their expiry in `oe`, a unix timestamp in hex
+fn url_expiry(url: &Url) -> Option<DateTime<Utc>> {
+    url.query_pairs()
+        .find(|(key, _)| key == "oe")
+        .and_then(|(_, value)| i64::from_str_radix(&value, 16).ok())
+        .and_then(|timestamp| DateTime::from_timestamp(timestamp, 0))
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum DownloadState {
     RateLimited,
     Success(MediaFile),
-    Error, // TODO: add error inside
+    Error(FailureReason),
+    /// Cancelled by the user before it finished
+    Cancelled,
+}
+
+/// Why a download failed, shown to users as a localized message and counted for admins
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum FailureReason {
+    LoginRequired,
+    CheckpointRequired,
+    RateLimited,
+    PrivateAccount,
+    MediaNotFound,
+    AgeRestricted,
+    GeoBlocked,
+    Unknown,
+}
+
+impl FailureReason {
+    pub fn from_error(error: &(dyn std::error::Error + 'static)) -> Self {
+        if let Some(BotError::PlatformError(error)) = error.downcast_ref::<BotError>() {
+            return Self::from_error(error);
+        }
+
+        if let Some(PlatformError::Instagram(error)) = error.downcast_ref::<PlatformError>() {
+            return error.into();
+        }
+
+        if let Some(error) = error.downcast_ref::<InstagramError>() {
+            return error.into();
+        }
+
+        if let Some(AuthError::AuthenticationRequired) = error.downcast_ref::<AuthError>() {
+            return FailureReason::LoginRequired;
+        }
+
+        FailureReason::Unknown
+    }
+
+    /// Failures that may go away on their own, everything else needs the user or an admin to act
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, FailureReason::RateLimited | FailureReason::Unknown)
+    }
+
+    pub fn message_key(&self) -> &'static str {
+        match self {
+            FailureReason::LoginRequired => "messages.download.failure.login_required",
+            FailureReason::CheckpointRequired => "messages.download.failure.checkpoint_required",
+            FailureReason::RateLimited => "messages.download.failure.rate_limited",
+            FailureReason::PrivateAccount => "messages.download.failure.private_account",
+            FailureReason::MediaNotFound => "messages.download.failure.media_not_found",
+            FailureReason::AgeRestricted => "messages.download.failure.age_restricted",
+            FailureReason::GeoBlocked => "messages.download.failure.geo_blocked",
+            FailureReason::Unknown => "messages.download.failure.unknown",
+        }
+    }
+}
+
+impl From<&InstagramError> for FailureReason {
+    fn from(error: &InstagramError) -> Self {
+        match error {
+            InstagramError::LoginRequired | InstagramError::AuthenticationError(_) => FailureReason::LoginRequired,
+            InstagramError::CheckpointRequired => FailureReason::CheckpointRequired,
+            InstagramError::RateLimited => FailureReason::RateLimited,
+            InstagramError::PrivateAccount => FailureReason::PrivateAccount,
+            InstagramError::MediaNotFound => FailureReason::MediaNotFound,
+            InstagramError::AgeRestricted => FailureReason::AgeRestricted,
+            InstagramError::GeoBlocked => FailureReason::GeoBlocked,
+            _ => FailureReason::Unknown,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum PostDownloadState {
     Success,
     Error,
+    Cancelled,
 }
 
 impl MediaFile {
-    pub fn get_preview_text(&self) -> String {
+    pub fn is_private(&self) -> bool {
+        self.author.as_ref().is_some_and(|author| author.is_private)
+    }
+
+    pub fn get_preview_text(&self, locale: &str) -> String {
         let mut preview = String::new();
 
         if let Some(title) = &self.title {
@@ -140,8 +250,8 @@ impl MediaFile {
         }
 
         if let Some(desc) = &self.description {
-            let truncated_desc = if desc.len() > 100 {
-                format!("{}...", &desc[0..97])
+            let truncated_desc = if desc.chars().count() > 100 {
+                format!("{}...", desc.chars().take(97).collect::<String>())
             } else {
                 desc.clone()
             };
@@ -150,73 +260,285 @@ impl MediaFile {
         }
 
         match self.content_type {
-            MediaContentType::Single => {
-                if let Some(first_item) = self.items.first() {
-                    match first_item.media_type {
-                        MediaType::Image => {
-                            let text = t!("messages.download.preview.single_image");
-
-                            preview.push_str(&format!("📷 {}\n\n", text));
-                        }
-                        MediaType::Video => {
-                            let duration = first_item.duration.map(|d| format!("{}", d.num_seconds()));
-
-                            let text = match duration {
-                                Some(duration) => t!(
-                                    "messages.download.preview.single_video_with_duration",
-                                    duration = duration
-                                ),
-                                None => t!("messages.download.preview.single_video"),
-                            };
-
-                            preview.push_str(&format!("🎥 {}\n\n", text));
-                        }
-                        _ => todo!(),
-                    }
-                }
+            MediaContentType::Story => {
+                preview.push_str(&format!(
+                    "📖 {}\n\n",
+                    t!("messages.download.preview.story", locale = locale)
+                ));
             }
+            MediaContentType::Album => {
+                let text = match self.items.len() {
+                    1 => t!("messages.download.preview.album_single", locale = locale),
+                    count => t!("messages.download.preview.album", locale = locale, count = count),
+                };
 
-            MediaContentType::Multiple => {
-                let mut image_count = 0;
-                let mut video_count = 0;
-                let mut audio_count = 0;
-
-                for item in &self.items {
-                    match item.media_type {
-                        MediaType::Image => image_count += 1,
-                        MediaType::Video => video_count += 1,
-                        MediaType::Audio => audio_count += 1,
-                    }
-                }
-
-                if image_count > 0 && video_count == 0 && audio_count == 0 {
-                    let text = t!("messages.download.preview.multiple_images", count = image_count);
-
-                    preview.push_str(&format!("📷 {}\n\n", text));
-                } else if video_count > 0 && image_count == 0 && audio_count == 0 {
-                    let text = t!("messages.download.preview.multiple_videos", count = video_count);
-
-                    preview.push_str(&format!("🎥 {}\n\n", text));
-                } else if audio_count > 0 && image_count == 0 && video_count == 0 {
-                    let text = t!("messages.download.preview.multiple_audios", count = audio_count);
-
-                    preview.push_str(&format!("🎵 {}\n\n", text));
-                } else {
-                    todo!()
-
-                    // preview.push_str(&format!("📦 *{}*:\n",
-                    //     t!("messages.download.preview.multiple_files"),
-                    //     image_count,
-                    //     video_count,
-                    //     audio_count,
-                    // ));
-                }
+                preview.push_str(&format!("🗂 {}\n\n", text));
             }
-            _ => todo!(),
+            MediaContentType::Playlist => {
+                let text = match self.items.len() {
+                    1 => t!("messages.download.preview.playlist_single", locale = locale),
+                    count => t!("messages.download.preview.playlist", locale = locale, count = count),
+                };
+
+                preview.push_str(&format!("🎶 {}\n\n", text));
+            }
+            MediaContentType::Single | MediaContentType::Multiple => {}
         }
 
+        preview.push_str(&self.get_items_preview_text(locale));
+
         preview.push_str(&format!("⏰ {}\n", self.created_at.format("%Y-%m-%d %H:%M:%S")));
 
         preview
     }
+
+    fn get_items_preview_text(&self, locale: &str) -> String {
+        let mut image_count = 0;
+        let mut video_count = 0;
+        let mut audio_count = 0;
+
+        for item in &self.items {
+            match item.media_type {
+                MediaType::Image => image_count += 1,
+                MediaType::Video => video_count += 1,
+                MediaType::Audio => audio_count += 1,
+            }
+        }
+
+        match (image_count, video_count, audio_count) {
+            (0, 0, 0) => String::new(),
+            (1, 0, 0) => format!(
+                "📷 {}\n\n",
+                t!("messages.download.preview.single_image", locale = locale)
+            ),
+            (0, 1, 0) => {
+                let duration = self.items[0].duration.map(|d| format!("{}", d.num_seconds()));
+
+                let text = match duration {
+                    Some(duration) => t!(
+                        "messages.download.preview.single_video_with_duration",
+                        locale = locale,
+                        duration = duration
+                    ),
+                    None => t!("messages.download.preview.single_video", locale = locale),
+                };
+
+                format!("🎥 {}\n\n", text)
+            }
+            (0, 0, 1) => format!(
+                "🎵 {}\n\n",
+                t!("messages.download.preview.single_audio", locale = locale)
+            ),
+            (count, 0, 0) => format!(
+                "📷 {}\n\n",
+                t!(
+                    "messages.download.preview.multiple_images",
+                    locale = locale,
+                    count = count
+                )
+            ),
+            (0, count, 0) => format!(
+                "🎥 {}\n\n",
+                t!(
+                    "messages.download.preview.multiple_videos",
+                    locale = locale,
+                    count = count
+                )
+            ),
+            (0, 0, count) => format!(
+                "🎵 {}\n\n",
+                t!(
+                    "messages.download.preview.multiple_audios",
+                    locale = locale,
+                    count = count
+                )
+            ),
+            (images, videos, audios) => {
+                let counts = [
+                    (images, "image", "images"),
+                    (videos, "video", "videos"),
+                    (audios, "audio", "audios"),
+                ]
+                .into_iter()
+                .filter(|(count, _, _)| *count > 0)
+                .map(|(count, one, many)| match count {
+                    1 => t!(format!("messages.download.preview.count.{}", one), locale = locale),
+                    count => t!(
+                        format!("messages.download.preview.count.{}", many),
+                        locale = locale,
+                        count = count
+                    ),
+                })
+                .collect::<Vec<_>>()
+                .join(&t!("messages.download.preview.mixed_separator", locale = locale));
+
+                let text = t!(
+                    "messages.download.preview.mixed",
+                    locale = locale,
+                    count = self.items.len(),
+                    counts = counts
+                );
+
+                format!("📦 {}\n\n", text)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(id: &str, media_type: MediaType) -> MediaFileItem {
+        MediaFileItem {
+            id: id.to_string(),
+            media_type,
+            url: Url::parse(&format!("https://example.com/{}", id)).unwrap(),
+            duration: None,
+            created_at: Utc::now(),
+            telegram_file_id: None,
+        }
+    }
+
+    fn media_file(content_type: MediaContentType, items: Vec<MediaFileItem>) -> MediaFile {
+        MediaFile {
+            id: "id".to_string(),
+            created_at: Utc::now(),
+            title: None,
+            description: None,
+            author: Some(MediaAuthor {
+                id: "1".to_string(),
+                username: "author".to_string(),
+                is_private: false,
+            }),
+            content_type,
+            thumbnail: None,
+            items,
+            platform: Platform::Instagram,
+        }
+    }
+
+    #[test]
+    fn test_preview_single() {
+        let preview = media_file(MediaContentType::Single, vec![item("1", MediaType::Image)]).get_preview_text("en");
+        assert!(preview.contains("👤 author"));
+        assert!(preview.contains("📷 Found one image"));
+
+        let mut video = item("1", MediaType::Video);
+        video.duration = Some(Duration::seconds(42));
+        let preview = media_file(MediaContentType::Single, vec![video]).get_preview_text("en");
+        assert!(preview.contains("🎥 Found one video with duration 42 seconds"));
+    }
+
+    #[test]
+    fn test_preview_multiple() {
+        let images = vec![item("1", MediaType::Image), item("2", MediaType::Image)];
+        let preview = media_file(MediaContentType::Multiple, images).get_preview_text("en");
+        assert!(preview.contains("📷 Found 2 images"));
+
+        let mixed = vec![
+            item("1", MediaType::Image),
+            item("2", MediaType::Video),
+            item("3", MediaType::Image),
+        ];
+        let preview = media_file(MediaContentType::Multiple, mixed).get_preview_text("en");
+        assert!(preview.contains("📦 Found 3 files: 2 images, 1 video\n"));
+
+        let mixed = vec![item("1", MediaType::Image), item("2", MediaType::Audio)];
+        let preview = media_file(MediaContentType::Multiple, mixed).get_preview_text("zh");
+        assert!(preview.contains("找到 2 个文件：1 张图片，1 个音频"));
+    }
+
+    #[test]
+    fn test_preview_story() {
+        let preview = media_file(MediaContentType::Story, vec![item("1", MediaType::Video)]).get_preview_text("en");
+        assert!(preview.contains("📖 Story"));
+        assert!(preview.contains("🎥 Found one video"));
+    }
+
+    #[test]
+    fn test_preview_album() {
+        let items = vec![item("1", MediaType::Image), item("2", MediaType::Video)];
+        let preview = media_file(MediaContentType::Album, items).get_preview_text("en");
+        assert!(preview.contains("🗂 Album with 2 items"));
+        assert!(preview.contains("📦 Found 2 files"));
+    }
+
+    #[test]
+    fn test_preview_playlist() {
+        let items = vec![item("1", MediaType::Audio), item("2", MediaType::Audio)];
+        let preview = media_file(MediaContentType::Playlist, items).get_preview_text("en");
+        assert!(preview.contains("🎶 Playlist with 2 items"));
+        assert!(preview.contains("🎵 Found 2 audios"));
+    }
+
+    #[test]
+    fn test_preview_truncates_multibyte_description() {
+        let mut file = media_file(MediaContentType::Single, vec![item("1", MediaType::Image)]);
+        file.description = Some("🙂".repeat(150));
+
+        let preview = file.get_preview_text("en");
+        assert!(preview.contains(&format!("📄 {}...", "🙂".repeat(97))));
+    }
+
+    #[test]
+    fn test_failure_reason_from_error() {
+        let error: Box<dyn std::error::Error + Send + Sync> =
+            PlatformError::from(InstagramError::PrivateAccount).into();
+        assert_eq!(FailureReason::from_error(error.as_ref()), FailureReason::PrivateAccount);
+
+        let error = BotError::PlatformError(InstagramError::MediaNotFound.into());
+        assert_eq!(FailureReason::from_error(&error), FailureReason::MediaNotFound);
+
+        let error: Box<dyn std::error::Error + Send + Sync> = AuthError::AuthenticationRequired.into();
+        assert_eq!(FailureReason::from_error(error.as_ref()), FailureReason::LoginRequired);
+
+        let error = PlatformError::ParsingError("Missing items".into());
+        assert_eq!(FailureReason::from_error(&error), FailureReason::Unknown);
+    }
+
+    #[test]
+    fn test_cached_items_keep_their_telegram_file_id() {
+        let mut file = media_file(MediaContentType::Single, vec![item("1", MediaType::Image)]);
+        let cached = serde_json::to_value(&file).unwrap();
+        assert!(cached["items"][0].get("telegram_file_id").is_none());
+        assert_eq!(serde_json::from_value::<MediaFile>(cached).unwrap(), file);
+
+        file.items[0].telegram_file_id = Some("AgACAgQAAxkBAAI".to_string());
+        let cached = serde_json::to_string(&file).unwrap();
+        assert_eq!(serde_json::from_str::<MediaFile>(&cached).unwrap(), file);
+    }
+
+    #[test]
+    fn test_expires_with_its_first_link() {
+        let mut file = media_file(
+            MediaContentType::Multiple,
+            vec![item("1", MediaType::Image), item("2", MediaType::Video)],
+        );
+        assert_eq!(file.expires_at(), None);
+
+        file.items[0].url = Url::parse("https://scontent.cdninstagram.com/v/1.jpg?oh=00_AY&oe=67A5B2C0").unwrap();
+        file.thumbnail = Some(Url::parse("https://scontent.cdninstagram.com/v/t.jpg?oe=67A5B2B0&_nc_sid=1").unwrap());
+        assert_eq!(file.expires_at(), DateTime::from_timestamp(0x67A5B2B0, 0));
+
+        file.thumbnail = None;
+        assert_eq!(file.expires_at(), DateTime::from_timestamp(0x67A5B2C0, 0));
+    }
+
+    #[test]
+    fn test_links_of_items_sent_before_dont_expire_it() {
+        let mut file = media_file(
+            MediaContentType::Multiple,
+            vec![item("1", MediaType::Image), item("2", MediaType::Video)],
+        );
+        file.items[0].url = Url::parse("https://scontent.cdninstagram.com/v/1.jpg?oe=67A5B2B0").unwrap();
+        file.items[1].url = Url::parse("https://scontent.cdninstagram.com/v/2.mp4?oe=67A5B2C0").unwrap();
+        file.thumbnail = Some(Url::parse("https://scontent.cdninstagram.com/v/t.jpg?oe=67A5B2A0").unwrap());
+
+        file.items[0].telegram_file_id = Some("file-1".to_string());
+        assert_eq!(file.expires_at(), DateTime::from_timestamp(0x67A5B2A0, 0));
+
+        file.items[1].telegram_file_id = Some("file-2".to_string());
+        assert_eq!(file.expires_at(), None);
+    }
 }
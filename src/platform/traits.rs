@@ -17,7 +17,8 @@ pub trait PlatformCapability: Send + Sync + Any {
 
     async fn parse_url(&self, url_str: &str) -> Result<PlatformIdentifier, PlatformError>;
 
-    async fn fetch_resource(&self, identifier: &PlatformIdentifier) -> HandlerResult<MediaFile>;
+    async fn fetch_resource(&self, identifier: &PlatformIdentifier, telegram_user_id: &str)
+        -> HandlerResult<MediaFile>;
 
     #[allow(unused)]
     async fn pre_process(
@@ -27,8 +28,16 @@ pub trait PlatformCapability: Send + Sync + Any {
         media_info: &MediaFile,
     ) -> HandlerResult<MediaFile>;
 
-    async fn send_to_telegram(&self, bot: &Throttle<Bot>, chat_id: ChatId, media_file: &MediaFile)
-        -> HandlerResult<()>;
+    /// Sends the media to the chat, calling `progress` with the number of items sent and the total. The media
+    /// is fetched again for `telegram_user_id` if Telegram can't send it from what was cached.
+    async fn send_to_telegram(
+        &self,
+        bot: &Throttle<Bot>,
+        chat_id: ChatId,
+        telegram_user_id: &str,
+        media_file: &MediaFile,
+        progress: &(dyn Fn(usize, usize) + Send + Sync),
+    ) -> HandlerResult<()>;
 
     #[allow(unused)]
     async fn post_process(&self, bot: &Throttle<Bot>, chat_id: ChatId, media_info: &MediaFile) -> HandlerResult<()>;
@@ -1,4 +1,9 @@
-use super::{instagram::extract_instagram_url, Platform};
+use teloxide::{
+    types::{InputFile, Message},
+    ApiError, RequestError,
+};
+
+use super::{instagram::extract_instagram_url, MediaFileItem, Platform};
 
 pub fn extract_url_from_message(platform: &Platform, text: &str) -> Option<String> {
     match platform {
@@ -6,3 +11,78 @@ pub fn extract_url_from_message(platform: &Platform, text: &str) -> Option<Strin
         _ => None,
     }
 }
+
+/// The file to send for an item, by the id Telegram gave it before if `reuse_file_id` and one is known,
+/// otherwise by url so Telegram fetches it from the platform
+pub fn telegram_input_file(item: &MediaFileItem, reuse_file_id: bool) -> InputFile {
+    match &item.telegram_file_id {
+        Some(file_id) if reuse_file_id => InputFile::file_id(file_id.clone()),
+        _ => InputFile::url(item.url.clone()),
+    }
+}
+
+/// The id Telegram gave the photo, video or audio of a message we sent
+pub fn sent_file_id(message: &Message) -> Option<String> {
+    message
+        .photo()
+        .and_then(|sizes| sizes.iter().max_by_key(|size| size.width * size.height))
+        .map(|size| size.file.id.clone())
+        .or_else(|| message.video().map(|video| video.file.id.clone()))
+        .or_else(|| message.audio().map(|audio| audio.file.id.clone()))
+}
+
+/// Whether Telegram rejected a file id, e.g. one it no longer knows
+pub fn is_stale_file_id(error: &RequestError) -> bool {
+    matches!(
+        error,
+        RequestError::Api(ApiError::WrongFileId | ApiError::WrongFileIdOrUrl | ApiError::FileIdInvalid)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use teloxide::types::ChatId;
+    use url::Url;
+
+    use super::*;
+    use crate::platform::MediaType;
+
+    fn item(telegram_file_id: Option<&str>) -> MediaFileItem {
+        MediaFileItem {
+            id: "1".to_string(),
+            media_type: MediaType::Image,
+            url: Url::parse("https://example.com/1.jpg").unwrap(),
+            duration: None,
+            created_at: Utc::now(),
+            telegram_file_id: telegram_file_id.map(str::to_string),
+        }
+    }
+
+    /// File ids and urls are both sent as plain strings
+    fn sent_as(file: InputFile) -> String {
+        serde_json::to_value(file).unwrap().as_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_telegram_input_file() {
+        assert_eq!(sent_as(telegram_input_file(&item(Some("file-id")), true)), "file-id");
+        assert_eq!(
+            sent_as(telegram_input_file(&item(Some("file-id")), false)),
+            "https://example.com/1.jpg"
+        );
+        assert_eq!(
+            sent_as(telegram_input_file(&item(None), true)),
+            "https://example.com/1.jpg"
+        );
+    }
+
+    #[test]
+    fn test_is_stale_file_id() {
+        assert!(is_stale_file_id(&RequestError::Api(ApiError::WrongFileId)));
+        assert!(is_stale_file_id(&RequestError::Api(ApiError::WrongFileIdOrUrl)));
+        assert!(is_stale_file_id(&RequestError::Api(ApiError::FileIdInvalid)));
+        assert!(!is_stale_file_id(&RequestError::Api(ApiError::BotBlocked)));
+        assert!(!is_stale_file_id(&RequestError::MigrateToChatId(ChatId(1))));
+    }
+}
@@ -1,4 +1,6 @@
-use crate::{config::ConfigError, error::BotError};
+use teloxide::{ApiError, RequestError};
+
+use crate::{config::ConfigError, error::BotError, platform::FailureReason};
 
 #[derive(Debug, thiserror::Error)]
 pub enum RuntimeError {
@@ -8,10 +10,69 @@ pub enum RuntimeError {
     RecvError(String),
     #[error("task error: {0}")]
     TaskError(String),
+    #[error("scheduler error: {0}")]
+    SchedulerError(String),
+    #[error("download failed: {0:?}")]
+    DownloadFailed(FailureReason),
+    #[error("{0} timed out")]
+    Timeout(String),
+    /// Part of the media is in the chat already, another attempt would send it twice
+    #[error("sending stopped after {sent} of {total} items: {reason}")]
+    PartiallySent { sent: usize, total: usize, reason: String },
+    /// Another attempt would most likely panic the same way
+    #[error("task panicked: {0}")]
+    Panicked(String),
+    /// Telegram will refuse the request again, e.g. the user blocked the bot or the chat is gone
+    #[error("rejected by Telegram: {0}")]
+    Rejected(String),
+    #[error("task cancelled")]
+    Cancelled,
+    #[error("already queued as task {0}")]
+    DuplicateTask(String),
+    #[error("shutting down")]
+    ShuttingDown,
     #[error("other error: {0}")]
     Other(String),
 }
 
+impl RuntimeError {
+    /// Transient failures worth another attempt, e.g. Instagram rate limits or Telegram timeouts
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            RuntimeError::TaskError(_) | RuntimeError::Timeout(_) => true,
+            RuntimeError::DownloadFailed(reason) => reason.is_retryable(),
+            _ => false,
+        }
+    }
+
+    /// Wraps a failed Telegram request, only those that may succeed later are retryable
+    pub fn telegram(context: &str, error: &(dyn std::error::Error + 'static)) -> Self {
+        let message = format!("{}: {}", context, error);
+
+        match error.downcast_ref::<RequestError>() {
+            Some(RequestError::MigrateToChatId(_)) => RuntimeError::Rejected(message),
+            Some(RequestError::Api(error)) if is_permanent(error) => RuntimeError::Rejected(message),
+            _ => RuntimeError::TaskError(message),
+        }
+    }
+}
+
+fn is_permanent(error: &ApiError) -> bool {
+    matches!(
+        error,
+        ApiError::BotBlocked
+            | ApiError::BotKicked
+            | ApiError::BotKickedFromSupergroup
+            | ApiError::ChatNotFound
+            | ApiError::UserNotFound
+            | ApiError::UserDeactivated
+            | ApiError::GroupDeactivated
+            | ApiError::CantInitiateConversation
+            | ApiError::NotEnoughRightsToPostMessages
+            | ApiError::RequestEntityTooLarge
+    )
+}
+
 impl From<BotError> for RuntimeError {
     fn from(error: BotError) -> Self {
         match error {
@@ -26,3 +87,22 @@ impl From<ConfigError> for RuntimeError {
         RuntimeError::Other(error.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_transient_telegram_errors_are_retried() {
+        let blocked = RuntimeError::telegram("Failed to send media", &RequestError::Api(ApiError::BotBlocked));
+        assert!(matches!(&blocked, RuntimeError::Rejected(e) if e.contains("Failed to send media")));
+        assert!(!blocked.is_retryable());
+
+        let boxed: Box<dyn std::error::Error + Send + Sync> = Box::new(RequestError::Api(ApiError::ChatNotFound));
+        assert!(!RuntimeError::telegram("Failed to send media", boxed.as_ref()).is_retryable());
+
+        let unknown = RequestError::Api(ApiError::Unknown("Internal Server Error".to_string()));
+        assert!(RuntimeError::telegram("Failed to edit message", &unknown).is_retryable());
+        assert!(!RuntimeError::Panicked("boom".to_string()).is_retryable());
+    }
+}
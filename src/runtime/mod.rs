@@ -1,45 +1,58 @@
-use std::sync::Arc;
-use teloxide::{adaptors::Throttle, Bot};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use teloxide::{
+    adaptors::Throttle,
+    prelude::Requester,
+    types::{ChatId, MessageId},
+    Bot,
+};
 use tokio::sync::broadcast;
 
 mod cache;
 mod error;
+mod progress;
 mod queue;
+mod scheduler;
 mod task;
 mod worker;
 
 pub use cache::*;
 pub use error::*;
+pub use progress::{Progress, ProgressReporter};
 pub use queue::TaskQueueManager;
+pub use scheduler::{Schedule, Scheduler};
 pub use task::{DownloadTask, TaskContext};
-pub use worker::WorkerPool;
+pub use worker::{download::replace_preview, WorkerPool};
 
-use crate::config::AppConfig;
+use crate::config::QueueConfig;
+use worker::StageTimeouts;
 
 #[derive(Clone)]
 pub struct RuntimeManager {
     pub queue_manager: TaskQueueManager,
     pub worker_pool: Arc<WorkerPool>,
+    pub scheduler: Scheduler,
+    bot: Throttle<Bot>,
     shutdown: broadcast::Sender<()>,
 }
 
 impl RuntimeManager {
-    pub fn new(queue_capacity: usize, bot: Throttle<Bot>) -> Result<Self, RuntimeError> {
+    pub fn new(queue_config: &QueueConfig, bot: Throttle<Bot>) -> Result<Self, RuntimeError> {
         info!("Initializing RuntimeManager...");
         let (shutdown_tx, _) = broadcast::channel(1);
-        let queue_manager = TaskQueueManager::new(queue_capacity);
+        let queue_manager = TaskQueueManager::new(queue_config);
         let mut worker_pool = WorkerPool::new();
+        let scheduler = Scheduler::new(shutdown_tx.clone());
 
         info!("Adding download worker...");
 
-        let concurrency = AppConfig::get()?.runtime.queue.worker_count;
+        let concurrency = queue_config.worker_count;
 
         worker_pool.add_worker(worker::download::DownloadWorker::new(
             "download_worker",
             concurrency,
             queue_manager.clone(),
             bot.clone(),
-            // shutdown_tx.clone(),
+            StageTimeouts::from(queue_config),
         ));
 
         info!("Adding post download worker...");
@@ -49,7 +62,7 @@ impl RuntimeManager {
             concurrency,
             queue_manager.clone(),
             bot.clone(),
-            // shutdown_tx.clone(),
+            StageTimeouts::from(queue_config),
         ));
 
         info!("RuntimeManager initialized");
@@ -57,6 +70,8 @@ impl RuntimeManager {
         Ok(Self {
             queue_manager,
             worker_pool: Arc::new(worker_pool),
+            scheduler,
+            bot,
             shutdown: shutdown_tx,
         })
     }
@@ -66,14 +81,89 @@ impl RuntimeManager {
         Ok(())
     }
 
-    // pub async fn shutdown(&self) -> Result<(), RuntimeError> {
-    //     self.shutdown
-    //         .send(())
-    //         .map_err(|e| RuntimeError::QueueError(e.to_string()))?;
+    /// Cancels everything a user has queued, running or waiting for confirmation, returns how much there was
+    pub async fn cancel_user_work(&self, user_id: u64) -> Result<usize, RuntimeError> {
+        let cancelled = self.queue_manager.cancel_user_tasks(user_id).await?;
+        let confirmations = self.queue_manager.take_user_confirmations(user_id).await?;
+
+        for task in &confirmations {
+            if let Err(e) = self
+                .bot
+                .delete_message(ChatId(task.context.chat_id), MessageId(task.context.message_id))
+                .await
+            {
+                debug!("Failed to delete preview of {}: {}", task.media_file.id, e);
+            }
+        }
+
+        Ok(cancelled + confirmations.len())
+    }
 
-    //     self.worker_pool.stop_all().await?;
-    //     Ok(())
-    // }
+    /// Drops the confirmations nobody answered in time and tells their users, returns how many expired
+    pub async fn sweep_expired_confirmations(&self) -> Result<usize, RuntimeError> {
+        let expired = self.queue_manager.take_expired_confirmations().await?;
+
+        for task in &expired {
+            worker::download::expire_pending_preview(&self.bot, task).await;
+        }
+
+        if !expired.is_empty() {
+            info!("Expired {} pending confirmations", expired.len());
+        }
+
+        Ok(expired.len())
+    }
+
+    /// Stops taking new tasks, gives the running ones until `deadline` to finish and tells the users whose
+    /// queued work is lost because the queue only lives in memory
+    pub async fn shutdown(&self, deadline: Duration) -> Result<(), RuntimeError> {
+        info!("Shutting down runtime...");
+        self.queue_manager.stop_accepting();
+        let _ = self.shutdown.send(());
+
+        match tokio::time::timeout(deadline, self.worker_pool.stop_all()).await {
+            Ok(result) => result?,
+            Err(_) => warn!("Workers did not finish within {:?}, abandoning running tasks", deadline),
+        }
+
+        let (downloads, post_downloads, confirmations) = self.queue_manager.drain_volatile().await?;
+
+        for task in &confirmations {
+            worker::download::expire_pending_preview(&self.bot, task).await;
+        }
+
+        let mut dropped: HashMap<i64, (usize, &'static str)> = HashMap::new(); // chat id -> (tasks, locale)
+        let contexts = downloads
+            .iter()
+            .map(|task| &task.context)
+            .chain(post_downloads.iter().map(|task| &task.context));
+        for context in contexts {
+            dropped
+                .entry(context.chat_id)
+                .or_insert((0, context.language.locale()))
+                .0 += 1;
+        }
+
+        for (chat_id, (count, locale)) in &dropped {
+            if let Err(e) = self
+                .bot
+                .send_message(
+                    ChatId(*chat_id),
+                    t!("messages.shutdown.tasks_dropped", locale = locale, count = count),
+                )
+                .await
+            {
+                debug!("Failed to tell chat {} about dropped tasks: {}", chat_id, e);
+            }
+        }
+
+        info!(
+            "Runtime stopped, dropped {} queued tasks and {} pending confirmations",
+            downloads.len() + post_downloads.len(),
+            confirmations.len()
+        );
+        Ok(())
+    }
 
     // pub fn get_queue_manager(&self) -> Arc<TaskQueueManager> {
     //     Arc::clone(&self.queue_manager)
@@ -83,9 +173,3 @@ impl RuntimeManager {
     //     Arc::clone(&self.worker_pool)
     // }
 }
-
-impl Drop for RuntimeManager {
-    fn drop(&mut self) {
-        let _ = self.shutdown.send(());
-    }
-}
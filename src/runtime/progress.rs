@@ -0,0 +1,194 @@
+use std::{future::Future, time::Duration};
+
+use teloxide::{
+    adaptors::Throttle,
+    payloads::EditMessageTextSetters,
+    prelude::Requester,
+    types::{ChatId, InlineKeyboardMarkup, MessageId},
+    Bot,
+};
+use tokio::{sync::watch, task::JoinHandle};
+
+use super::task::CancellationToken;
+
+/// Minimum time between two edits of a status message, Telegram allows about one message per second
+/// in a chat and `Throttle` delays everything else sent to it
+pub const UPDATE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Where a task is at, shown to the user in a status message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Progress {
+    /// Waiting in the queue, position 1 is next
+    Queued { position: usize },
+    /// Items sent to the chat so far. There is no byte count: media goes out by URL or Telegram file id, so
+    /// Telegram downloads it itself and the bot never sees the bytes.
+    Sending { sent: usize, total: usize },
+}
+
+impl Progress {
+    pub fn message(&self, locale: &str) -> String {
+        match self {
+            Progress::Queued { position } => t!(
+                "callbacks.download.progress.queued",
+                locale = locale,
+                position = position
+            )
+            .to_string(),
+            Progress::Sending { sent, total } => t!(
+                "callbacks.download.progress.sending",
+                locale = locale,
+                sent = sent,
+                total = total
+            )
+            .to_string(),
+        }
+    }
+}
+
+/// Keeps a single status message up to date. Only the latest progress is rendered and edits are spaced
+/// by [`UPDATE_INTERVAL`], so tasks can report as often as they like.
+pub struct ProgressReporter<P> {
+    tx: watch::Sender<Option<P>>,
+    stop: CancellationToken,
+    task: JoinHandle<()>,
+}
+
+impl<P> ProgressReporter<P>
+where
+    P: Send + Sync + 'static,
+{
+    pub fn spawn<F>(
+        bot: Throttle<Bot>,
+        chat_id: ChatId,
+        message_id: MessageId,
+        keyboard: Option<InlineKeyboardMarkup>,
+        render: F,
+    ) -> Self
+    where
+        F: Fn(&P) -> String + Send + 'static,
+    {
+        Self::with_editor(render, move |text| {
+            let mut edit = bot.edit_message_text(chat_id, message_id, text);
+            if let Some(keyboard) = &keyboard {
+                edit = edit.reply_markup(keyboard.clone());
+            }
+
+            async move {
+                if let Err(e) = edit.await {
+                    debug!("Failed to update progress message: {}", e);
+                }
+            }
+        })
+    }
+
+    /// Hands the rendered progress to `edit` instead of editing a Telegram message
+    pub(crate) fn with_editor<F, E, Fut>(render: F, mut edit: E) -> Self
+    where
+        F: Fn(&P) -> String + Send + 'static,
+        E: FnMut(String) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send,
+    {
+        let (tx, mut rx) = watch::channel(None);
+        let stop = CancellationToken::default();
+
+        let task = tokio::spawn({
+            let stop = stop.clone();
+            async move {
+                let mut last_text = None;
+
+                loop {
+                    tokio::select! {
+                        changed = rx.changed() => if changed.is_err() { break },
+                        _ = stop.cancelled() => break,
+                    }
+
+                    let Some(text) = rx.borrow_and_update().as_ref().map(&render) else {
+                        continue;
+                    };
+
+                    // Telegram rejects edits that don't change anything
+                    if last_text.as_ref() != Some(&text) {
+                        edit(text.clone()).await;
+                        last_text = Some(text);
+                    }
+
+                    tokio::select! {
+                        _ = tokio::time::sleep(UPDATE_INTERVAL) => {}
+                        _ = stop.cancelled() => break,
+                    }
+                }
+            }
+        });
+
+        Self { tx, stop, task }
+    }
+
+    pub fn report(&self, progress: P) {
+        self.tx.send_replace(Some(progress));
+    }
+
+    /// Stops updating the message, returns once an edit in flight went out so the caller can replace the
+    /// message without being overwritten
+    pub async fn finish(self) {
+        self.stop.cancel();
+        let _ = self.task.await;
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tokio::time::Instant;
+
+    use super::*;
+
+    /// Edits made by a reporter, with the time since it was created
+    pub(crate) type Edits = Arc<Mutex<Vec<(Duration, String)>>>;
+
+    pub(crate) fn recording_reporter<P, F>(render: F) -> (ProgressReporter<P>, Edits)
+    where
+        P: Send + Sync + 'static,
+        F: Fn(&P) -> String + Send + 'static,
+    {
+        let edits = Edits::default();
+        let start = Instant::now();
+        let reporter = ProgressReporter::with_editor(render, {
+            let edits = edits.clone();
+            move |text| {
+                edits.lock().unwrap().push((start.elapsed(), text));
+                async {}
+            }
+        });
+
+        (reporter, edits)
+    }
+
+    fn edit(millis: u64, text: &str) -> (Duration, String) {
+        (Duration::from_millis(millis), text.to_string())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_edits_are_throttled_and_deduplicated() {
+        let (reporter, edits) = recording_reporter(|sent: &usize| format!("{} sent", sent));
+
+        // The first report shows right away, the latest of those within an interval after it
+        reporter.report(1);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        reporter.report(2);
+        reporter.report(3);
+        tokio::time::sleep(Duration::from_millis(2000)).await;
+
+        // Reporting what is shown already edits nothing, the next change still waits for the interval
+        reporter.report(3);
+        tokio::time::sleep(Duration::from_millis(2500)).await;
+        reporter.report(4);
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+
+        reporter.finish().await;
+        assert_eq!(
+            *edits.lock().unwrap(),
+            vec![edit(0, "1 sent"), edit(2000, "3 sent"), edit(6000, "4 sent")]
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use crate::config::QueueConfig;
+
+use super::priority::Priority;
+
+/// How a backend picks the next task: highest priority after aging first, then the user who was served
+/// longest ago, then the oldest task. Users at their in-flight limit are skipped until a task of theirs
+/// is finished.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FairnessPolicy {
+    /// Tasks a user may have popped and not finished yet, 0 for no limit
+    pub max_in_flight_per_user: usize,
+    /// Waiting this long raises a task by one priority level, zero disables aging
+    pub aging: Duration,
+}
+
+impl From<&QueueConfig> for FairnessPolicy {
+    fn from(config: &QueueConfig) -> Self {
+        Self {
+            max_in_flight_per_user: config.max_in_flight_per_user,
+            aging: Duration::from_secs(config.aging_secs),
+        }
+    }
+}
+
+impl FairnessPolicy {
+    /// Whether a user with `in_flight` unfinished tasks may be handed another one
+    pub fn allows(&self, in_flight: usize) -> bool {
+        self.max_in_flight_per_user == 0 || in_flight < self.max_in_flight_per_user
+    }
+
+    /// The priority a task competes with once it waited since `enqueued_at`
+    pub fn effective_priority(&self, priority: Priority, enqueued_at: DateTime<Utc>, now: DateTime<Utc>) -> Priority {
+        if self.aging.is_zero() {
+            return priority;
+        }
+
+        let waited = (now - enqueued_at).to_std().unwrap_or_default();
+        let levels = waited.as_millis() / self.aging.as_millis();
+        Priority::from_rank(priority.rank().saturating_sub(levels.min(u8::MAX as u128) as u8))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aging_raises_priority_one_level_per_period() {
+        let policy = FairnessPolicy {
+            max_in_flight_per_user: 2,
+            aging: Duration::from_secs(60),
+        };
+        let now = Utc::now();
+
+        assert_eq!(policy.effective_priority(Priority::Low, now, now), Priority::Low);
+        assert_eq!(
+            policy.effective_priority(Priority::Low, now - chrono::Duration::seconds(61), now),
+            Priority::Normal
+        );
+        assert_eq!(
+            policy.effective_priority(Priority::Low, now - chrono::Duration::hours(1), now),
+            Priority::High
+        );
+        assert_eq!(
+            FairnessPolicy::default().effective_priority(Priority::Low, now - chrono::Duration::hours(1), now),
+            Priority::Low
+        );
+
+        assert!(policy.allows(1));
+        assert!(!policy.allows(2));
+        assert!(FairnessPolicy::default().allows(100));
+    }
+}
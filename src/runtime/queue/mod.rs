@@ -1,76 +1,786 @@
+mod fairness;
+mod pending;
 pub mod priority;
+mod redis;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use dashmap::{mapref::entry::Entry, DashMap};
+use fairness::FairnessPolicy;
+use pending::PendingConfirmations;
+use priority::{Priority, PriorityQueue};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::oneshot;
 
-use dashmap::DashMap;
-use priority::PriorityQueue;
-use std::sync::Arc;
-
-use crate::platform::{DownloadState, MediaFile, PostDownloadState};
+use crate::{
+    config::{QueueBackendKind, QueueConfig},
+    platform::{DownloadState, MediaFile, PostDownloadState},
+};
 
 use super::{
-    task::{DownloadTask, PostDownloadTask, TaskWithResult},
+    progress::{Progress, ProgressReporter, UPDATE_INTERVAL},
+    task::{CancellationToken, Cancellations, DeadLetter, DownloadTask, PostDownloadTask, Task},
     RuntimeError, TaskContext,
 };
 
+/// Where queued tasks are stored, popped tasks stay owned by the backend until acknowledged
+#[async_trait]
+pub trait QueueBackend<T: Task>: Send + Sync {
+    async fn push(&self, task: T, priority: Priority) -> Result<(), RuntimeError>;
+    /// Waits until a task is available, cancel safe so workers can race it against their shutdown signal
+    async fn pop(&self) -> T;
+    /// Marks a popped task as done so it is never delivered again
+    async fn ack(&self, task_id: &str) -> Result<(), RuntimeError>;
+    /// Hands a popped task back to the queue, it is delivered again once `delay` passed
+    async fn nack(&self, task: T, priority: Priority, delay: Duration) -> Result<(), RuntimeError>;
+    /// Takes a task out of the queue before it is popped, `None` if it is not waiting anymore
+    async fn remove(&self, task_id: &str) -> Result<Option<T>, RuntimeError>;
+    async fn len(&self) -> Result<usize, RuntimeError>;
+    /// How many tasks are served before this one plus one, `None` if it is not queued. An estimate, the
+    /// fairness policy may let tasks queued later overtake it.
+    async fn position(&self, task_id: &str) -> Result<Option<usize>, RuntimeError>;
+    fn capacity(&self) -> usize;
+    /// Moves a popped task to the dead letters, it is not delivered again unless requeued
+    async fn dead_letter(&self, task: T, letter: DeadLetter) -> Result<(), RuntimeError>;
+    async fn dead_letters(&self) -> Result<Vec<DeadLetter>, RuntimeError>;
+    async fn take_dead_letter(&self, task_id: &str) -> Result<Option<T>, RuntimeError>;
+    /// Takes out the queued tasks that would be lost when the process exits, durable backends keep theirs
+    async fn drain_volatile(&self) -> Result<Vec<T>, RuntimeError>;
+}
+
+/// A queue backend plus the channels of the callers waiting for results in this process
+pub struct TaskQueue<T: Task> {
+    backend: Arc<dyn QueueBackend<T>>,
+    waiters: DashMap<String, oneshot::Sender<T::Result>>, // task id -> result
+}
+
+impl<T: Task> TaskQueue<T> {
+    pub fn new(backend: Arc<dyn QueueBackend<T>>) -> Self {
+        Self {
+            backend,
+            waiters: DashMap::new(),
+        }
+    }
+
+    pub async fn push(&self, task: T, priority: Priority) -> Result<oneshot::Receiver<T::Result>, RuntimeError> {
+        let task_id = task.id().to_string();
+        let (tx, rx) = oneshot::channel();
+        self.waiters.insert(task_id.clone(), tx);
+
+        if let Err(e) = self.backend.push(task, priority).await {
+            self.waiters.remove(&task_id);
+            return Err(e);
+        }
+
+        Ok(rx)
+    }
+
+    pub async fn pop(&self) -> T {
+        self.backend.pop().await
+    }
+
+    /// Acknowledges a processed task and hands its result to whoever is waiting, nobody is when the task
+    /// was redelivered after a restart
+    pub async fn complete(&self, task_id: &str, result: T::Result) {
+        if let Err(e) = self.backend.ack(task_id).await {
+            error!("Failed to acknowledge task {}: {}", task_id, e);
+        }
+
+        match self.waiters.remove(task_id) {
+            Some((_, tx)) => {
+                if tx.send(result).is_err() {
+                    error!("Failed to send result of task {}", task_id);
+                }
+            }
+            None => info!("Task {} completed without a waiting caller", task_id),
+        }
+    }
+
+    /// Schedules a retry of a failed task while its policy allows it, otherwise moves it to the dead
+    /// letters and hands it back so the caller can report the failure
+    pub async fn fail(&self, mut task: T, priority: Priority, error: &RuntimeError) -> Option<T>
+    where
+        T: Clone,
+    {
+        task.record_failure();
+        let policy = task.retry_policy();
+
+        if error.is_retryable() && policy.should_retry(task.attempts()) {
+            let delay = policy.backoff(task.attempts());
+            warn!(
+                "Task {} failed on attempt {}/{}, retrying in {:?}: {}",
+                task.id(),
+                task.attempts(),
+                policy.max_attempts,
+                delay,
+                error
+            );
+
+            match self.backend.nack(task.clone(), priority, delay).await {
+                Ok(()) => return None,
+                Err(e) => error!("Failed to schedule retry of task {}: {}", task.id(), e),
+            }
+        }
+
+        let letter = DeadLetter {
+            task_id: task.id().to_string(),
+            summary: task.summary(),
+            error: error.to_string(),
+            attempts: task.attempts(),
+            failed_at: Utc::now(),
+        };
+
+        if let Err(e) = self.backend.dead_letter(task.clone(), letter).await {
+            error!("Failed to store dead letter {}: {}", task.id(), e);
+        }
+
+        Some(task)
+    }
+
+    pub async fn dead_letters(&self) -> Result<Vec<DeadLetter>, RuntimeError> {
+        self.backend.dead_letters().await
+    }
+
+    /// Drops a task that was not popped yet and hands `result` to whoever is waiting, `false` if the task
+    /// is not waiting anymore
+    pub async fn remove(&self, task_id: &str, result: T::Result) -> Result<bool, RuntimeError> {
+        if self.backend.remove(task_id).await?.is_none() {
+            return Ok(false);
+        }
+
+        if let Some((_, tx)) = self.waiters.remove(task_id) {
+            let _ = tx.send(result);
+        }
+
+        Ok(true)
+    }
+
+    /// Puts a dead letter back in the queue with a fresh set of attempts, `false` if there is no such task
+    pub async fn requeue_dead_letter(
+        &self,
+        task_id: &str,
+        priority: impl Fn(&T) -> Priority,
+    ) -> Result<bool, RuntimeError> {
+        let Some(mut task) = self.backend.take_dead_letter(task_id).await? else {
+            return Ok(false);
+        };
+
+        task.reset_attempts();
+        let priority = priority(&task);
+        self.backend.push(task, priority).await?;
+
+        Ok(true)
+    }
+
+    pub async fn position(&self, task_id: &str) -> Result<Option<usize>, RuntimeError> {
+        self.backend.position(task_id).await
+    }
+
+    /// Takes out the tasks that won't survive a restart, see [`QueueBackend::drain_volatile`]. Their callers
+    /// stop waiting without a result.
+    pub async fn drain_volatile(&self) -> Result<Vec<T>, RuntimeError> {
+        let tasks = self.backend.drain_volatile().await?;
+        for task in &tasks {
+            self.waiters.remove(task.id());
+        }
+        Ok(tasks)
+    }
+
+    pub async fn len(&self) -> usize {
+        self.backend.len().await.unwrap_or_else(|e| {
+            error!("Failed to get queue length: {}", e);
+            0
+        })
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.backend.capacity()
+    }
+}
+
 #[derive(Clone)]
 pub struct TaskQueueManager {
-    download_queue: Arc<PriorityQueue<DownloadTask>>,
-    post_download_queue: Arc<PriorityQueue<PostDownloadTask>>,
-    pending_confirmations: Arc<DashMap<String, PostDownloadTask>>, // identifier -> task
+    download_queue: Arc<TaskQueue<DownloadTask>>,
+    post_download_queue: Arc<TaskQueue<PostDownloadTask>>,
+    pending_confirmations: PendingConfirmations,
+    cancellations: Cancellations,
+    position_watchers: Arc<DashMap<String, (CancellationToken, oneshot::Receiver<()>)>>, // task id -> (stop, stopped)
+    queued_resources: Arc<DashMap<(u64, String), String>>, // (user id, resource id) -> task id
+    /// Cleared on shutdown so no new work is queued while the workers drain
+    accepting: Arc<AtomicBool>,
 }
 
 impl TaskQueueManager {
-    pub fn new(capacity: usize) -> Self {
-        Self {
-            download_queue: Arc::new(PriorityQueue::new(capacity)),
-            post_download_queue: Arc::new(PriorityQueue::new(capacity)),
-            pending_confirmations: Arc::new(DashMap::new()), // TODO add pending capacity
+    pub fn new(config: &QueueConfig) -> Self {
+        let visibility_timeout = Duration::from_secs(config.visibility_timeout_secs);
+        let confirmation_ttl = Duration::from_secs(config.confirmation_ttl_secs);
+        let fairness = FairnessPolicy::from(config);
+
+        match config.backend {
+            QueueBackendKind::Memory => Self {
+                download_queue: Arc::new(TaskQueue::new(Arc::new(
+                    PriorityQueue::new(config.capacity).with_fairness(fairness),
+                ))),
+                post_download_queue: Arc::new(TaskQueue::new(Arc::new(
+                    PriorityQueue::new(config.capacity).with_fairness(fairness),
+                ))),
+                pending_confirmations: PendingConfirmations::memory(confirmation_ttl, config.confirmation_capacity),
+                cancellations: Cancellations::default(),
+                position_watchers: Arc::new(DashMap::new()),
+                queued_resources: Arc::new(DashMap::new()),
+                accepting: Arc::new(AtomicBool::new(true)),
+            },
+            QueueBackendKind::Redis => Self {
+                download_queue: Arc::new(TaskQueue::new(Arc::new(
+                    redis::RedisQueue::new("download", config.capacity, visibility_timeout).with_fairness(fairness),
+                ))),
+                post_download_queue: Arc::new(TaskQueue::new(Arc::new(
+                    redis::RedisQueue::new("post_download", config.capacity, visibility_timeout)
+                        .with_fairness(fairness),
+                ))),
+                pending_confirmations: PendingConfirmations::redis(confirmation_ttl, config.confirmation_capacity),
+                cancellations: Cancellations::default(),
+                position_watchers: Arc::new(DashMap::new()),
+                queued_resources: Arc::new(DashMap::new()),
+                accepting: Arc::new(AtomicBool::new(true)),
+            },
         }
     }
 
-    pub async fn get_task_by_identifier(&self, identifier: &str) -> Option<PostDownloadTask> {
-        self.pending_confirmations.remove(identifier).map(|(_, task)| task)
+    /// Takes the preview a user is confirming, `None` once it expired or was already confirmed
+    pub async fn get_task_by_identifier(
+        &self,
+        user_id: u64,
+        identifier: &str,
+    ) -> Result<Option<PostDownloadTask>, RuntimeError> {
+        self.pending_confirmations
+            .take(&PendingConfirmations::key(user_id, identifier))
+            .await
     }
 
-    pub async fn push_download_task(&self, task: DownloadTask) -> Result<DownloadState, RuntimeError> {
+    /// Enqueues a download task, the receiver resolves once it is processed. Fails with
+    /// [`RuntimeError::DuplicateTask`] while the user has a task for the same resource queued or running.
+    pub async fn enqueue_download_task(
+        &self,
+        task: DownloadTask,
+    ) -> Result<oneshot::Receiver<DownloadState>, RuntimeError> {
+        if !self.accepting.load(Ordering::SeqCst) {
+            return Err(RuntimeError::ShuttingDown);
+        }
+
+        let resource = task
+            .resource_id
+            .clone()
+            .map(|resource_id| (task.context.user_id, resource_id));
+
+        if let Some(resource) = &resource {
+            match self.queued_resources.entry(resource.clone()) {
+                Entry::Occupied(entry) => return Err(RuntimeError::DuplicateTask(entry.get().clone())),
+                Entry::Vacant(entry) => {
+                    entry.insert(task.id.clone());
+                }
+            }
+        }
+
+        let task_id = task.id.clone();
         let priority = task.context.user_tier.into();
-        let rx = self.download_queue.push(task, priority).await?;
-        rx.await.map_err(|e| RuntimeError::RecvError(e.to_string()))
+        self.cancellations.token(&task_id, task.context.user_id);
+
+        let rx = self.download_queue.push(task, priority).await;
+        if rx.is_err() {
+            self.cancellations.remove(&task_id);
+            if let Some(resource) = &resource {
+                self.queued_resources.remove(resource);
+            }
+        }
+        rx
     }
 
-    async fn push_post_download_task(&self, task: PostDownloadTask) -> Result<PostDownloadState, RuntimeError> {
-        let priority = task.context.user_tier.into();
-        let rx = self.post_download_queue.push(task, priority).await?;
-        rx.await.map_err(|e| RuntimeError::RecvError(e.to_string()))
+    /// Lets the user queue the resource of a finished or dropped task again
+    fn release_resource(&self, task_id: &str) {
+        self.queued_resources.retain(|_, queued| queued != task_id);
     }
 
-    pub async fn handle_download_confirmation(&self, identifier: &str) -> Result<PostDownloadState, RuntimeError> {
-        if let Some(task) = self.get_task_by_identifier(identifier).await {
-            let post_task = PostDownloadTask::new(task.media_file, task.context);
+    async fn push_post_download_task(
+        &self,
+        task: PostDownloadTask,
+    ) -> Result<oneshot::Receiver<PostDownloadState>, RuntimeError> {
+        if !self.accepting.load(Ordering::SeqCst) {
+            return Err(RuntimeError::ShuttingDown);
+        }
 
-            let state = self.push_post_download_task(post_task).await?;
-            Ok(state)
-        } else {
-            Err(RuntimeError::QueueError("task not found".to_string()))
+        let priority = task.context.user_tier.into();
+        self.cancellations.token(&task.id, task.context.user_id);
+        self.post_download_queue.push(task, priority).await
+    }
+
+    /// Queues the delivery of a confirmed preview, `None` if the preview expired meanwhile
+    pub async fn handle_download_confirmation(
+        &self,
+        user_id: u64,
+        identifier: &str,
+    ) -> Result<Option<oneshot::Receiver<PostDownloadState>>, RuntimeError> {
+        match self.get_task_by_identifier(user_id, identifier).await? {
+            Some(task) => {
+                let post_task = PostDownloadTask::new(task.media_file, task.context);
+                Ok(Some(self.push_post_download_task(post_task).await?))
+            }
+            None => Ok(None),
         }
     }
 
-    pub async fn pop_download_task(&self) -> Option<TaskWithResult<DownloadTask>> {
+    /// Waits for the next download task
+    pub async fn pop_download_task(&self) -> DownloadTask {
         self.download_queue.pop().await
     }
 
-    pub async fn pop_post_download_task(&self) -> Option<TaskWithResult<PostDownloadTask>> {
+    /// Waits for the next post download task
+    pub async fn pop_post_download_task(&self) -> PostDownloadTask {
         self.post_download_queue.pop().await
     }
 
-    pub fn add_pending_confirmation(&self, media_file: MediaFile, context: TaskContext) {
-        let post_task = PostDownloadTask::new(media_file.clone(), context);
-        self.pending_confirmations.insert(media_file.id, post_task);
+    pub async fn complete_download_task(&self, task_id: &str, result: DownloadState) {
+        self.cancellations.remove(task_id);
+        self.release_resource(task_id);
+        self.download_queue.complete(task_id, result).await
+    }
+
+    pub async fn complete_post_download_task(&self, task_id: &str, result: PostDownloadState) {
+        self.cancellations.remove(task_id);
+        self.post_download_queue.complete(task_id, result).await
+    }
+
+    /// The token a worker checks while processing a task
+    pub fn cancellation_token(&self, task_id: &str, user_id: u64) -> CancellationToken {
+        self.cancellations.token(task_id, user_id)
+    }
+
+    /// Shows the queue position of a download in its status message until a worker picks it up
+    pub fn watch_download_position(&self, task_id: &str, reporter: ProgressReporter<Progress>) {
+        let stop = CancellationToken::default();
+        let (stopped_tx, stopped_rx) = oneshot::channel();
+        self.position_watchers
+            .insert(task_id.to_string(), (stop.clone(), stopped_rx));
+
+        let manager = self.clone();
+        let task_id = task_id.to_string();
+
+        tokio::spawn(async move {
+            loop {
+                match manager.download_queue.position(&task_id).await {
+                    Ok(Some(position)) if !stop.is_cancelled() => reporter.report(Progress::Queued { position }),
+                    Ok(_) => break,
+                    Err(e) => debug!("Failed to get queue position of task {}: {}", task_id, e),
+                }
+
+                tokio::select! {
+                    _ = tokio::time::sleep(UPDATE_INTERVAL) => {}
+                    _ = stop.cancelled() => break,
+                }
+            }
+
+            reporter.finish().await;
+            manager.position_watchers.remove(&task_id);
+            let _ = stopped_tx.send(());
+        });
+    }
+
+    /// Stops showing the queue position of a task, returns once its status message is not edited anymore
+    pub async fn stop_watching_position(&self, task_id: &str) {
+        if let Some((_, (stop, stopped))) = self.position_watchers.remove(task_id) {
+            stop.cancel();
+            let _ = stopped.await;
+        }
+    }
+
+    /// Cancels one of the user's tasks, queued ones are dropped right away and running ones stop at their
+    /// next checkpoint. `false` if there is no such task.
+    pub async fn cancel_task(&self, user_id: u64, task_id: &str) -> Result<bool, RuntimeError> {
+        if !self.cancellations.cancel(task_id, user_id) {
+            return Ok(false);
+        }
+
+        self.stop_watching_position(task_id).await;
+
+        let removed = self.download_queue.remove(task_id, DownloadState::Cancelled).await?
+            || self
+                .post_download_queue
+                .remove(task_id, PostDownloadState::Cancelled)
+                .await?;
+
+        if removed {
+            self.cancellations.remove(task_id);
+            self.release_resource(task_id);
+        }
+
+        Ok(true)
+    }
+
+    /// Cancels every task of a user, returning how many there were
+    pub async fn cancel_user_tasks(&self, user_id: u64) -> Result<usize, RuntimeError> {
+        let task_ids = self.cancellations.cancel_user(user_id);
+
+        for task_id in &task_ids {
+            self.cancel_task(user_id, task_id).await?;
+        }
+
+        Ok(task_ids.len())
+    }
+
+    /// Drops the preview the user cancelled, `false` if it already expired
+    pub async fn cancel_pending_confirmation(&self, user_id: u64, identifier: &str) -> Result<bool, RuntimeError> {
+        Ok(self
+            .pending_confirmations
+            .take(&PendingConfirmations::key(user_id, identifier))
+            .await?
+            .is_some())
+    }
+
+    /// Drops every preview of a user and returns them, so their messages can be cleaned up
+    pub async fn take_user_confirmations(&self, user_id: u64) -> Result<Vec<PostDownloadTask>, RuntimeError> {
+        self.pending_confirmations.take_user(user_id).await
+    }
+
+    /// Retries or dead-letters a failed download task, see [`TaskQueue::fail`]
+    pub async fn fail_download_task(&self, task: DownloadTask, error: &RuntimeError) -> Option<DownloadTask> {
+        let priority = task.context.user_tier.into();
+        self.download_queue.fail(task, priority, error).await
+    }
+
+    pub async fn fail_post_download_task(
+        &self,
+        task: PostDownloadTask,
+        error: &RuntimeError,
+    ) -> Option<PostDownloadTask> {
+        let priority = task.context.user_tier.into();
+        self.post_download_queue.fail(task, priority, error).await
+    }
+
+    /// Dead letters of both queues, most recent first
+    pub async fn dead_letters(&self) -> Result<Vec<DeadLetter>, RuntimeError> {
+        let mut dead_letters = self.download_queue.dead_letters().await?;
+        dead_letters.extend(self.post_download_queue.dead_letters().await?);
+        dead_letters.sort_by_key(|letter| std::cmp::Reverse(letter.failed_at));
+        Ok(dead_letters)
+    }
+
+    pub async fn requeue_dead_letter(&self, task_id: &str) -> Result<bool, RuntimeError> {
+        let priority = |context: &TaskContext| Priority::from(context.user_tier);
+
+        if self
+            .download_queue
+            .requeue_dead_letter(task_id, |task| priority(&task.context))
+            .await?
+        {
+            return Ok(true);
+        }
+
+        self.post_download_queue
+            .requeue_dead_letter(task_id, |task| priority(&task.context))
+            .await
+    }
+
+    /// Refuses new tasks from now on, already queued ones are still handed to the workers
+    pub fn stop_accepting(&self) {
+        self.accepting.store(false, Ordering::SeqCst);
+    }
+
+    /// Takes out the queued tasks and confirmations that would be lost on shutdown, nothing with a durable
+    /// backend
+    pub async fn drain_volatile(
+        &self,
+    ) -> Result<(Vec<DownloadTask>, Vec<PostDownloadTask>, Vec<PostDownloadTask>), RuntimeError> {
+        let downloads = self.download_queue.drain_volatile().await?;
+        let post_downloads = self.post_download_queue.drain_volatile().await?;
+        let confirmations = self.pending_confirmations.drain_volatile().await?;
+
+        for task_id in downloads
+            .iter()
+            .map(|task| &task.id)
+            .chain(post_downloads.iter().map(|task| &task.id))
+        {
+            self.stop_watching_position(task_id).await;
+            self.cancellations.remove(task_id);
+            self.release_resource(task_id);
+        }
+
+        Ok((downloads, post_downloads, confirmations))
+    }
+
+    /// Queued and maximum number of tasks, for the download and post download queues
+    pub async fn queue_stats(&self) -> [(usize, usize); 2] {
+        [
+            (self.download_queue.len().await, self.download_queue.capacity()),
+            (
+                self.post_download_queue.len().await,
+                self.post_download_queue.capacity(),
+            ),
+        ]
+    }
+
+    /// Stores a preview until the user confirms it, returning the previews evicted to make room
+    pub async fn add_pending_confirmation(
+        &self,
+        media_file: MediaFile,
+        context: TaskContext,
+    ) -> Result<Vec<PostDownloadTask>, RuntimeError> {
+        let key = PendingConfirmations::key(context.user_id, &media_file.id);
+        let post_task = PostDownloadTask::new(media_file, context);
+        self.pending_confirmations.insert(&key, post_task).await
+    }
+
+    pub async fn update_pending_confirmation_context(
+        &self,
+        media_file_id: String,
+        context: TaskContext,
+    ) -> Result<(), RuntimeError> {
+        let key = PendingConfirmations::key(context.user_id, &media_file_id);
+        self.pending_confirmations.update_context(&key, context).await
+    }
+
+    /// Removes the previews nobody confirmed in time
+    pub async fn take_expired_confirmations(&self) -> Result<Vec<PostDownloadTask>, RuntimeError> {
+        self.pending_confirmations.take_expired().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        platform::FailureReason,
+        runtime::{progress::tests::recording_reporter, task::RetryPolicy},
+    };
+
+    #[derive(Debug, Clone)]
+    struct TestTask {
+        id: String,
+        attempts: u32,
+    }
+
+    impl TestTask {
+        fn new(id: &str) -> Self {
+            Self {
+                id: id.to_string(),
+                attempts: 0,
+            }
+        }
     }
 
-    pub fn update_pending_confirmation_context(&self, media_file_id: String, context: TaskContext) {
-        if let Some(mut post_task) = self.pending_confirmations.get_mut(&media_file_id) {
-            post_task.context = context;
+    impl Task for TestTask {
+        type Result = String;
+
+        fn id(&self) -> &str {
+            &self.id
+        }
+
+        fn user_id(&self) -> u64 {
+            0
+        }
+
+        fn attempts(&self) -> u32 {
+            self.attempts
+        }
+
+        fn record_failure(&mut self) {
+            self.attempts += 1;
         }
+
+        fn reset_attempts(&mut self) {
+            self.attempts = 0;
+        }
+
+        fn summary(&self) -> String {
+            format!("test {}", self.id)
+        }
+
+        fn retry_policy(&self) -> RetryPolicy {
+            RetryPolicy {
+                max_attempts: 2,
+                base_delay: Duration::from_millis(10),
+                max_delay: Duration::from_millis(10),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_result_is_delivered_on_complete() {
+        let queue = TaskQueue::new(Arc::new(PriorityQueue::<TestTask>::new(2)));
+
+        let rx = queue.push(TestTask::new("a"), Priority::Normal).await.unwrap();
+        assert_eq!(queue.len().await, 1);
+
+        let task = queue.pop().await;
+        queue.complete(&task.id, format!("done {}", task.id)).await;
+        assert_eq!(rx.await.unwrap(), "done a");
+
+        // A task redelivered without a waiting caller is still acknowledged
+        queue.complete("unknown", "ignored".to_string()).await;
+
+        // A push rejected by the backend leaves no waiter behind
+        queue.push(TestTask::new("b"), Priority::Low).await.unwrap();
+        queue.push(TestTask::new("c"), Priority::Low).await.unwrap();
+        assert!(queue.push(TestTask::new("d"), Priority::Low).await.is_err());
+        assert!(!queue.waiters.contains_key("d"));
+    }
+
+    #[tokio::test]
+    async fn test_failed_task_is_retried_then_dead_lettered() {
+        let queue = TaskQueue::new(Arc::new(PriorityQueue::<TestTask>::new(2)));
+        let transient = RuntimeError::TaskError("timeout".to_string());
+
+        let rx = queue.push(TestTask::new("a"), Priority::Normal).await.unwrap();
+
+        // First failure is retried after the backoff
+        let task = queue.pop().await;
+        assert!(queue.fail(task, Priority::Normal, &transient).await.is_none());
+        assert_eq!(queue.len().await, 0);
+
+        let task = tokio::time::timeout(Duration::from_secs(1), queue.pop()).await.unwrap();
+        assert_eq!(task.attempts, 1);
+
+        // The policy allows two attempts
+        let task = queue.fail(task, Priority::Normal, &transient).await.unwrap();
+        queue.complete(&task.id, "failed".to_string()).await;
+        assert_eq!(rx.await.unwrap(), "failed");
+
+        let dead_letters = queue.dead_letters().await.unwrap();
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(dead_letters[0].summary, "test a");
+        assert_eq!(dead_letters[0].attempts, 2);
+        assert!(dead_letters[0].error.contains("timeout"));
+
+        // Fatal errors skip the retries
+        queue.push(TestTask::new("b"), Priority::Normal).await.unwrap();
+        let task = queue.pop().await;
+        let fatal = RuntimeError::DownloadFailed(FailureReason::PrivateAccount);
+        assert!(queue.fail(task, Priority::Normal, &fatal).await.is_some());
+
+        assert!(queue.requeue_dead_letter("a", |_| Priority::High).await.unwrap());
+        assert!(!queue.requeue_dead_letter("a", |_| Priority::High).await.unwrap());
+        assert_eq!(queue.dead_letters().await.unwrap().len(), 1);
+
+        let task = queue.pop().await;
+        assert_eq!(task.id, "a");
+        assert_eq!(task.attempts, 0);
+    }
+
+    #[tokio::test]
+    async fn test_queue_position() {
+        let queue = TaskQueue::new(Arc::new(PriorityQueue::<TestTask>::new(3)));
+
+        queue.push(TestTask::new("a"), Priority::Low).await.unwrap();
+        queue.push(TestTask::new("b"), Priority::Low).await.unwrap();
+        queue.push(TestTask::new("c"), Priority::High).await.unwrap();
+
+        assert_eq!(queue.position("c").await.unwrap(), Some(1));
+        assert_eq!(queue.position("b").await.unwrap(), Some(3));
+
+        queue.pop().await;
+        queue.pop().await;
+        assert_eq!(queue.position("a").await.unwrap(), None);
+        assert_eq!(queue.position("b").await.unwrap(), Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_removed_task_is_never_popped() {
+        let queue = TaskQueue::new(Arc::new(PriorityQueue::<TestTask>::new(3)));
+
+        let removed = queue.push(TestTask::new("a"), Priority::High).await.unwrap();
+        queue.push(TestTask::new("b"), Priority::Low).await.unwrap();
+
+        assert!(queue.remove("a", "cancelled".to_string()).await.unwrap());
+        assert_eq!(removed.await.unwrap(), "cancelled");
+        assert!(!queue.remove("a", "cancelled".to_string()).await.unwrap());
+
+        // Retries waiting out their backoff can be removed too
+        let task = queue.pop().await;
+        assert_eq!(task.id, "b");
+        let transient = RuntimeError::TaskError("timeout".to_string());
+        assert!(queue.fail(task, Priority::Low, &transient).await.is_none());
+        assert!(queue.remove("b", "cancelled".to_string()).await.unwrap());
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(tokio::time::timeout(Duration::from_millis(50), queue.pop())
+            .await
+            .is_err());
+    }
+
+    fn download_task(user_id: u64) -> DownloadTask {
+        let context = TaskContext {
+            user_id,
+            chat_id: user_id as i64,
+            message_id: 1,
+            user_tier: Default::default(),
+            platform: crate::platform::Platform::Instagram,
+            language: Default::default(),
+        };
+
+        DownloadTask::new("https://www.instagram.com/p/post/".to_string(), context)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_queue_position_is_shown_until_the_task_is_picked_up() {
+        let manager = TaskQueueManager::new(&QueueConfig {
+            capacity: 10,
+            worker_count: 1,
+            backend: QueueBackendKind::Memory,
+            visibility_timeout_secs: 900,
+            confirmation_ttl_secs: 3600,
+            confirmation_capacity: 10,
+            max_in_flight_per_user: 0,
+            aging_secs: 0,
+            fetch_timeout_secs: 120,
+            send_timeout_secs: 300,
+            shutdown_timeout_secs: 30,
+        });
+        let render = |progress: &Progress| match progress {
+            Progress::Queued { position } => format!("queued {}", position),
+            Progress::Sending { .. } => unreachable!(),
+        };
+
+        let first = download_task(1);
+        let second = download_task(2);
+        let second_id = second.id.clone();
+        let _first_rx = manager.enqueue_download_task(first).await.unwrap();
+        let _second_rx = manager.enqueue_download_task(second).await.unwrap();
+
+        let (reporter, edits) = recording_reporter(render);
+        manager.watch_download_position(&second_id, reporter);
+
+        // Moving up is shown at the next check, a worker picking the task up ends the watch
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        manager.pop_download_task().await;
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        manager.pop_download_task().await;
+        tokio::time::sleep(Duration::from_millis(5000)).await;
+
+        assert_eq!(
+            *edits.lock().unwrap(),
+            vec![
+                (Duration::ZERO, "queued 2".to_string()),
+                (Duration::from_millis(2000), "queued 1".to_string()),
+            ]
+        );
+        assert!(manager.position_watchers.is_empty());
+
+        // Stopping the watch leaves the status message to the worker
+        let third = download_task(3);
+        let third_id = third.id.clone();
+        let _third_rx = manager.enqueue_download_task(third).await.unwrap();
+        let (reporter, edits) = recording_reporter(render);
+        manager.watch_download_position(&third_id, reporter);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        manager.stop_watching_position(&third_id).await;
+        tokio::time::sleep(Duration::from_millis(5000)).await;
+        assert_eq!(*edits.lock().unwrap(), vec![(Duration::ZERO, "queued 1".to_string())]);
     }
 }
 
@@ -0,0 +1,456 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use redis::aio::MultiplexedConnection;
+
+use crate::{
+    runtime::{task::PostDownloadTask, RuntimeError, TaskContext},
+    storage::RedisClient,
+};
+
+const HASH_KEY: &str = "queue:pending_confirmations";
+const EXPIRY_KEY: &str = "queue:pending_confirmations:expiry";
+
+/// Makes room by evicting the confirmations closest to expiry, then stores the new one
+const INSERT_SCRIPT: &str = r#"
+local evicted = {}
+if redis.call('HEXISTS', KEYS[1], ARGV[1]) == 0 then
+    local overflow = redis.call('ZCARD', KEYS[2]) - tonumber(ARGV[4]) + 1
+    if overflow > 0 then
+        for _, key in ipairs(redis.call('ZRANGE', KEYS[2], 0, overflow - 1)) do
+            local payload = redis.call('HGET', KEYS[1], key)
+            if payload then
+                table.insert(evicted, payload)
+            end
+            redis.call('HDEL', KEYS[1], key)
+            redis.call('ZREM', KEYS[2], key)
+        end
+    end
+end
+redis.call('HSET', KEYS[1], ARGV[1], ARGV[2])
+redis.call('ZADD', KEYS[2], ARGV[3], ARGV[1])
+return evicted
+"#;
+
+const TAKE_SCRIPT: &str = r#"
+local payload = redis.call('HGET', KEYS[1], ARGV[1])
+local expires_at = redis.call('ZSCORE', KEYS[2], ARGV[1])
+redis.call('HDEL', KEYS[1], ARGV[1])
+redis.call('ZREM', KEYS[2], ARGV[1])
+if payload and expires_at and tonumber(expires_at) > tonumber(ARGV[2]) then
+    return payload
+end
+return false
+"#;
+
+/// Only replaces a confirmation that is still there, one consumed in the meantime stays consumed
+const UPDATE_SCRIPT: &str = r#"
+if redis.call('HEXISTS', KEYS[1], ARGV[1]) == 1 then
+    redis.call('HSET', KEYS[1], ARGV[1], ARGV[2])
+end
+return 1
+"#;
+
+const TAKE_EXPIRED_SCRIPT: &str = r#"
+local expired = {}
+for _, key in ipairs(redis.call('ZRANGEBYSCORE', KEYS[2], '-inf', ARGV[1])) do
+    local payload = redis.call('HGET', KEYS[1], key)
+    if payload then
+        table.insert(expired, payload)
+    end
+    redis.call('HDEL', KEYS[1], key)
+    redis.call('ZREM', KEYS[2], key)
+end
+return expired
+"#;
+
+/// Removes every confirmation whose key starts with the given prefix, bounded by the store capacity
+const TAKE_PREFIX_SCRIPT: &str = r#"
+local taken = {}
+for _, key in ipairs(redis.call('ZRANGE', KEYS[2], 0, -1)) do
+    if string.sub(key, 1, string.len(ARGV[1])) == ARGV[1] then
+        local payload = redis.call('HGET', KEYS[1], key)
+        if payload then
+            table.insert(taken, payload)
+        end
+        redis.call('HDEL', KEYS[1], key)
+        redis.call('ZREM', KEYS[2], key)
+    end
+end
+return taken
+"#;
+
+#[derive(Clone)]
+enum Store {
+    Memory(Arc<DashMap<String, (DateTime<Utc>, PostDownloadTask)>>), // key -> (expires at, task)
+    Redis,
+}
+
+/// Previews waiting for the user to press "Confirm", keyed per user and media so that users previewing the
+/// same post don't overwrite each other. Stored next to the queued tasks so they survive a restart when
+/// the queue does.
+#[derive(Clone)]
+pub struct PendingConfirmations {
+    store: Store,
+    ttl: Duration,
+    capacity: usize,
+}
+
+impl PendingConfirmations {
+    pub fn memory(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            store: Store::Memory(Arc::new(DashMap::new())),
+            ttl,
+            capacity,
+        }
+    }
+
+    pub fn redis(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            store: Store::Redis,
+            ttl,
+            capacity,
+        }
+    }
+
+    pub fn key(user_id: u64, media_id: &str) -> String {
+        format!("{}:{}", user_id, media_id)
+    }
+
+    async fn connection() -> Result<MultiplexedConnection, RuntimeError> {
+        RedisClient::get()
+            .map_err(|e| RuntimeError::QueueError(e.to_string()))?
+            .get_connection()
+            .await
+            .map_err(|e| RuntimeError::QueueError(e.to_string()))
+    }
+
+    fn decode(payloads: Vec<String>) -> Vec<PostDownloadTask> {
+        payloads
+            .iter()
+            .filter_map(|payload| match serde_json::from_str(payload) {
+                Ok(task) => Some(task),
+                Err(e) => {
+                    error!("Failed to decode pending confirmation: {}", e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Stores a confirmation, returning the ones evicted to stay within capacity
+    pub async fn insert(&self, key: &str, task: PostDownloadTask) -> Result<Vec<PostDownloadTask>, RuntimeError> {
+        let expires_at = Utc::now() + self.ttl;
+
+        match &self.store {
+            Store::Memory(map) => {
+                let mut evicted = Vec::new();
+
+                while !map.contains_key(key) && map.len() >= self.capacity {
+                    let oldest = map
+                        .iter()
+                        .min_by_key(|entry| entry.value().0)
+                        .map(|entry| entry.key().clone());
+
+                    match oldest.and_then(|oldest| map.remove(&oldest)) {
+                        Some((_, (_, task))) => evicted.push(task),
+                        None => break,
+                    }
+                }
+
+                map.insert(key.to_string(), (expires_at, task));
+                Ok(evicted)
+            }
+            Store::Redis => {
+                let payload = serde_json::to_string(&task).map_err(|e| RuntimeError::QueueError(e.to_string()))?;
+
+                let evicted: Vec<String> = redis::cmd("EVAL")
+                    .arg(INSERT_SCRIPT)
+                    .arg(2)
+                    .arg(HASH_KEY)
+                    .arg(EXPIRY_KEY)
+                    .arg(key)
+                    .arg(payload)
+                    .arg(expires_at.timestamp_millis())
+                    .arg(self.capacity)
+                    .query_async(&mut Self::connection().await?)
+                    .await
+                    .map_err(|e| RuntimeError::QueueError(e.to_string()))?;
+
+                Ok(Self::decode(evicted))
+            }
+        }
+    }
+
+    /// Removes and returns the task, `None` if it expired or a second confirmation of the same preview
+    pub async fn take(&self, key: &str) -> Result<Option<PostDownloadTask>, RuntimeError> {
+        match &self.store {
+            Store::Memory(map) => Ok(map
+                .remove(key)
+                .filter(|(_, (expires_at, _))| *expires_at > Utc::now())
+                .map(|(_, (_, task))| task)),
+            Store::Redis => {
+                let payload: Option<String> = redis::cmd("EVAL")
+                    .arg(TAKE_SCRIPT)
+                    .arg(2)
+                    .arg(HASH_KEY)
+                    .arg(EXPIRY_KEY)
+                    .arg(key)
+                    .arg(Utc::now().timestamp_millis())
+                    .query_async(&mut Self::connection().await?)
+                    .await
+                    .map_err(|e| RuntimeError::QueueError(e.to_string()))?;
+
+                payload
+                    .map(|payload| serde_json::from_str(&payload))
+                    .transpose()
+                    .map_err(|e| RuntimeError::QueueError(e.to_string()))
+            }
+        }
+    }
+
+    pub async fn update_context(&self, key: &str, context: TaskContext) -> Result<(), RuntimeError> {
+        match &self.store {
+            Store::Memory(map) => {
+                if let Some(mut entry) = map.get_mut(key) {
+                    entry.1.context = context;
+                }
+            }
+            Store::Redis => {
+                let payload: Option<String> = redis::cmd("HGET")
+                    .arg(HASH_KEY)
+                    .arg(key)
+                    .query_async(&mut Self::connection().await?)
+                    .await
+                    .map_err(|e| RuntimeError::QueueError(e.to_string()))?;
+
+                if let Some(payload) = payload {
+                    let mut task = serde_json::from_str::<PostDownloadTask>(&payload)
+                        .map_err(|e| RuntimeError::QueueError(e.to_string()))?;
+                    task.context = context;
+                    let payload = serde_json::to_string(&task).map_err(|e| RuntimeError::QueueError(e.to_string()))?;
+
+                    redis::cmd("EVAL")
+                        .arg(UPDATE_SCRIPT)
+                        .arg(1)
+                        .arg(HASH_KEY)
+                        .arg(key)
+                        .arg(payload)
+                        .query_async::<_, i32>(&mut Self::connection().await?)
+                        .await
+                        .map_err(|e| RuntimeError::QueueError(e.to_string()))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes and returns every confirmation of a user
+    pub async fn take_user(&self, user_id: u64) -> Result<Vec<PostDownloadTask>, RuntimeError> {
+        let prefix = Self::key(user_id, "");
+
+        match &self.store {
+            Store::Memory(map) => {
+                let keys = map
+                    .iter()
+                    .filter(|entry| entry.key().starts_with(&prefix))
+                    .map(|entry| entry.key().clone())
+                    .collect::<Vec<_>>();
+
+                Ok(keys
+                    .iter()
+                    .filter_map(|key| map.remove(key).map(|(_, (_, task))| task))
+                    .collect())
+            }
+            Store::Redis => {
+                let taken: Vec<String> = redis::cmd("EVAL")
+                    .arg(TAKE_PREFIX_SCRIPT)
+                    .arg(2)
+                    .arg(HASH_KEY)
+                    .arg(EXPIRY_KEY)
+                    .arg(prefix)
+                    .query_async(&mut Self::connection().await?)
+                    .await
+                    .map_err(|e| RuntimeError::QueueError(e.to_string()))?;
+
+                Ok(Self::decode(taken))
+            }
+        }
+    }
+
+    /// Removes and returns every confirmation if they are only kept in memory, they stay in Redis otherwise
+    pub async fn drain_volatile(&self) -> Result<Vec<PostDownloadTask>, RuntimeError> {
+        match &self.store {
+            Store::Memory(map) => {
+                let keys = map.iter().map(|entry| entry.key().clone()).collect::<Vec<_>>();
+
+                Ok(keys
+                    .iter()
+                    .filter_map(|key| map.remove(key).map(|(_, (_, task))| task))
+                    .collect())
+            }
+            Store::Redis => Ok(Vec::new()),
+        }
+    }
+
+    /// Removes and returns every confirmation past its TTL
+    pub async fn take_expired(&self) -> Result<Vec<PostDownloadTask>, RuntimeError> {
+        let now = Utc::now();
+
+        match &self.store {
+            Store::Memory(map) => {
+                let expired = map
+                    .iter()
+                    .filter(|entry| entry.value().0 <= now)
+                    .map(|entry| entry.key().clone())
+                    .collect::<Vec<_>>();
+
+                Ok(expired
+                    .iter()
+                    .filter_map(|key| map.remove(key).map(|(_, (_, task))| task))
+                    .collect())
+            }
+            Store::Redis => {
+                let expired: Vec<String> = redis::cmd("EVAL")
+                    .arg(TAKE_EXPIRED_SCRIPT)
+                    .arg(2)
+                    .arg(HASH_KEY)
+                    .arg(EXPIRY_KEY)
+                    .arg(now.timestamp_millis())
+                    .query_async(&mut Self::connection().await?)
+                    .await
+                    .map_err(|e| RuntimeError::QueueError(e.to_string()))?;
+
+                Ok(Self::decode(expired))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        platform::{MediaContentType, MediaFile, Platform},
+        service::Language,
+    };
+
+    fn task(user_id: u64, media_id: &str) -> PostDownloadTask {
+        let media_file = MediaFile {
+            id: media_id.to_string(),
+            created_at: Utc::now(),
+            title: None,
+            description: None,
+            author: None,
+            content_type: MediaContentType::Single,
+            thumbnail: None,
+            items: vec![],
+            platform: Platform::Instagram,
+        };
+        let context = TaskContext {
+            user_id,
+            chat_id: user_id as i64,
+            message_id: 1,
+            user_tier: Default::default(),
+            platform: Platform::Instagram,
+            language: Language::default(),
+        };
+
+        PostDownloadTask::new(media_file, context)
+    }
+
+    #[tokio::test]
+    async fn test_memory_confirmations_expire_and_stay_bounded() {
+        let pending = PendingConfirmations::memory(Duration::from_millis(100), 2);
+
+        // Two users previewing the same post don't overwrite each other
+        for user_id in [1, 2] {
+            let key = PendingConfirmations::key(user_id, "post");
+            assert!(pending.insert(&key, task(user_id, "post")).await.unwrap().is_empty());
+        }
+        let first = pending.take(&PendingConfirmations::key(1, "post")).await.unwrap();
+        assert_eq!(first.unwrap().context.user_id, 1);
+        assert!(pending
+            .take(&PendingConfirmations::key(1, "post"))
+            .await
+            .unwrap()
+            .is_none());
+
+        // The one closest to expiry makes room for new previews
+        let evicted = pending
+            .insert(&PendingConfirmations::key(3, "post"), task(3, "post"))
+            .await
+            .unwrap();
+        assert!(evicted.is_empty());
+        let evicted = pending
+            .insert(&PendingConfirmations::key(4, "post"), task(4, "post"))
+            .await
+            .unwrap();
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].context.user_id, 2);
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+
+        assert!(pending
+            .take(&PendingConfirmations::key(3, "post"))
+            .await
+            .unwrap()
+            .is_none());
+        let expired = pending.take_expired().await.unwrap();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].context.user_id, 4);
+        assert!(pending.take_expired().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_redis_confirmations_expire_and_stay_bounded() {
+        let Some(_redis) = super::super::redis::test_redis().await else {
+            return;
+        };
+        let pending = PendingConfirmations::redis(Duration::from_millis(100), 2);
+
+        for user_id in [1, 2] {
+            let key = PendingConfirmations::key(user_id, "post");
+            assert!(pending.insert(&key, task(user_id, "post")).await.unwrap().is_empty());
+        }
+        let first = pending.take(&PendingConfirmations::key(1, "post")).await.unwrap();
+        assert_eq!(first.unwrap().context.user_id, 1);
+        assert!(pending
+            .take(&PendingConfirmations::key(1, "post"))
+            .await
+            .unwrap()
+            .is_none());
+
+        let key = PendingConfirmations::key(2, "post");
+        let mut context = task(2, "post").context;
+        context.language = Language::German;
+        pending.update_context(&key, context).await.unwrap();
+
+        let evicted = pending
+            .insert(&PendingConfirmations::key(3, "post"), task(3, "post"))
+            .await
+            .unwrap();
+        assert!(evicted.is_empty());
+        let evicted = pending
+            .insert(&PendingConfirmations::key(4, "post"), task(4, "post"))
+            .await
+            .unwrap();
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].context.user_id, 2);
+        assert_eq!(evicted[0].context.language, Language::German);
+
+        let taken = pending.take_user(3).await.unwrap();
+        assert_eq!(taken.len(), 1);
+        assert_eq!(taken[0].context.user_id, 3);
+        assert!(pending.take_user(3).await.unwrap().is_empty());
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+
+        let expired = pending.take_expired().await.unwrap();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].context.user_id, 4);
+        assert!(pending.take_expired().await.unwrap().is_empty());
+    }
+}
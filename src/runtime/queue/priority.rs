@@ -1,10 +1,15 @@
 use crate::context::UserTier;
-use crate::runtime::task::{Task, TaskWithResult};
+use crate::runtime::task::{DeadLetter, Task};
 use crate::runtime::RuntimeError;
+use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use std::cmp::Ordering;
-use std::collections::BinaryHeap;
-use tokio::sync::{oneshot, Mutex};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use tokio::sync::{Mutex, Notify};
+use tokio::time::Instant;
+
+use super::{fairness::FairnessPolicy, QueueBackend};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Priority {
@@ -13,6 +18,25 @@ pub enum Priority {
     Low,
 }
 
+impl Priority {
+    /// 0 for the highest priority, as used in the scores of the Redis backend
+    pub fn rank(self) -> u8 {
+        match self {
+            Priority::High => 0,
+            Priority::Normal => 1,
+            Priority::Low => 2,
+        }
+    }
+
+    pub fn from_rank(rank: u8) -> Self {
+        match rank {
+            0 => Priority::High,
+            1 => Priority::Normal,
+            _ => Priority::Low,
+        }
+    }
+}
+
 impl From<UserTier> for Priority {
     fn from(tier: UserTier) -> Self {
         match tier {
@@ -31,15 +55,7 @@ impl PartialOrd for Priority {
 
 impl Ord for Priority {
     fn cmp(&self, other: &Self) -> Ordering {
-        match (self, other) {
-            (Priority::High, Priority::High) => Ordering::Equal,
-            (Priority::High, _) => Ordering::Greater,
-            (Priority::Normal, Priority::High) => Ordering::Less,
-            (Priority::Normal, Priority::Normal) => Ordering::Equal,
-            (Priority::Normal, Priority::Low) => Ordering::Greater,
-            (Priority::Low, Priority::Low) => Ordering::Equal,
-            (Priority::Low, _) => Ordering::Less,
-        }
+        self.rank().cmp(&other.rank()).reverse()
     }
 }
 
@@ -48,86 +64,299 @@ struct PrioritizedItem<T: Task> {
     priority: Priority,
     timestamp: DateTime<Utc>,
     task: T,
-    result_tx: oneshot::Sender<T::Result>,
-}
-
-impl<T: Task> PartialEq for PrioritizedItem<T> {
-    fn eq(&self, other: &Self) -> bool {
-        self.priority == other.priority && self.timestamp == other.timestamp
-    }
 }
 
-impl<T: Task> Eq for PrioritizedItem<T> {}
-
-impl<T: Task> PartialOrd for PrioritizedItem<T> {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
-    }
+/// What the fairness policy needs to know about the users of a queue
+#[derive(Default)]
+struct UserActivity {
+    /// Popped tasks that are neither finished nor back in the queue, task id -> user id
+    in_flight: HashMap<String, u64>,
+    last_served: HashMap<u64, DateTime<Utc>>,
 }
 
-impl<T: Task> Ord for PrioritizedItem<T> {
-    fn cmp(&self, other: &Self) -> Ordering {
-        match self.priority.cmp(&other.priority) {
-            Ordering::Equal => self.timestamp.cmp(&other.timestamp).reverse(),
-            other => other,
-        }
+impl UserActivity {
+    fn in_flight(&self, user_id: u64) -> usize {
+        self.in_flight.values().filter(|user| **user == user_id).count()
     }
 }
 
+/// In-memory queue backend, tasks are lost when the process exits
 pub struct PriorityQueue<T: Task> {
-    inner: Mutex<BinaryHeap<PrioritizedItem<T>>>,
+    inner: Mutex<Vec<PrioritizedItem<T>>>,
     capacity: usize,
+    fairness: FairnessPolicy,
+    /// Wakes up a consumer waiting in `pop` whenever an item is pushed or a user drops below their limit
+    notify: Notify,
+    /// Retries waiting out their backoff, moved into the queue once due
+    delayed: Mutex<Vec<(Instant, Priority, T)>>,
+    dead_letters: Mutex<Vec<(DeadLetter, T)>>,
+    activity: Mutex<UserActivity>,
 }
 
 impl<T: Task> PriorityQueue<T> {
     pub fn new(capacity: usize) -> Self {
         Self {
-            inner: Mutex::new(BinaryHeap::with_capacity(capacity)),
+            inner: Mutex::new(Vec::with_capacity(capacity)),
             capacity,
+            fairness: FairnessPolicy::default(),
+            notify: Notify::new(),
+            delayed: Mutex::new(Vec::new()),
+            dead_letters: Mutex::new(Vec::new()),
+            activity: Mutex::new(UserActivity::default()),
         }
     }
 
-    pub async fn push(&self, task: T, priority: Priority) -> Result<oneshot::Receiver<T::Result>, RuntimeError> {
+    /// Limits and interleaves the tasks of each user, without it tasks are served by priority and age only
+    pub fn with_fairness(mut self, fairness: FairnessPolicy) -> Self {
+        self.fairness = fairness;
+        self
+    }
+
+    /// Lower is served first, see [`FairnessPolicy`]
+    fn order_key(
+        &self,
+        item: &PrioritizedItem<T>,
+        activity: &UserActivity,
+        now: DateTime<Utc>,
+    ) -> (u8, Option<DateTime<Utc>>, DateTime<Utc>) {
+        let priority = self.fairness.effective_priority(item.priority, item.timestamp, now);
+        let last_served = activity.last_served.get(&item.task.user_id()).copied();
+        (priority.rank(), last_served, item.timestamp)
+    }
+
+    pub async fn push(&self, task: T, priority: Priority) -> Result<(), RuntimeError> {
         let mut queue = self.inner.lock().await;
         if queue.len() >= self.capacity {
             return Err(RuntimeError::QueueError("Queue is full".to_string()));
         }
 
-        let (tx, rx) = oneshot::channel();
-
         queue.push(PrioritizedItem {
             priority,
             timestamp: Utc::now(),
             task,
-            result_tx: tx,
         });
+        drop(queue);
+
+        self.notify.notify_one();
 
-        Ok(rx)
+        Ok(())
     }
 
-    pub async fn pop(&self) -> Option<TaskWithResult<T>> {
+    /// Waits until an item is available, cancel safe so it can be raced against a shutdown signal
+    pub async fn pop(&self) -> T {
+        loop {
+            if let Some(item) = self.try_pop().await {
+                return item;
+            }
+
+            let next_due = self.delayed.lock().await.iter().map(|(due, _, _)| *due).min();
+
+            // A push in between stores a permit, so the wakeup is not lost
+            match next_due {
+                Some(due) => {
+                    tokio::select! {
+                        _ = self.notify.notified() => {}
+                        _ = tokio::time::sleep_until(due) => {}
+                    }
+                }
+                None => self.notify.notified().await,
+            }
+        }
+    }
+
+    /// Like `pop` but gives up after `timeout`
+    #[allow(dead_code)]
+    pub async fn pop_timeout(&self, timeout: Duration) -> Option<T> {
+        tokio::time::timeout(timeout, self.pop()).await.ok()
+    }
+
+    /// Takes the next item without waiting, `None` as well when every waiting user is at their limit
+    pub async fn try_pop(&self) -> Option<T> {
         let mut queue = self.inner.lock().await;
-        queue.pop().map(|item| TaskWithResult {
-            task: item.task,
-            result_tx: item.result_tx,
-        })
-    }
-    // TODO: for admin to check status
-    // pub async fn len(&self) -> usize {
-    //     self.inner.lock().await.len()
-    // }
-
-    // pub async fn is_empty(&self) -> bool {
-    //     self.inner.lock().await.is_empty()
-    // }
-
-    // pub fn capacity(&self) -> usize {
-    //     self.capacity
-    // }
+
+        let mut delayed = self.delayed.lock().await;
+        let now = Instant::now();
+        let (due, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut *delayed)
+            .into_iter()
+            .partition(|(due, _, _)| *due <= now);
+        *delayed = waiting;
+        drop(delayed);
+
+        let mut activity = self.activity.lock().await;
+
+        for (_, priority, task) in due {
+            activity.in_flight.remove(task.id());
+            queue.push(PrioritizedItem {
+                priority,
+                timestamp: Utc::now(),
+                task,
+            });
+        }
+
+        let now = Utc::now();
+        let index = queue
+            .iter()
+            .enumerate()
+            .filter(|(_, item)| self.fairness.allows(activity.in_flight(item.task.user_id())))
+            .min_by_key(|(_, item)| self.order_key(item, &activity, now))
+            .map(|(index, _)| index)?;
+        let item = queue.swap_remove(index);
+
+        let user_id = item.task.user_id();
+        activity.in_flight.insert(item.task.id().to_string(), user_id);
+        activity.last_served.insert(user_id, now);
+
+        // Users with nothing left would otherwise keep their turn forever
+        let active = queue
+            .iter()
+            .map(|item| item.task.user_id())
+            .chain(activity.in_flight.values().copied())
+            .collect::<HashSet<_>>();
+        activity.last_served.retain(|user_id, _| active.contains(user_id));
+
+        // Only one permit is stored, pass it on so other consumers see the remaining items
+        if !queue.is_empty() {
+            self.notify.notify_one();
+        }
+
+        Some(item.task)
+    }
+
+    pub async fn len(&self) -> usize {
+        self.inner.lock().await.len()
+    }
+
+    #[allow(dead_code)]
+    pub async fn is_empty(&self) -> bool {
+        self.inner.lock().await.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Forgets a popped task, waking a consumer in case its user was held back by the limit
+    async fn finish(&self, task_id: &str) {
+        if self.activity.lock().await.in_flight.remove(task_id).is_some() {
+            self.notify.notify_one();
+        }
+    }
+}
+
+#[async_trait]
+impl<T: Task> QueueBackend<T> for PriorityQueue<T> {
+    async fn push(&self, task: T, priority: Priority) -> Result<(), RuntimeError> {
+        PriorityQueue::push(self, task, priority).await
+    }
+
+    async fn pop(&self) -> T {
+        PriorityQueue::pop(self).await
+    }
+
+    /// Popped tasks are already gone from memory
+    async fn ack(&self, task_id: &str) -> Result<(), RuntimeError> {
+        self.finish(task_id).await;
+        Ok(())
+    }
+
+    /// Retries stay in flight for their user until they are due
+    async fn nack(&self, task: T, priority: Priority, delay: Duration) -> Result<(), RuntimeError> {
+        if delay.is_zero() {
+            self.finish(task.id()).await;
+            return PriorityQueue::push(self, task, priority).await;
+        }
+
+        self.delayed.lock().await.push((Instant::now() + delay, priority, task));
+        // Wake a consumer so it starts waiting for the new due time
+        self.notify.notify_one();
+        Ok(())
+    }
+
+    async fn remove(&self, task_id: &str) -> Result<Option<T>, RuntimeError> {
+        let mut queue = self.inner.lock().await;
+        if let Some(index) = queue.iter().position(|item| item.task.id() == task_id) {
+            return Ok(Some(queue.swap_remove(index).task));
+        }
+        drop(queue);
+
+        let mut delayed = self.delayed.lock().await;
+        let index = delayed.iter().position(|(_, _, task)| task.id() == task_id);
+        let task = index.map(|index| delayed.remove(index).2);
+        drop(delayed);
+
+        if task.is_some() {
+            self.finish(task_id).await;
+        }
+        Ok(task)
+    }
+
+    async fn dead_letter(&self, task: T, letter: DeadLetter) -> Result<(), RuntimeError> {
+        self.finish(task.id()).await;
+        self.dead_letters.lock().await.push((letter, task));
+        Ok(())
+    }
+
+    async fn dead_letters(&self) -> Result<Vec<DeadLetter>, RuntimeError> {
+        Ok(self
+            .dead_letters
+            .lock()
+            .await
+            .iter()
+            .map(|(letter, _)| letter.clone())
+            .collect())
+    }
+
+    async fn take_dead_letter(&self, task_id: &str) -> Result<Option<T>, RuntimeError> {
+        let mut dead_letters = self.dead_letters.lock().await;
+        let index = dead_letters.iter().position(|(letter, _)| letter.task_id == task_id);
+        Ok(index.map(|index| dead_letters.remove(index).1))
+    }
+
+    async fn len(&self) -> Result<usize, RuntimeError> {
+        Ok(PriorityQueue::len(self).await)
+    }
+
+    /// Ignores the in-flight limit, so it is a lower bound while the user has other tasks running
+    async fn position(&self, task_id: &str) -> Result<Option<usize>, RuntimeError> {
+        let queue = self.inner.lock().await;
+        let activity = self.activity.lock().await;
+        let now = Utc::now();
+
+        let Some(item) = queue.iter().find(|item| item.task.id() == task_id) else {
+            return Ok(None);
+        };
+
+        let key = self.order_key(item, &activity, now);
+        Ok(Some(
+            queue
+                .iter()
+                .filter(|other| self.order_key(other, &activity, now) < key)
+                .count()
+                + 1,
+        ))
+    }
+
+    fn capacity(&self) -> usize {
+        PriorityQueue::capacity(self)
+    }
+
+    /// Everything still queued or waiting for a retry is lost on exit
+    async fn drain_volatile(&self) -> Result<Vec<T>, RuntimeError> {
+        let mut queue = std::mem::take(&mut *self.inner.lock().await);
+        let activity = self.activity.lock().await;
+        let now = Utc::now();
+        queue.sort_by_key(|item| self.order_key(item, &activity, now));
+        drop(activity);
+
+        let mut tasks = queue.into_iter().map(|item| item.task).collect::<Vec<_>>();
+        tasks.extend(self.delayed.lock().await.drain(..).map(|(_, _, task)| task));
+        Ok(tasks)
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::super::fairness::FairnessPolicy;
     use super::*;
     use std::time::Duration;
     use tokio::time::sleep;
@@ -135,10 +364,42 @@ mod tests {
     #[derive(Debug)]
     struct TestTask {
         id: i32,
+        user: u64,
+        key: String,
+    }
+
+    impl TestTask {
+        fn new(id: i32, user: u64) -> Self {
+            Self {
+                id,
+                user,
+                key: id.to_string(),
+            }
+        }
     }
 
     impl Task for TestTask {
         type Result = i32;
+
+        fn id(&self) -> &str {
+            &self.key
+        }
+
+        fn user_id(&self) -> u64 {
+            self.user
+        }
+
+        fn attempts(&self) -> u32 {
+            0
+        }
+
+        fn record_failure(&mut self) {}
+
+        fn reset_attempts(&mut self) {}
+
+        fn summary(&self) -> String {
+            self.id.to_string()
+        }
     }
 
     #[tokio::test]
@@ -146,29 +407,19 @@ mod tests {
         let queue = PriorityQueue::<TestTask>::new(5);
 
         // Push tasks with different priorities
-        let rx1 = queue.push(TestTask { id: 1 }, Priority::Low).await.unwrap();
+        queue.push(TestTask::new(1, 0), Priority::Low).await.unwrap();
+        sleep(Duration::from_millis(10)).await;
+        queue.push(TestTask::new(2, 0), Priority::High).await.unwrap();
         sleep(Duration::from_millis(10)).await;
-        let rx2 = queue.push(TestTask { id: 2 }, Priority::High).await.unwrap();
+        queue.push(TestTask::new(3, 0), Priority::Normal).await.unwrap();
         sleep(Duration::from_millis(10)).await;
-        let rx3 = queue.push(TestTask { id: 3 }, Priority::Normal).await.unwrap();
+        queue.push(TestTask::new(4, 0), Priority::Low).await.unwrap();
 
         // Pop tasks and verify order
-        let task1 = queue.pop().await.unwrap();
-        assert_eq!(task1.task.id, 2); // High priority
-        task1.result_tx.send(task1.task.id).unwrap();
-
-        let task2 = queue.pop().await.unwrap();
-        assert_eq!(task2.task.id, 3); // Normal priority
-        task2.result_tx.send(task2.task.id).unwrap();
-
-        let task3 = queue.pop().await.unwrap();
-        assert_eq!(task3.task.id, 1); // Low priority
-        task3.result_tx.send(task3.task.id).unwrap();
-
-        // Verify results
-        assert_eq!(rx2.await.unwrap(), 2);
-        assert_eq!(rx3.await.unwrap(), 3);
-        assert_eq!(rx1.await.unwrap(), 1);
+        assert_eq!(queue.pop().await.id, 2); // High priority
+        assert_eq!(queue.pop().await.id, 3); // Normal priority
+        assert_eq!(queue.pop().await.id, 1); // Low priority, oldest first
+        assert_eq!(queue.pop().await.id, 4);
     }
 
     #[tokio::test]
@@ -176,11 +427,92 @@ mod tests {
         let queue = PriorityQueue::<TestTask>::new(2);
 
         // Fill queue to capacity
-        queue.push(TestTask { id: 1 }, Priority::Normal).await.unwrap();
-        queue.push(TestTask { id: 2 }, Priority::Normal).await.unwrap();
+        queue.push(TestTask::new(1, 0), Priority::Normal).await.unwrap();
+        queue.push(TestTask::new(2, 0), Priority::Normal).await.unwrap();
 
         // Try to push when full
-        let result = queue.push(TestTask { id: 3 }, Priority::Normal).await;
+        let result = queue.push(TestTask::new(3, 0), Priority::Normal).await;
         assert!(result.is_err());
+
+        assert_eq!(queue.len().await, 2);
+        assert_eq!(queue.capacity(), 2);
+        assert!(!queue.is_empty().await);
+    }
+
+    #[tokio::test]
+    async fn test_fairness_limits_and_interleaves_users() {
+        let queue = PriorityQueue::<TestTask>::new(10).with_fairness(FairnessPolicy {
+            max_in_flight_per_user: 1,
+            aging: Duration::from_millis(50),
+        });
+
+        for id in 1..=3 {
+            queue.push(TestTask::new(id, 1), Priority::Low).await.unwrap();
+        }
+        queue.push(TestTask::new(4, 2), Priority::Low).await.unwrap();
+
+        assert_eq!(queue.try_pop().await.unwrap().id, 1);
+        assert_eq!(queue.try_pop().await.unwrap().id, 4);
+        // User 1 is at their limit until task 1 is acknowledged
+        assert!(queue.try_pop().await.is_none());
+        QueueBackend::ack(&queue, "1").await.unwrap();
+        assert_eq!(queue.try_pop().await.unwrap().id, 2);
+        QueueBackend::ack(&queue, "2").await.unwrap();
+        assert_eq!(queue.try_pop().await.unwrap().id, 3);
+
+        // Waiting two aging periods lifts a free task up to subscribers, the older one goes first
+        queue.push(TestTask::new(5, 3), Priority::Low).await.unwrap();
+        sleep(Duration::from_millis(120)).await;
+        queue.push(TestTask::new(6, 4), Priority::High).await.unwrap();
+        assert_eq!(queue.try_pop().await.unwrap().id, 5);
+        assert_eq!(queue.try_pop().await.unwrap().id, 6);
+    }
+
+    #[tokio::test]
+    async fn test_drain_volatile_takes_queued_and_delayed() {
+        let queue = PriorityQueue::<TestTask>::new(5);
+
+        queue.push(TestTask::new(1, 0), Priority::Low).await.unwrap();
+        queue.push(TestTask::new(2, 0), Priority::High).await.unwrap();
+        QueueBackend::nack(&queue, TestTask::new(3, 0), Priority::Normal, Duration::from_secs(60))
+            .await
+            .unwrap();
+
+        let drained = QueueBackend::drain_volatile(&queue).await.unwrap();
+        assert_eq!(drained.iter().map(|task| task.id).collect::<Vec<_>>(), [2, 1, 3]);
+        assert!(queue.try_pop().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_pop_waits_for_push() {
+        let queue = std::sync::Arc::new(PriorityQueue::<TestTask>::new(5));
+
+        assert!(queue.try_pop().await.is_none());
+        assert!(queue.pop_timeout(Duration::from_millis(20)).await.is_none());
+
+        let consumers = (0..2)
+            .map(|_| {
+                let queue = queue.clone();
+                tokio::spawn(async move { queue.pop().await.id })
+            })
+            .collect::<Vec<_>>();
+
+        sleep(Duration::from_millis(20)).await;
+        queue.push(TestTask::new(1, 0), Priority::Low).await.unwrap();
+        queue.push(TestTask::new(2, 0), Priority::Low).await.unwrap();
+
+        let mut ids = Vec::new();
+        for consumer in consumers {
+            ids.push(
+                tokio::time::timeout(Duration::from_secs(1), consumer)
+                    .await
+                    .unwrap()
+                    .unwrap(),
+            );
+        }
+        ids.sort();
+
+        assert_eq!(ids, vec![1, 2]);
+        assert!(queue.is_empty().await);
     }
 }
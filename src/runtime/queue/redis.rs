@@ -0,0 +1,875 @@
+use std::{marker::PhantomData, time::Duration};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use redis::aio::MultiplexedConnection;
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::sync::{oneshot, Notify};
+
+use crate::{
+    runtime::{
+        task::{DeadLetter, Task},
+        RuntimeError,
+    },
+    storage::RedisClient,
+};
+
+use super::{fairness::FairnessPolicy, priority::Priority, QueueBackend};
+
+/// Lua helpers shared by the scripts that pick or rank pending tasks, mirrors [`FairnessPolicy`] and the
+/// in-memory backend. Scores are `rank * 10^13 + enqueue time`, see [`RedisQueue::score`].
+macro_rules! fairness_lua {
+    () => {
+        r#"
+local function order_key(users, served, id, score, now, aging)
+    local rank = math.floor(score / 10000000000000)
+    local enqueued_at = score - rank * 10000000000000
+    if aging > 0 then
+        rank = math.max(0, rank - math.floor((now - enqueued_at) / aging))
+    end
+    local user = redis.call('HGET', users, id) or id
+    local last_served = tonumber(redis.call('HGET', served, user) or '-1')
+    return {rank, last_served, enqueued_at}, user
+end
+
+local function before(a, b)
+    if a[1] ~= b[1] then
+        return a[1] < b[1]
+    end
+    if a[2] ~= b[2] then
+        return a[2] < b[2]
+    end
+    return a[3] < b[3]
+end
+
+local function index(prefix, waiting, user, id, score)
+    redis.call('ZADD', prefix .. user, score, id)
+    redis.call('SADD', waiting, user)
+end
+
+local function unindex(prefix, waiting, user, id)
+    redis.call('ZREM', prefix .. user, id)
+    if redis.call('ZCARD', prefix .. user) == 0 then
+        redis.call('SREM', waiting, user)
+    end
+end
+
+local function release(users, in_flight, id)
+    local user = redis.call('HGET', users, id) or id
+    if redis.call('HINCRBY', in_flight, user, -1) <= 0 then
+        redis.call('HDEL', in_flight, user)
+    end
+end
+"#
+    };
+}
+
+/// Atomically checks the capacity and stores the task with its priority score and user
+const PUSH_SCRIPT: &str = concat!(
+    fairness_lua!(),
+    r#"
+if redis.call('ZCARD', KEYS[1]) >= tonumber(ARGV[1]) then
+    return 0
+end
+redis.call('HSET', KEYS[2], ARGV[2], ARGV[3])
+redis.call('HSET', KEYS[3], ARGV[2], ARGV[4])
+redis.call('HSET', KEYS[4], ARGV[2], ARGV[5])
+redis.call('ZADD', KEYS[1], ARGV[4], ARGV[2])
+index(ARGV[6], KEYS[5], ARGV[5], ARGV[2], ARGV[4])
+return 1
+"#
+);
+
+/// Requeues the tasks whose visibility timeout expired, then moves the task the fairness policy picks to
+/// the processing set. Returns nothing when every waiting user is at their in-flight limit.
+///
+/// Only the oldest task of each user in each priority band is ranked, aging raises it first and it is
+/// served before the user's other tasks of the band anyway. Queues from before the per-user index are
+/// indexed on the first pop.
+const POP_SCRIPT: &str = concat!(
+    fairness_lua!(),
+    r#"
+local now = tonumber(ARGV[1])
+local limit = tonumber(ARGV[3])
+local aging = tonumber(ARGV[4])
+local prefix = ARGV[5]
+local lowest_rank = tonumber(ARGV[6])
+
+if redis.call('EXISTS', KEYS[9]) == 0 then
+    local pending = redis.call('ZRANGE', KEYS[1], 0, -1, 'WITHSCORES')
+    for i = 1, #pending, 2 do
+        local user = redis.call('HGET', KEYS[5], pending[i]) or pending[i]
+        index(prefix, KEYS[8], user, pending[i], pending[i + 1])
+    end
+    redis.call('SET', KEYS[9], 1)
+end
+
+local expired = redis.call('ZRANGEBYSCORE', KEYS[2], '-inf', now)
+for _, id in ipairs(expired) do
+    redis.call('ZREM', KEYS[2], id)
+    release(KEYS[5], KEYS[6], id)
+    local score = redis.call('HGET', KEYS[4], id)
+    if score then
+        redis.call('ZADD', KEYS[1], score, id)
+        index(prefix, KEYS[8], redis.call('HGET', KEYS[5], id) or id, id, score)
+    end
+end
+
+local waiting = redis.call('SMEMBERS', KEYS[8])
+local best, best_key, best_user
+for _, user in ipairs(waiting) do
+    local in_flight = tonumber(redis.call('HGET', KEYS[6], user) or '0')
+    if limit == 0 or in_flight < limit then
+        for rank = 0, lowest_rank do
+            local min = string.format('%.0f', rank * 10000000000000)
+            local max = '(' .. string.format('%.0f', (rank + 1) * 10000000000000)
+            local oldest = redis.call('ZRANGEBYSCORE', prefix .. user, min, max, 'WITHSCORES', 'LIMIT', 0, 1)
+            if #oldest > 0 then
+                local key = order_key(KEYS[5], KEYS[7], oldest[1], tonumber(oldest[2]), now, aging)
+                if best == nil or before(key, best_key) then
+                    best, best_key, best_user = oldest[1], key, user
+                end
+            end
+        end
+    end
+end
+
+-- Users with nothing left would otherwise keep their turn forever
+local active = {}
+for _, user in ipairs(waiting) do
+    active[user] = true
+end
+for _, user in ipairs(redis.call('HKEYS', KEYS[7])) do
+    if not active[user] and redis.call('HEXISTS', KEYS[6], user) == 0 then
+        redis.call('HDEL', KEYS[7], user)
+    end
+end
+
+if best == nil then
+    return false
+end
+redis.call('ZREM', KEYS[1], best)
+unindex(prefix, KEYS[8], best_user, best)
+local payload = redis.call('HGET', KEYS[3], best)
+if not payload then
+    redis.call('HDEL', KEYS[4], best)
+    redis.call('HDEL', KEYS[5], best)
+    return false
+end
+redis.call('ZADD', KEYS[2], now + tonumber(ARGV[2]), best)
+redis.call('HINCRBY', KEYS[6], best_user, 1)
+redis.call('HSET', KEYS[7], best_user, now)
+return {best, payload}
+"#
+);
+
+/// How many pending tasks the fairness policy ranks before the given one, plus one
+const POSITION_SCRIPT: &str = concat!(
+    fairness_lua!(),
+    r#"
+local now = tonumber(ARGV[2])
+local aging = tonumber(ARGV[3])
+
+local score = redis.call('ZSCORE', KEYS[1], ARGV[1])
+if not score then
+    return false
+end
+local target = order_key(KEYS[2], KEYS[3], ARGV[1], tonumber(score), now, aging)
+
+local ahead = 0
+local pending = redis.call('ZRANGE', KEYS[1], 0, -1, 'WITHSCORES')
+for i = 1, #pending, 2 do
+    local key = order_key(KEYS[2], KEYS[3], pending[i], tonumber(pending[i + 1]), now, aging)
+    if before(key, target) then
+        ahead = ahead + 1
+    end
+end
+return ahead + 1
+"#
+);
+
+const ACK_SCRIPT: &str = concat!(
+    fairness_lua!(),
+    r#"
+if redis.call('ZREM', KEYS[1], ARGV[1]) == 1 then
+    release(KEYS[4], KEYS[5], ARGV[1])
+end
+redis.call('HDEL', KEYS[2], ARGV[1])
+redis.call('HDEL', KEYS[3], ARGV[1])
+redis.call('HDEL', KEYS[4], ARGV[1])
+return 1
+"#
+);
+
+/// Stores the updated task and pushes its visibility deadline to the end of the backoff, the pop script
+/// then puts it back at its original position in the queue. It counts as in flight until then.
+const NACK_SCRIPT: &str = r#"
+redis.call('HSET', KEYS[2], ARGV[1], ARGV[2])
+redis.call('ZADD', KEYS[1], ARGV[3], ARGV[1])
+return 1
+"#;
+
+/// Only removes tasks that are still waiting, popped ones are stopped through their cancellation token
+const REMOVE_SCRIPT: &str = concat!(
+    fairness_lua!(),
+    r#"
+if redis.call('ZREM', KEYS[1], ARGV[1]) == 0 then
+    return false
+end
+unindex(ARGV[2], KEYS[5], redis.call('HGET', KEYS[4], ARGV[1]) or ARGV[1], ARGV[1])
+local payload = redis.call('HGET', KEYS[2], ARGV[1])
+redis.call('HDEL', KEYS[2], ARGV[1])
+redis.call('HDEL', KEYS[3], ARGV[1])
+redis.call('HDEL', KEYS[4], ARGV[1])
+return payload
+"#
+);
+
+const DEAD_LETTER_SCRIPT: &str = concat!(
+    fairness_lua!(),
+    r#"
+if redis.call('ZREM', KEYS[1], ARGV[1]) == 1 then
+    release(KEYS[6], KEYS[7], ARGV[1])
+end
+redis.call('HDEL', KEYS[2], ARGV[1])
+redis.call('HDEL', KEYS[3], ARGV[1])
+redis.call('HDEL', KEYS[6], ARGV[1])
+redis.call('HSET', KEYS[4], ARGV[1], ARGV[2])
+redis.call('HSET', KEYS[5], ARGV[1], ARGV[3])
+return 1
+"#
+);
+
+/// Puts a popped task back at its position in the queue
+const REQUEUE_SCRIPT: &str = concat!(
+    fairness_lua!(),
+    r#"
+if redis.call('ZREM', KEYS[1], ARGV[1]) == 0 then
+    return 0
+end
+release(KEYS[4], KEYS[5], ARGV[1])
+local score = redis.call('HGET', KEYS[3], ARGV[1])
+if score then
+    redis.call('ZADD', KEYS[2], score, ARGV[1])
+    index(ARGV[2], KEYS[6], redis.call('HGET', KEYS[4], ARGV[1]) or ARGV[1], ARGV[1], score)
+end
+return 1
+"#
+);
+
+const TAKE_DEAD_LETTER_SCRIPT: &str = r#"
+local payload = redis.call('HGET', KEYS[2], ARGV[1])
+redis.call('HDEL', KEYS[1], ARGV[1])
+redis.call('HDEL', KEYS[2], ARGV[1])
+return payload
+"#;
+
+fn queue_key(name: &str, suffix: &str) -> String {
+    format!("queue:{}:{}", name, suffix)
+}
+
+async fn connection() -> Result<MultiplexedConnection, RuntimeError> {
+    RedisClient::get()
+        .map_err(|e| RuntimeError::QueueError(e.to_string()))?
+        .get_connection()
+        .await
+        .map_err(|e| RuntimeError::QueueError(e.to_string()))
+}
+
+/// Hands a popped task back to the queue right away instead of after the visibility timeout
+async fn requeue(name: String, task_id: String) {
+    let requeued = async {
+        redis::cmd("EVAL")
+            .arg(REQUEUE_SCRIPT)
+            .arg(6)
+            .arg(queue_key(&name, "processing"))
+            .arg(queue_key(&name, "pending"))
+            .arg(queue_key(&name, "scores"))
+            .arg(queue_key(&name, "users"))
+            .arg(queue_key(&name, "in_flight"))
+            .arg(queue_key(&name, "waiting"))
+            .arg(&task_id)
+            .arg(queue_key(&name, "user:"))
+            .query_async::<_, i32>(&mut connection().await?)
+            .await
+            .map_err(|e| RuntimeError::QueueError(e.to_string()))
+    };
+
+    match requeued.await {
+        Ok(_) => info!(
+            "Put task {} back into queue {} after its pop was dropped",
+            task_id, name
+        ),
+        Err(e) => error!("Failed to put task {} back into queue {}: {}", task_id, name, e),
+    }
+}
+
+type PopReply = Result<Option<(String, String)>, RuntimeError>;
+
+/// Waits for the pop script, which runs on its own task so that a dropped pop can't lose its reply. A task
+/// popped for a caller that stopped waiting, e.g. a worker whose `select!` picked the shutdown signal, is
+/// requeued by whichever side sees it last.
+struct PendingPop {
+    name: String,
+    reply: oneshot::Receiver<PopReply>,
+}
+
+impl Drop for PendingPop {
+    fn drop(&mut self) {
+        self.reply.close();
+        if let Ok(Ok(Some((task_id, _)))) = self.reply.try_recv() {
+            tokio::spawn(requeue(self.name.clone(), task_id));
+        }
+    }
+}
+
+/// Durable queue backend, survives restarts
+///
+/// - `queue:<name>:pending`: sorted set of task ids, the lowest score is served first
+/// - `queue:<name>:processing`: sorted set of popped task ids scored by their visibility deadline, tasks
+///   not acknowledged in time are delivered again
+/// - `queue:<name>:tasks` / `queue:<name>:scores`: hashes with the payload and priority score of each task
+/// - `queue:<name>:users`: hash with the user of each task
+/// - `queue:<name>:user:<user>` / `queue:<name>:waiting`: sorted set of the pending tasks of each user and
+///   set of the users having any, so popping ranks each user's oldest task instead of the whole queue
+/// - `queue:<name>:indexed`: set once the pending tasks are in the per-user sets
+/// - `queue:<name>:in_flight` / `queue:<name>:served`: hashes with the unfinished tasks of each user and
+///   when they were last served, for the fairness policy
+/// - `queue:<name>:dead` / `queue:<name>:dead_tasks`: hashes with the dead letters and their payloads
+pub struct RedisQueue<T> {
+    name: String,
+    capacity: usize,
+    visibility_timeout: Duration,
+    fairness: FairnessPolicy,
+    poll_interval: Duration,
+    /// Wakes up local consumers right away, tasks pushed by other instances or redelivered after a
+    /// timeout are picked up by polling
+    notify: Notify,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> RedisQueue<T>
+where
+    T: Task + Serialize + DeserializeOwned,
+{
+    pub fn new(name: &str, capacity: usize, visibility_timeout: Duration) -> Self {
+        Self {
+            name: name.to_string(),
+            capacity,
+            visibility_timeout,
+            fairness: FairnessPolicy::default(),
+            poll_interval: Duration::from_secs(1),
+            notify: Notify::new(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Limits and interleaves the tasks of each user, without it tasks are served by priority and age only
+    pub fn with_fairness(mut self, fairness: FairnessPolicy) -> Self {
+        self.fairness = fairness;
+        self
+    }
+
+    fn key(&self, suffix: &str) -> String {
+        queue_key(&self.name, suffix)
+    }
+
+    /// Higher priorities get lower scores, ties are broken by enqueue time
+    fn score(priority: Priority) -> i64 {
+        priority.rank() as i64 * 10_000_000_000_000 + Utc::now().timestamp_millis()
+    }
+
+    async fn try_pop(&self) -> Result<Option<T>, RuntimeError> {
+        let mut script = redis::cmd("EVAL");
+        script
+            .arg(POP_SCRIPT)
+            .arg(9)
+            .arg(self.key("pending"))
+            .arg(self.key("processing"))
+            .arg(self.key("tasks"))
+            .arg(self.key("scores"))
+            .arg(self.key("users"))
+            .arg(self.key("in_flight"))
+            .arg(self.key("served"))
+            .arg(self.key("waiting"))
+            .arg(self.key("indexed"))
+            .arg(Utc::now().timestamp_millis())
+            .arg(self.visibility_timeout.as_millis() as i64)
+            .arg(self.fairness.max_in_flight_per_user)
+            .arg(self.fairness.aging.as_millis() as i64)
+            .arg(self.key("user:"))
+            .arg(Priority::Low.rank());
+
+        let (tx, rx) = oneshot::channel();
+        let mut pending = PendingPop {
+            name: self.name.clone(),
+            reply: rx,
+        };
+        let name = self.name.clone();
+        tokio::spawn(async move {
+            let reply = async {
+                script
+                    .query_async(&mut connection().await?)
+                    .await
+                    .map_err(|e| RuntimeError::QueueError(e.to_string()))
+            };
+            if let Err(Ok(Some((task_id, _)))) = tx.send(reply.await) {
+                requeue(name, task_id).await;
+            }
+        });
+
+        let item = (&mut pending.reply)
+            .await
+            .map_err(|_| RuntimeError::QueueError("Pop script was dropped".to_string()))??;
+
+        let Some((id, payload)) = item else {
+            return Ok(None);
+        };
+
+        match serde_json::from_str::<T>(&payload) {
+            Ok(task) => Ok(Some(task)),
+            Err(e) => {
+                // A payload from an incompatible version would be redelivered forever
+                error!("Dropping undecodable task {} from queue {}: {}", id, self.name, e);
+                QueueBackend::<T>::ack(self, &id).await?;
+                Ok(None)
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<T> QueueBackend<T> for RedisQueue<T>
+where
+    T: Task + Serialize + DeserializeOwned,
+{
+    async fn push(&self, task: T, priority: Priority) -> Result<(), RuntimeError> {
+        let payload = serde_json::to_string(&task).map_err(|e| RuntimeError::QueueError(e.to_string()))?;
+        let mut conn = connection().await?;
+
+        let pushed: i32 = redis::cmd("EVAL")
+            .arg(PUSH_SCRIPT)
+            .arg(5)
+            .arg(self.key("pending"))
+            .arg(self.key("tasks"))
+            .arg(self.key("scores"))
+            .arg(self.key("users"))
+            .arg(self.key("waiting"))
+            .arg(self.capacity)
+            .arg(task.id())
+            .arg(payload)
+            .arg(Self::score(priority))
+            .arg(task.user_id())
+            .arg(self.key("user:"))
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| RuntimeError::QueueError(e.to_string()))?;
+
+        if pushed == 0 {
+            return Err(RuntimeError::QueueError("Queue is full".to_string()));
+        }
+
+        self.notify.notify_one();
+        Ok(())
+    }
+
+    async fn pop(&self) -> T {
+        loop {
+            match self.try_pop().await {
+                Ok(Some(task)) => return task,
+                Ok(None) => {}
+                Err(e) => error!("Failed to pop from queue {}: {}", self.name, e),
+            }
+
+            tokio::select! {
+                _ = self.notify.notified() => {}
+                _ = tokio::time::sleep(self.poll_interval) => {}
+            }
+        }
+    }
+
+    async fn ack(&self, task_id: &str) -> Result<(), RuntimeError> {
+        let mut conn = connection().await?;
+
+        redis::cmd("EVAL")
+            .arg(ACK_SCRIPT)
+            .arg(5)
+            .arg(self.key("processing"))
+            .arg(self.key("tasks"))
+            .arg(self.key("scores"))
+            .arg(self.key("users"))
+            .arg(self.key("in_flight"))
+            .arg(task_id)
+            .query_async::<_, i32>(&mut conn)
+            .await
+            .map_err(|e| RuntimeError::QueueError(e.to_string()))?;
+
+        // The user may have been held back by the in-flight limit
+        self.notify.notify_one();
+        Ok(())
+    }
+
+    async fn nack(&self, task: T, _priority: Priority, delay: Duration) -> Result<(), RuntimeError> {
+        let payload = serde_json::to_string(&task).map_err(|e| RuntimeError::QueueError(e.to_string()))?;
+        let mut conn = connection().await?;
+
+        redis::cmd("EVAL")
+            .arg(NACK_SCRIPT)
+            .arg(2)
+            .arg(self.key("processing"))
+            .arg(self.key("tasks"))
+            .arg(task.id())
+            .arg(payload)
+            .arg(Utc::now().timestamp_millis() + delay.as_millis() as i64)
+            .query_async::<_, i32>(&mut conn)
+            .await
+            .map_err(|e| RuntimeError::QueueError(e.to_string()))?;
+
+        self.notify.notify_one();
+        Ok(())
+    }
+
+    async fn remove(&self, task_id: &str) -> Result<Option<T>, RuntimeError> {
+        let mut conn = connection().await?;
+
+        let payload: Option<String> = redis::cmd("EVAL")
+            .arg(REMOVE_SCRIPT)
+            .arg(5)
+            .arg(self.key("pending"))
+            .arg(self.key("tasks"))
+            .arg(self.key("scores"))
+            .arg(self.key("users"))
+            .arg(self.key("waiting"))
+            .arg(task_id)
+            .arg(self.key("user:"))
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| RuntimeError::QueueError(e.to_string()))?;
+
+        payload
+            .map(|payload| serde_json::from_str(&payload))
+            .transpose()
+            .map_err(|e| RuntimeError::QueueError(e.to_string()))
+    }
+
+    async fn dead_letter(&self, task: T, letter: DeadLetter) -> Result<(), RuntimeError> {
+        let payload = serde_json::to_string(&task).map_err(|e| RuntimeError::QueueError(e.to_string()))?;
+        let letter = serde_json::to_string(&letter).map_err(|e| RuntimeError::QueueError(e.to_string()))?;
+        let mut conn = connection().await?;
+
+        redis::cmd("EVAL")
+            .arg(DEAD_LETTER_SCRIPT)
+            .arg(7)
+            .arg(self.key("processing"))
+            .arg(self.key("tasks"))
+            .arg(self.key("scores"))
+            .arg(self.key("dead"))
+            .arg(self.key("dead_tasks"))
+            .arg(self.key("users"))
+            .arg(self.key("in_flight"))
+            .arg(task.id())
+            .arg(letter)
+            .arg(payload)
+            .query_async::<_, i32>(&mut conn)
+            .await
+            .map_err(|e| RuntimeError::QueueError(e.to_string()))?;
+
+        Ok(())
+    }
+
+    async fn dead_letters(&self) -> Result<Vec<DeadLetter>, RuntimeError> {
+        let mut conn = connection().await?;
+
+        let letters: Vec<String> = redis::cmd("HVALS")
+            .arg(self.key("dead"))
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| RuntimeError::QueueError(e.to_string()))?;
+
+        letters
+            .iter()
+            .map(|letter| serde_json::from_str(letter).map_err(|e| RuntimeError::QueueError(e.to_string())))
+            .collect()
+    }
+
+    async fn take_dead_letter(&self, task_id: &str) -> Result<Option<T>, RuntimeError> {
+        let mut conn = connection().await?;
+
+        let payload: Option<String> = redis::cmd("EVAL")
+            .arg(TAKE_DEAD_LETTER_SCRIPT)
+            .arg(2)
+            .arg(self.key("dead"))
+            .arg(self.key("dead_tasks"))
+            .arg(task_id)
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| RuntimeError::QueueError(e.to_string()))?;
+
+        payload
+            .map(|payload| serde_json::from_str(&payload))
+            .transpose()
+            .map_err(|e| RuntimeError::QueueError(e.to_string()))
+    }
+
+    async fn len(&self) -> Result<usize, RuntimeError> {
+        let mut conn = connection().await?;
+
+        redis::cmd("ZCARD")
+            .arg(self.key("pending"))
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| RuntimeError::QueueError(e.to_string()))
+    }
+
+    async fn position(&self, task_id: &str) -> Result<Option<usize>, RuntimeError> {
+        let mut conn = connection().await?;
+
+        redis::cmd("EVAL")
+            .arg(POSITION_SCRIPT)
+            .arg(3)
+            .arg(self.key("pending"))
+            .arg(self.key("users"))
+            .arg(self.key("served"))
+            .arg(task_id)
+            .arg(Utc::now().timestamp_millis())
+            .arg(self.fairness.aging.as_millis() as i64)
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| RuntimeError::QueueError(e.to_string()))
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Queued tasks stay in Redis and popped ones are redelivered after the visibility timeout
+    async fn drain_volatile(&self) -> Result<Vec<T>, RuntimeError> {
+        Ok(Vec::new())
+    }
+}
+
+/// Connects to the server in `REDIS_TEST_URL` and empties it, so it must be a throwaway database. Tests
+/// using it are skipped without one, the guard keeps them from running at the same time.
+#[cfg(test)]
+pub(super) async fn test_redis() -> Option<tokio::sync::MutexGuard<'static, ()>> {
+    static LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    let Ok(url) = std::env::var("REDIS_TEST_URL") else {
+        println!("REDIS_TEST_URL is not set, skipping");
+        return None;
+    };
+
+    let guard = LOCK.lock().await;
+    RedisClient::init(&url).await.unwrap();
+    redis::cmd("FLUSHDB")
+        .query_async::<_, ()>(&mut connection().await.unwrap())
+        .await
+        .unwrap();
+
+    Some(guard)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    struct TestTask {
+        id: String,
+        user: u64,
+    }
+
+    impl TestTask {
+        fn new(id: &str, user: u64) -> Self {
+            Self {
+                id: id.to_string(),
+                user,
+            }
+        }
+    }
+
+    impl Task for TestTask {
+        type Result = ();
+
+        fn id(&self) -> &str {
+            &self.id
+        }
+
+        fn user_id(&self) -> u64 {
+            self.user
+        }
+
+        fn attempts(&self) -> u32 {
+            0
+        }
+
+        fn record_failure(&mut self) {}
+
+        fn reset_attempts(&mut self) {}
+
+        fn summary(&self) -> String {
+            format!("test {}", self.id)
+        }
+    }
+
+    async fn push(queue: &RedisQueue<TestTask>, id: &str, user: u64, priority: Priority) {
+        queue.push(TestTask::new(id, user), priority).await.unwrap();
+        // Tasks queued within the same millisecond have no defined order
+        tokio::time::sleep(Duration::from_millis(2)).await;
+    }
+
+    async fn try_pop(queue: &RedisQueue<TestTask>) -> Option<String> {
+        queue.try_pop().await.unwrap().map(|task| task.id)
+    }
+
+    #[tokio::test]
+    async fn test_redis_queue_delivers_until_acknowledged() {
+        let Some(_redis) = test_redis().await else {
+            return;
+        };
+        let queue = RedisQueue::<TestTask>::new("test", 3, Duration::from_millis(200));
+
+        push(&queue, "low", 1, Priority::Low).await;
+        push(&queue, "high", 2, Priority::High).await;
+        push(&queue, "normal", 3, Priority::Normal).await;
+        assert!(queue.push(TestTask::new("full", 4), Priority::High).await.is_err());
+        assert_eq!(queue.len().await.unwrap(), 3);
+        assert_eq!(queue.position("low").await.unwrap(), Some(3));
+
+        assert_eq!(try_pop(&queue).await.as_deref(), Some("high"));
+        queue.ack("high").await.unwrap();
+
+        // Not acknowledged in time, so delivered again
+        assert_eq!(try_pop(&queue).await.as_deref(), Some("normal"));
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert_eq!(try_pop(&queue).await.as_deref(), Some("normal"));
+
+        // A retry waits out its backoff
+        queue
+            .nack(TestTask::new("normal", 3), Priority::Normal, Duration::from_millis(100))
+            .await
+            .unwrap();
+        assert_eq!(try_pop(&queue).await.as_deref(), Some("low"));
+        assert_eq!(try_pop(&queue).await, None);
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(try_pop(&queue).await.as_deref(), Some("normal"));
+
+        queue.ack("low").await.unwrap();
+        queue.ack("normal").await.unwrap();
+        assert_eq!(queue.len().await.unwrap(), 0);
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert_eq!(try_pop(&queue).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_redis_queue_removes_and_dead_letters() {
+        let Some(_redis) = test_redis().await else {
+            return;
+        };
+        let queue = RedisQueue::<TestTask>::new("test", 10, Duration::from_secs(60));
+
+        push(&queue, "a", 1, Priority::Normal).await;
+        push(&queue, "b", 1, Priority::Normal).await;
+
+        assert_eq!(queue.remove("a").await.unwrap().unwrap().id, "a");
+        assert!(queue.remove("a").await.unwrap().is_none());
+        assert_eq!(queue.position("b").await.unwrap(), Some(1));
+
+        // Popped tasks can't be removed, only dead lettered
+        let task = queue.try_pop().await.unwrap().unwrap();
+        assert!(queue.remove("b").await.unwrap().is_none());
+        let letter = DeadLetter {
+            task_id: task.id.clone(),
+            summary: task.summary(),
+            error: "failed".to_string(),
+            attempts: 3,
+            failed_at: Utc::now(),
+        };
+        queue.dead_letter(task, letter).await.unwrap();
+
+        let letters = queue.dead_letters().await.unwrap();
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].summary, "test b");
+        assert_eq!(queue.take_dead_letter("b").await.unwrap().unwrap().id, "b");
+        assert!(queue.take_dead_letter("b").await.unwrap().is_none());
+        assert!(queue.dead_letters().await.unwrap().is_empty());
+        assert_eq!(queue.len().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_redis_queue_limits_and_interleaves_users() {
+        let Some(_redis) = test_redis().await else {
+            return;
+        };
+        let queue = RedisQueue::<TestTask>::new("test", 10, Duration::from_secs(60)).with_fairness(FairnessPolicy {
+            max_in_flight_per_user: 1,
+            aging: Duration::from_millis(50),
+        });
+
+        for id in ["1", "2", "3"] {
+            push(&queue, id, 1, Priority::Low).await;
+        }
+        push(&queue, "4", 2, Priority::Low).await;
+
+        assert_eq!(try_pop(&queue).await.as_deref(), Some("1"));
+        assert_eq!(try_pop(&queue).await.as_deref(), Some("4"));
+        // User 1 is at their limit until task 1 is acknowledged
+        assert_eq!(try_pop(&queue).await, None);
+        queue.ack("1").await.unwrap();
+        assert_eq!(try_pop(&queue).await.as_deref(), Some("2"));
+        queue.ack("2").await.unwrap();
+        assert_eq!(try_pop(&queue).await.as_deref(), Some("3"));
+
+        // Waiting two aging periods lifts a free task up to subscribers, the older one goes first
+        push(&queue, "5", 3, Priority::Low).await;
+        tokio::time::sleep(Duration::from_millis(120)).await;
+        push(&queue, "6", 4, Priority::High).await;
+        assert_eq!(try_pop(&queue).await.as_deref(), Some("5"));
+        assert_eq!(try_pop(&queue).await.as_deref(), Some("6"));
+    }
+
+    #[tokio::test]
+    async fn test_redis_queue_serves_other_users_behind_a_long_backlog() {
+        let Some(_redis) = test_redis().await else {
+            return;
+        };
+        let queue = RedisQueue::<TestTask>::new("test", 200, Duration::from_secs(60)).with_fairness(FairnessPolicy {
+            max_in_flight_per_user: 0,
+            aging: Duration::ZERO,
+        });
+
+        for i in 0..150 {
+            queue
+                .push(TestTask::new(&format!("backlog-{}", i), 1), Priority::Normal)
+                .await
+                .unwrap();
+        }
+        push(&queue, "other", 2, Priority::Normal).await;
+
+        assert_eq!(try_pop(&queue).await.as_deref(), Some("backlog-0"));
+        // User 2 has waited longer than user 1 although their task is behind the whole backlog
+        assert_eq!(try_pop(&queue).await.as_deref(), Some("other"));
+        assert_eq!(queue.len().await.unwrap(), 149);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_pop_puts_its_task_back() {
+        let Some(_redis) = test_redis().await else {
+            return;
+        };
+        let queue = RedisQueue::<TestTask>::new("test", 10, Duration::from_secs(60)).with_fairness(FairnessPolicy {
+            max_in_flight_per_user: 1,
+            aging: Duration::ZERO,
+        });
+        push(&queue, "a", 1, Priority::Normal).await;
+
+        // Dropped after sending the script, like a worker picking its shutdown signal
+        assert!(tokio::time::timeout(Duration::ZERO, queue.pop()).await.is_err());
+
+        // Back in the queue and no longer counted against the user's limit
+        let task = tokio::time::timeout(Duration::from_secs(5), queue.pop()).await.unwrap();
+        assert_eq!(task.id, "a");
+    }
+}
@@ -0,0 +1,358 @@
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use dashmap::DashMap;
+use tokio::sync::broadcast;
+
+use crate::error::BotResult;
+
+use super::RuntimeError;
+
+type JobFn = Arc<dyn Fn() -> Pin<Box<dyn Future<Output = BotResult<()>> + Send>> + Send + Sync>;
+
+/// When a job runs, cron expressions are evaluated in UTC
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    Interval(Duration),
+    Cron(CronSchedule),
+}
+
+impl Schedule {
+    /// Runs every `secs` seconds, zero is rejected as the job would run in a busy loop
+    pub fn every(secs: u64) -> Result<Self, RuntimeError> {
+        if secs == 0 {
+            return Err(RuntimeError::SchedulerError(
+                "Interval must be at least one second".to_string(),
+            ));
+        }
+        Ok(Schedule::Interval(Duration::from_secs(secs)))
+    }
+
+    pub fn cron(expression: &str) -> Result<Self, RuntimeError> {
+        Ok(Schedule::Cron(expression.parse()?))
+    }
+
+    /// How long to wait after `now` until the next run
+    fn delay_from(&self, now: DateTime<Utc>) -> Duration {
+        match self {
+            Schedule::Interval(interval) => *interval,
+            Schedule::Cron(cron) => cron
+                .next_after(now)
+                .and_then(|next| (next - now).to_std().ok())
+                .unwrap_or(Duration::MAX),
+        }
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Schedule::Interval(interval) => write!(f, "every {:?}", interval),
+            Schedule::Cron(cron) => write!(f, "cron {}", cron.expression),
+        }
+    }
+}
+
+/// A standard five field cron expression (minute, hour, day of month, month, day of week) supporting
+/// `*`, values, ranges, lists and steps
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    /// With both day fields restricted a day matches either of them, like cron does
+    day_or: bool,
+}
+
+impl FromStr for CronSchedule {
+    type Err = RuntimeError;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let fields = expression.split_whitespace().collect::<Vec<_>>();
+        let [minute, hour, day_of_month, month, day_of_week] = fields[..] else {
+            return Err(RuntimeError::SchedulerError(format!(
+                "Invalid cron expression: {}",
+                expression
+            )));
+        };
+
+        let mut days_of_week = parse_cron_field(day_of_week, 0, 7)?;
+        // Both 0 and 7 are Sunday
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week = (days_of_week | 1) & !(1 << 7);
+        }
+
+        Ok(Self {
+            expression: expression.to_string(),
+            minutes: parse_cron_field(minute, 0, 59)?,
+            hours: parse_cron_field(hour, 0, 23)?,
+            days_of_month: parse_cron_field(day_of_month, 1, 31)?,
+            months: parse_cron_field(month, 1, 12)?,
+            days_of_week,
+            day_or: !day_of_month.starts_with('*') && !day_of_week.starts_with('*'),
+        })
+    }
+}
+
+impl CronSchedule {
+    /// The first matching minute strictly after `after`, `None` if there is none within a year
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut candidate = after.with_second(0)?.with_nanosecond(0)? + chrono::Duration::minutes(1);
+
+        for _ in 0..366 * 24 * 60 {
+            if self.matches(candidate) {
+                return Some(candidate);
+            }
+            candidate += chrono::Duration::minutes(1);
+        }
+
+        None
+    }
+
+    fn matches(&self, time: DateTime<Utc>) -> bool {
+        let day_of_month = self.days_of_month & (1 << time.day()) != 0;
+        let day_of_week = self.days_of_week & (1 << time.weekday().num_days_from_sunday()) != 0;
+        let day = if self.day_or {
+            day_of_month || day_of_week
+        } else {
+            day_of_month && day_of_week
+        };
+
+        day && self.minutes & (1 << time.minute()) != 0
+            && self.hours & (1 << time.hour()) != 0
+            && self.months & (1 << time.month()) != 0
+    }
+}
+
+fn parse_cron_field(field: &str, min: u32, max: u32) -> Result<u64, RuntimeError> {
+    let invalid = || RuntimeError::SchedulerError(format!("Invalid cron field: {}", field));
+    let mut bits = 0u64;
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().map_err(|_| invalid())?),
+            None => (part, 1),
+        };
+
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (
+                    start.parse::<u32>().map_err(|_| invalid())?,
+                    end.parse::<u32>().map_err(|_| invalid())?,
+                ),
+                None => {
+                    let value = range.parse::<u32>().map_err(|_| invalid())?;
+                    // `5/15` means every 15 starting at 5
+                    (value, if part.contains('/') { max } else { value })
+                }
+            },
+        };
+
+        if step == 0 || start < min || end > max || start > end {
+            return Err(invalid());
+        }
+
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+
+    Ok(bits)
+}
+
+/// The outcome of a job's latest run
+#[derive(Debug, Clone)]
+pub struct JobStatus {
+    pub name: String,
+    pub schedule: String,
+    pub runs: u64,
+    pub last_run: Option<DateTime<Utc>>,
+    pub last_duration: Option<Duration>,
+    pub last_error: Option<String>,
+}
+
+struct Job {
+    name: String,
+    schedule: Schedule,
+    run: JobFn,
+}
+
+/// Runs named periodic jobs until the runtime shuts down
+#[derive(Clone)]
+pub struct Scheduler {
+    jobs: Arc<Mutex<Vec<Job>>>,
+    statuses: Arc<DashMap<String, JobStatus>>,
+    shutdown: broadcast::Sender<()>,
+}
+
+impl Scheduler {
+    pub fn new(shutdown: broadcast::Sender<()>) -> Self {
+        Self {
+            jobs: Arc::new(Mutex::new(Vec::new())),
+            statuses: Arc::new(DashMap::new()),
+            shutdown,
+        }
+    }
+
+    /// Registers a job, it only runs once the scheduler is started
+    pub fn add_job<F, Fut>(&self, name: &str, schedule: Schedule, job: F)
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = BotResult<()>> + Send + 'static,
+    {
+        self.statuses.insert(
+            name.to_string(),
+            JobStatus {
+                name: name.to_string(),
+                schedule: schedule.to_string(),
+                runs: 0,
+                last_run: None,
+                last_duration: None,
+                last_error: None,
+            },
+        );
+
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner()).push(Job {
+            name: name.to_string(),
+            schedule,
+            run: Arc::new(move || Box::pin(job())),
+        });
+    }
+
+    /// Spawns every registered job, each one waits for its first scheduled time before running
+    pub fn start(&self) {
+        let jobs = std::mem::take(&mut *self.jobs.lock().unwrap_or_else(|e| e.into_inner()));
+
+        for job in jobs {
+            info!("Scheduling job {} ({})", job.name, job.schedule);
+            tokio::spawn(Self::run_job(
+                job,
+                Arc::clone(&self.statuses),
+                self.shutdown.subscribe(),
+            ));
+        }
+    }
+
+    async fn run_job(job: Job, statuses: Arc<DashMap<String, JobStatus>>, mut shutdown: broadcast::Receiver<()>) {
+        loop {
+            let delay = job.schedule.delay_from(Utc::now());
+
+            tokio::select! {
+                _ = shutdown.recv() => {
+                    info!("Job {} stopped", job.name);
+                    return;
+                }
+                _ = tokio::time::sleep(delay) => {}
+            }
+
+            debug!("Running job {} ...", job.name);
+            let started_at = Utc::now();
+            let timer = std::time::Instant::now();
+            let result = (job.run)().await;
+            let duration = timer.elapsed();
+
+            if let Err(e) = &result {
+                error!("Job {} failed: {}", job.name, e);
+            }
+
+            if let Some(mut status) = statuses.get_mut(&job.name) {
+                status.runs += 1;
+                status.last_run = Some(started_at);
+                status.last_duration = Some(duration);
+                status.last_error = result.err().map(|e| e.to_string());
+            }
+        }
+    }
+
+    /// The status of every registered job, sorted by name
+    pub fn job_statuses(&self) -> Vec<JobStatus> {
+        let mut statuses = self
+            .statuses
+            .iter()
+            .map(|status| status.value().clone())
+            .collect::<Vec<_>>();
+        statuses.sort_by(|a, b| a.name.cmp(&b.name));
+        statuses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::error::BotError;
+
+    #[test]
+    fn test_cron_next_after() {
+        let now = Utc.with_ymd_and_hms(2025, 1, 1, 10, 7, 30).unwrap();
+        let cases = [
+            ("* * * * *", Utc.with_ymd_and_hms(2025, 1, 1, 10, 8, 0).unwrap()),
+            ("*/15 * * * *", Utc.with_ymd_and_hms(2025, 1, 1, 10, 15, 0).unwrap()),
+            ("0 3 * * *", Utc.with_ymd_and_hms(2025, 1, 2, 3, 0, 0).unwrap()),
+            ("30 9 1,15 * *", Utc.with_ymd_and_hms(2025, 1, 15, 9, 30, 0).unwrap()),
+            // 2025-01-05 is a Sunday
+            ("0 0 * * 7", Utc.with_ymd_and_hms(2025, 1, 5, 0, 0, 0).unwrap()),
+            ("0 12 * 3-4 1-5", Utc.with_ymd_and_hms(2025, 3, 3, 12, 0, 0).unwrap()),
+        ];
+
+        for (expression, expected) in cases {
+            let cron = expression.parse::<CronSchedule>().unwrap();
+            assert_eq!(cron.next_after(now), Some(expected), "{}", expression);
+        }
+
+        for expression in ["* * * *", "60 * * * *", "*/0 * * * *", "5-1 * * * *", "a * * * *"] {
+            assert!(expression.parse::<CronSchedule>().is_err(), "{}", expression);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_scheduler_records_runs_and_stops_on_shutdown() {
+        assert!(Schedule::every(0).is_err());
+        assert_eq!(
+            Schedule::every(60).unwrap(),
+            Schedule::Interval(Duration::from_secs(60))
+        );
+
+        let (shutdown, _) = broadcast::channel(1);
+        let scheduler = Scheduler::new(shutdown.clone());
+        let runs = Arc::new(AtomicUsize::new(0));
+
+        let counter = Arc::clone(&runs);
+        scheduler.add_job("failing", Schedule::Interval(Duration::from_millis(100)), move || {
+            let counter = Arc::clone(&counter);
+            async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Err(BotError::Other(anyhow::anyhow!("boom")))
+            }
+        });
+        scheduler.start();
+
+        // The clock is paused, sleeping advances it straight to the next timer
+        tokio::time::sleep(Duration::from_millis(250)).await;
+
+        let status = &scheduler.job_statuses()[0];
+        assert_eq!(status.name, "failing");
+        assert_eq!(status.schedule, "every 100ms");
+        assert_eq!(status.runs, 2);
+        assert!(status.last_run.is_some());
+        assert!(status.last_error.as_deref().unwrap().contains("boom"));
+
+        shutdown.send(()).unwrap();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+}
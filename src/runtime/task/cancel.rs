@@ -0,0 +1,116 @@
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use tokio::sync::watch;
+
+/// Asks a task to stop, workers check it between steps and race long running steps against it
+#[derive(Debug, Clone)]
+pub struct CancellationToken {
+    cancelled: Arc<watch::Sender<bool>>,
+}
+
+impl Default for CancellationToken {
+    fn default() -> Self {
+        Self {
+            cancelled: Arc::new(watch::channel(false).0),
+        }
+    }
+}
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.cancelled.send_replace(true);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.cancelled.borrow()
+    }
+
+    /// Resolves once the token is cancelled
+    pub async fn cancelled(&self) {
+        let mut rx = self.cancelled.subscribe();
+        // The sender lives as long as the token, so this only returns once cancelled
+        let _ = rx.wait_for(|cancelled| *cancelled).await;
+    }
+}
+
+/// The cancellation tokens of the tasks that are queued or running in this process
+#[derive(Clone, Default)]
+pub struct Cancellations {
+    tokens: Arc<DashMap<String, (u64, CancellationToken)>>, // task id -> (user id, token)
+}
+
+impl Cancellations {
+    /// The token of a task, created on first use so that tasks redelivered after a restart get one too
+    pub fn token(&self, task_id: &str, user_id: u64) -> CancellationToken {
+        self.tokens
+            .entry(task_id.to_string())
+            .or_insert_with(|| (user_id, CancellationToken::default()))
+            .1
+            .clone()
+    }
+
+    /// Cancels a task on behalf of a user, `false` if it is unknown or belongs to someone else
+    pub fn cancel(&self, task_id: &str, user_id: u64) -> bool {
+        match self.tokens.get(task_id) {
+            Some(entry) if entry.0 == user_id => {
+                entry.1.cancel();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Cancels every task of a user, returning their ids
+    pub fn cancel_user(&self, user_id: u64) -> Vec<String> {
+        self.tokens
+            .iter()
+            .filter(|entry| entry.value().0 == user_id)
+            .map(|entry| {
+                entry.value().1.cancel();
+                entry.key().clone()
+            })
+            .collect()
+    }
+
+    pub fn remove(&self, task_id: &str) {
+        self.tokens.remove(task_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cancel_only_own_tasks() {
+        let cancellations = Cancellations::default();
+        let first = cancellations.token("a", 1);
+        let second = cancellations.token("b", 1);
+        let other = cancellations.token("c", 2);
+
+        assert!(!cancellations.cancel("a", 2));
+        assert!(!first.is_cancelled());
+
+        let waiting = tokio::spawn({
+            let first = first.clone();
+            async move { first.cancelled().await }
+        });
+        assert!(cancellations.cancel("a", 1));
+        tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let mut cancelled = cancellations.cancel_user(1);
+        cancelled.sort();
+        assert_eq!(cancelled, ["a", "b"]);
+        assert!(second.is_cancelled());
+        assert!(!other.is_cancelled());
+
+        cancellations.remove("a");
+        assert!(!cancellations.cancel("a", 1));
+    }
+}
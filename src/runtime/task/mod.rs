@@ -1,14 +1,41 @@
 use serde::{Deserialize, Serialize};
-use tokio::sync::oneshot;
 use uuid::Uuid;
 
+mod cancel;
+mod retry;
+
+pub use cancel::{CancellationToken, Cancellations};
+pub use retry::{DeadLetter, RetryPolicy};
+
 use crate::{
     context::UserTier,
     platform::{DownloadState, MediaFile, Platform, PostDownloadState},
+    service::Language,
 };
 
 pub trait Task: Send + Sync + 'static {
     type Result: Send + Sync + 'static;
+
+    /// Identifies the task across queue backends, used to acknowledge it once processed
+    fn id(&self) -> &str;
+
+    /// Who queued the task, queues limit and interleave the tasks of each user
+    fn user_id(&self) -> u64;
+
+    /// Failed attempts so far, stored with the task so that retries survive a restart
+    fn attempts(&self) -> u32;
+
+    fn record_failure(&mut self);
+
+    /// Clears the failed attempts, when an admin requeues a dead letter
+    fn reset_attempts(&mut self);
+
+    /// A short description for admins inspecting dead letters
+    fn summary(&self) -> String;
+
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::default()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, Ord, PartialEq, PartialOrd)]
@@ -18,11 +45,8 @@ pub struct TaskContext {
     pub message_id: i32,
     pub user_tier: UserTier,
     pub platform: Platform,
-}
-
-pub struct TaskWithResult<T: Task> {
-    pub task: T,
-    pub result_tx: oneshot::Sender<T::Result>,
+    /// Messages about the task are rendered in the language of the user who queued it
+    pub language: Language,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, Ord, PartialEq, PartialOrd)]
@@ -31,10 +55,43 @@ pub struct DownloadTask {
     pub url: String,
     pub context: TaskContext,
     pub created_at: chrono::DateTime<chrono::Utc>,
+    /// Part of a bulk download: the media is sent without a confirmation preview and
+    /// progress is reported by whoever enqueued the task
+    pub bulk: bool,
+    #[serde(default)]
+    pub attempts: u32,
+    /// Identifier of the linked resource, see `PlatformRegistry::generate_identifier`. Set for links the user
+    /// sent so the same one is not queued twice.
+    #[serde(default)]
+    pub resource_id: Option<String>,
 }
 
 impl Task for DownloadTask {
     type Result = DownloadState;
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn user_id(&self) -> u64 {
+        self.context.user_id
+    }
+
+    fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    fn record_failure(&mut self) {
+        self.attempts += 1;
+    }
+
+    fn reset_attempts(&mut self) {
+        self.attempts = 0;
+    }
+
+    fn summary(&self) -> String {
+        format!("download {} for user {}", self.url, self.context.user_id)
+    }
 }
 
 impl DownloadTask {
@@ -44,8 +101,23 @@ impl DownloadTask {
             url,
             context,
             created_at: chrono::Utc::now(),
+            bulk: false,
+            attempts: 0,
+            resource_id: None,
         }
     }
+
+    pub fn new_bulk(url: String, context: TaskContext) -> Self {
+        Self {
+            bulk: true,
+            ..Self::new(url, context)
+        }
+    }
+
+    pub fn with_resource_id(mut self, resource_id: String) -> Self {
+        self.resource_id = Some(resource_id);
+        self
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, Ord, PartialEq, PartialOrd)]
@@ -54,10 +126,36 @@ pub struct PostDownloadTask {
     pub media_file: MediaFile,
     pub context: TaskContext,
     pub created_at: chrono::DateTime<chrono::Utc>,
+    #[serde(default)]
+    pub attempts: u32,
 }
 
 impl Task for PostDownloadTask {
     type Result = PostDownloadState;
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn user_id(&self) -> u64 {
+        self.context.user_id
+    }
+
+    fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    fn record_failure(&mut self) {
+        self.attempts += 1;
+    }
+
+    fn reset_attempts(&mut self) {
+        self.attempts = 0;
+    }
+
+    fn summary(&self) -> String {
+        format!("send {} to user {}", self.media_file.id, self.context.user_id)
+    }
 }
 
 impl PostDownloadTask {
@@ -67,6 +165,7 @@ impl PostDownloadTask {
             media_file,
             context,
             created_at: chrono::Utc::now(),
+            attempts: 0,
         }
     }
 }
@@ -0,0 +1,74 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// How often and how fast a failed task is retried before it ends up in the dead letters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Whether a task that already failed `attempts` times gets another one
+    pub fn should_retry(&self, attempts: u32) -> bool {
+        attempts < self.max_attempts
+    }
+
+    /// Exponential backoff with jitter, somewhere between half and the full delay so that tasks failing
+    /// together don't retry together
+    pub fn backoff(&self, attempts: u32) -> Duration {
+        let exponent = attempts.saturating_sub(1).min(16);
+        let delay = self.base_delay.saturating_mul(1 << exponent).min(self.max_delay);
+
+        let millis = delay.as_millis() as u64;
+        Duration::from_millis(rand::thread_rng().gen_range(millis / 2..=millis))
+    }
+}
+
+/// A task that failed permanently, kept for admins to inspect and requeue
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub task_id: String,
+    pub summary: String,
+    pub error: String,
+    pub attempts: u32,
+    pub failed_at: DateTime<Utc>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(5),
+        };
+
+        for (attempts, max) in [(1, 2), (2, 4), (3, 5), (30, 5)] {
+            let delay = policy.backoff(attempts);
+            assert!(delay >= Duration::from_millis(max * 500), "{:?}", delay);
+            assert!(delay <= Duration::from_secs(max), "{:?}", delay);
+        }
+
+        assert!(policy.should_retry(2));
+        assert!(!policy.should_retry(3));
+    }
+}
@@ -1,136 +1,410 @@
-use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc,
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
 };
 
 use async_trait::async_trait;
+use chrono::Utc;
 use teloxide::{
     adaptors::Throttle,
-    payloads::{EditMessageTextSetters, SendMessageSetters, SendPhotoSetters},
+    payloads::{EditMessageCaptionSetters, EditMessageTextSetters, SendMessageSetters, SendPhotoSetters},
     prelude::Requester,
     types::{ChatId, InputFile, MessageId},
-    Bot,
+    Bot, RequestError,
 };
-use tokio::sync::broadcast;
+use tokio::{sync::broadcast, task::JoinHandle};
 
 use crate::{
     handler::{get_confirm_download_keyboard, get_download_ask_for_link_keyboard, get_main_menu_keyboard},
-    platform::{traits::PlatformCapability, DownloadState, Platform, PlatformInstagram, PostDownloadState},
+    platform::{
+        traits::PlatformCapability, DownloadState, FailureReason, MediaFile, Platform, PlatformInstagram,
+        PostDownloadState,
+    },
     runtime::{
+        progress::{Progress, ProgressReporter},
         queue::TaskQueueManager,
-        task::{DownloadTask, PostDownloadTask},
+        task::{CancellationToken, DownloadTask, PostDownloadTask},
         RuntimeError,
     },
     state::AppState,
 };
 
-use super::Worker;
+use super::{catch_panic, supervise, SlotHealth, Slots, StageTimeouts, Worker};
+
+/// Replaces a preview that can no longer be confirmed with a notice, photo previews only have a caption
+pub async fn replace_preview(
+    bot: &Throttle<Bot>,
+    chat_id: ChatId,
+    message_id: MessageId,
+    is_photo: bool,
+    text: impl Into<String>,
+    locale: &str,
+) -> Result<(), RequestError> {
+    if is_photo {
+        bot.edit_message_caption(chat_id, message_id)
+            .caption(text)
+            .reply_markup(get_main_menu_keyboard(locale))
+            .await?;
+    } else {
+        bot.edit_message_text(chat_id, message_id, text)
+            .reply_markup(get_main_menu_keyboard(locale))
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Expires the preview of a pending confirmation that was dropped, the user may have deleted it already
+pub async fn expire_pending_preview(bot: &Throttle<Bot>, task: &PostDownloadTask) {
+    let locale = task.context.language.locale();
+
+    if let Err(e) = replace_preview(
+        bot,
+        ChatId(task.context.chat_id),
+        MessageId(task.context.message_id),
+        task.media_file.thumbnail.is_some(),
+        t!("callbacks.download.expired", locale = locale),
+        locale,
+    )
+    .await
+    {
+        debug!("Failed to expire preview of {}: {}", task.media_file.id, e);
+    }
+}
+
+/// Sends the media within `timeout`. Once a chunk is in the chat another attempt would send it twice, so
+/// failures from then on are not retried, and failures after everything was sent are only logged.
+async fn send_media(
+    platform: &dyn PlatformCapability,
+    bot: &Throttle<Bot>,
+    chat_id: ChatId,
+    telegram_user_id: &str,
+    media_file: &MediaFile,
+    timeout: Duration,
+    progress: &(dyn Fn(usize, usize) + Send + Sync),
+) -> Result<(), RuntimeError> {
+    let sent = AtomicUsize::new(0);
+    let report = |done, total| {
+        sent.store(done, Ordering::SeqCst);
+        progress(done, total);
+    };
+
+    let result = match tokio::time::timeout(
+        timeout,
+        platform.send_to_telegram(bot, chat_id, telegram_user_id, media_file, &report),
+    )
+    .await
+    {
+        Ok(result) => result.map_err(|e| RuntimeError::telegram("Failed to send media", e.as_ref())),
+        Err(_) => Err(RuntimeError::Timeout("send".into())),
+    };
+
+    let total = media_file.items.len();
+    match (result, sent.load(Ordering::SeqCst)) {
+        (Err(e), sent) if sent == total => {
+            warn!("Sent all {} items of {} but: {}", total, media_file.id, e);
+            Ok(())
+        }
+        (Err(e), sent) if sent > 0 => Err(RuntimeError::PartiallySent {
+            sent,
+            total,
+            reason: e.to_string(),
+        }),
+        (result, _) => result,
+    }
+}
 
 #[derive(Clone)]
 pub struct DownloadWorker {
     name: String,
-    concurrency: usize,
     queue_manager: TaskQueueManager,
     bot: Throttle<Bot>,
+    timeouts: StageTimeouts,
     shutdown: broadcast::Sender<()>,
     running: Arc<AtomicBool>,
+    slots: Slots,
+    loops: Arc<Mutex<Vec<JoinHandle<()>>>>,
 }
 
 impl DownloadWorker {
-    pub fn new(name: &str, concurrency: usize, queue_manager: TaskQueueManager, bot: Throttle<Bot>) -> Self {
+    pub fn new(
+        name: &str,
+        concurrency: usize,
+        queue_manager: TaskQueueManager,
+        bot: Throttle<Bot>,
+        timeouts: StageTimeouts,
+    ) -> Self {
         let (shutdown, _) = broadcast::channel(1);
         Self {
             name: name.to_string(),
-            concurrency,
             queue_manager,
             bot,
+            timeouts,
             shutdown,
             running: Arc::new(AtomicBool::new(false)),
+            slots: Slots::new(name, concurrency),
+            loops: Arc::new(Mutex::new(Vec::new())),
         }
     }
 
-    async fn process_task(&self, task: DownloadTask) -> Result<DownloadState, RuntimeError> {
+    async fn process_task(&self, task: DownloadTask, token: &CancellationToken) -> Result<DownloadState, RuntimeError> {
+        if token.is_cancelled() {
+            return Err(RuntimeError::Cancelled);
+        }
+
         let platform_registry = AppState::get()?.platform_registry;
+        let locale = task.context.language.locale();
         let telegram_user_id = task.context.user_id.to_string();
 
-        let result = match task.context.platform {
-            crate::platform::Platform::Instagram => {
-                info!("Processing Instagram download task");
-                platform_registry
-                    .handle_download::<PlatformInstagram>(&task.context.platform, &task.url, &telegram_user_id)
-                    .await
-                    .map_err(|e| RuntimeError::TaskError(e.to_string()))?
+        let download = async {
+            match task.context.platform {
+                crate::platform::Platform::Instagram => {
+                    info!("Processing Instagram download task");
+                    platform_registry
+                        .handle_download::<PlatformInstagram>(
+                            &task.context.platform,
+                            &task.url,
+                            &telegram_user_id,
+                            !task.bulk,
+                        )
+                        .await
+                        .unwrap_or_else(|e| {
+                            error!("Failed to handle download: {}", e);
+                            DownloadState::Error(FailureReason::from_error(&e))
+                        })
+                }
+                _ => {
+                    error!("Not implemented yet");
+                    DownloadState::Error(FailureReason::Unknown)
+                }
             }
-            _ => {
-                error!("Not implemented yet");
-                DownloadState::Error
+        };
+
+        // Nothing was shown to the user yet, so the download can simply be dropped
+        let result = tokio::select! {
+            result = tokio::time::timeout(self.timeouts.fetch, download) => {
+                result.map_err(|_| RuntimeError::Timeout("fetch".into()))?
             }
+            _ = token.cancelled() => return Err(RuntimeError::Cancelled),
         };
 
+        // Transient failures are retried by the queue, the user only hears about the last one
+        if let DownloadState::Error(reason) = result {
+            if reason.is_retryable() {
+                return Err(RuntimeError::DownloadFailed(reason));
+            }
+        }
+
+        if task.bulk {
+            return self.deliver_bulk_result(&task, result).await;
+        }
+
         match result {
             crate::platform::DownloadState::RateLimited => {
                 self.bot
                     .edit_message_text(
                         ChatId(task.context.chat_id),
                         MessageId(task.context.message_id),
-                        t!("messages.download.download_limit_reached"),
+                        t!("messages.download.download_limit_reached", locale = locale),
                     )
-                    .reply_markup(get_main_menu_keyboard())
+                    .reply_markup(get_main_menu_keyboard(locale))
                     .await
-                    .unwrap();
+                    .map_err(|e| RuntimeError::telegram("Failed to edit message", &e))?;
 
                 Ok(DownloadState::RateLimited)
             }
             crate::platform::DownloadState::Success(media_info) => {
-                let queue_manager = &AppState::get().unwrap().runtime.queue_manager;
+                let queue_manager = &AppState::get()?.runtime.queue_manager;
 
-                queue_manager.add_pending_confirmation(media_info.clone(), task.context.clone());
+                let evicted = queue_manager
+                    .add_pending_confirmation(media_info.clone(), task.context.clone())
+                    .await?;
 
-                let preview_text = media_info.get_preview_text();
+                for evicted in evicted {
+                    expire_pending_preview(&self.bot, &evicted).await;
+                }
 
-                if let Some(thumbnail_url) = &media_info.thumbnail {
-                    self.bot
-                        .delete_message(ChatId(task.context.chat_id), MessageId(task.context.message_id))
-                        .await
-                        .map_err(|e| RuntimeError::TaskError(format!("Failed to delete message: {}", e)))?;
+                let preview_text = media_info.get_preview_text(locale);
 
-                    let new_message = self
+                // The status message is replaced only once the photo is out, thumbnail links of cached media
+                // may have expired and the preview is sent as text then
+                let photo = match &media_info.thumbnail {
+                    Some(thumbnail_url) => match self
                         .bot
                         .send_photo(ChatId(task.context.chat_id), InputFile::url(thumbnail_url.clone()))
-                        .caption(preview_text)
-                        .reply_markup(get_confirm_download_keyboard())
+                        .caption(preview_text.clone())
+                        .reply_markup(get_confirm_download_keyboard(locale))
                         .await
-                        .map_err(|e| RuntimeError::TaskError(format!("Failed to send preview message: {}", e)))?;
+                    {
+                        Ok(message) => Some(message),
+                        Err(e) => {
+                            warn!("Failed to send preview photo of {}: {}", media_info.id, e);
+                            None
+                        }
+                    },
+                    None => None,
+                };
+
+                if let Some(new_message) = photo {
+                    if let Err(e) = self
+                        .bot
+                        .delete_message(ChatId(task.context.chat_id), MessageId(task.context.message_id))
+                        .await
+                    {
+                        debug!("Failed to delete status message of task {}: {}", task.id, e);
+                    }
 
                     let mut updated_context = task.context.clone();
                     updated_context.message_id = new_message.id.0;
-                    queue_manager.update_pending_confirmation_context(media_info.id.clone(), updated_context);
+                    queue_manager
+                        .update_pending_confirmation_context(media_info.id.clone(), updated_context)
+                        .await?;
                 } else {
+                    if media_info.thumbnail.is_some() {
+                        // Expiring the preview has to edit its text then, not a caption
+                        let text_preview = MediaFile {
+                            thumbnail: None,
+                            ..media_info.clone()
+                        };
+                        queue_manager
+                            .add_pending_confirmation(text_preview, task.context.clone())
+                            .await?;
+                    }
+
                     self.bot
                         .edit_message_text(
                             ChatId(task.context.chat_id),
                             MessageId(task.context.message_id),
                             preview_text,
                         )
-                        .reply_markup(get_confirm_download_keyboard())
+                        .reply_markup(get_confirm_download_keyboard(locale))
                         .await
-                        .map_err(|e| RuntimeError::TaskError(format!("Failed to edit message: {}", e)))?;
+                        .map_err(|e| RuntimeError::telegram("Failed to edit message", &e))?;
                 }
 
                 Ok(DownloadState::Success(media_info))
             }
-            crate::platform::DownloadState::Error => {
+            crate::platform::DownloadState::Error(reason) => {
                 self.bot
                     .edit_message_text(
                         ChatId(task.context.chat_id),
                         MessageId(task.context.message_id),
-                        t!("messages.download.error"),
+                        t!(reason.message_key(), locale = locale),
                     )
-                    .reply_markup(get_main_menu_keyboard())
+                    .reply_markup(get_main_menu_keyboard(locale))
                     .await
-                    .map_err(|e| RuntimeError::TaskError(format!("Something went wrong: {}", e)))?;
+                    .map_err(|e| RuntimeError::telegram("Something went wrong", &e))?;
+
+                Ok(DownloadState::Error(reason))
+            }
+            crate::platform::DownloadState::Cancelled => Err(RuntimeError::Cancelled),
+        }
+    }
 
-                Ok(DownloadState::Error)
+    /// Tells the user about a task that ran out of attempts
+    async fn report_failure(&self, task: &DownloadTask, error: &RuntimeError) -> DownloadState {
+        let reason = match error {
+            RuntimeError::DownloadFailed(reason) => *reason,
+            _ => FailureReason::Unknown,
+        };
+
+        // Bulk downloads are reported by whoever enqueued them
+        if !task.bulk {
+            let locale = task.context.language.locale();
+            if let Err(e) = self
+                .bot
+                .edit_message_text(
+                    ChatId(task.context.chat_id),
+                    MessageId(task.context.message_id),
+                    t!(reason.message_key(), locale = locale),
+                )
+                .reply_markup(get_main_menu_keyboard(locale))
+                .await
+            {
+                warn!("Failed to report failure of task {}: {}", task.id, e);
+            }
+        }
+
+        DownloadState::Error(reason)
+    }
+
+    /// Bulk downloads skip the preview, the media is sent right away
+    async fn deliver_bulk_result(
+        &self,
+        task: &DownloadTask,
+        result: DownloadState,
+    ) -> Result<DownloadState, RuntimeError> {
+        if let DownloadState::Success(media_file) = &result {
+            let platform = AppState::get()?
+                .platform_registry
+                .get_platform::<PlatformInstagram>(&task.context.platform)
+                .ok_or_else(|| RuntimeError::Other("Platform not found".into()))?;
+
+            // Progress of bulk downloads is reported per task by whoever enqueued them
+            send_media(
+                platform.as_ref(),
+                &self.bot,
+                ChatId(task.context.chat_id),
+                &task.context.user_id.to_string(),
+                media_file,
+                self.timeouts.send,
+                &|_, _| {},
+            )
+            .await?;
+        }
+
+        Ok(result)
+    }
+
+    /// Takes tasks until the worker is stopped, the supervisor starts it again if it panics
+    async fn run_slot(self: Arc<Self>, slot: usize) {
+        let worker_name = self.slots.name(slot);
+        let queue_manager = self.queue_manager.clone();
+        let mut rx = self.shutdown.subscribe();
+
+        while self.running.load(Ordering::SeqCst) {
+            tokio::select! {
+                task = queue_manager.pop_download_task() => {
+                    let task_id = task.id.clone();
+                    // The status message is the worker's from here on
+                    queue_manager.stop_watching_position(&task_id).await;
+                    let token = queue_manager.cancellation_token(&task_id, task.context.user_id);
+                    self.slots.update(slot, |health| health.busy_since = Some(Utc::now()));
+
+                    let processed = catch_panic({
+                        let worker = self.clone();
+                        let task = task.clone();
+                        async move { worker.process_task(task, &token).await }
+                    })
+                    .await;
+
+                    let result = match processed {
+                        Ok(result) => Some(result),
+                        Err(RuntimeError::Cancelled) => {
+                            info!("Worker {} cancelled task {}", worker_name, task_id);
+                            Some(DownloadState::Cancelled)
+                        }
+                        Err(e) => {
+                            error!("Worker {} failed to process task: {}", worker_name, e);
+                            match queue_manager.fail_download_task(task, &e).await {
+                                Some(task) => Some(self.report_failure(&task, &e).await),
+                                None => None,
+                            }
+                        }
+                    };
+
+                    // Retried tasks are completed by a later attempt
+                    if let Some(result) = result {
+                        queue_manager.complete_download_task(&task_id, result).await;
+                    }
+                    self.slots.update(slot, |health| health.busy_since = None);
+                }
+                _ = rx.recv() => {
+                    break;
+                }
             }
         }
     }
@@ -148,41 +422,14 @@ impl Worker for DownloadWorker {
         }
 
         self.running.store(true, Ordering::SeqCst);
-        let running = self.running.clone();
-        let queue_manager = self.queue_manager.clone();
-        let worker_ref = Arc::new(self.clone());
-
-        for i in 0..self.concurrency {
-            let worker_name = format!("{}_{}", self.name, i);
-            let running = running.clone();
-            let queue_manager = queue_manager.clone();
-            let worker = worker_ref.clone();
-            let mut rx = self.shutdown.subscribe();
-
-            tokio::spawn(async move {
-                while running.load(Ordering::SeqCst) {
-                    tokio::select! {
-                        task = queue_manager.pop_download_task() => {
-                            if let Some(task_with_result) = task {
-                                let result = match worker.process_task(task_with_result.task).await {
-                                    Ok(result) => result,
-                                    Err(e) => {
-                                        error!("Worker {} failed to process task: {}", worker_name, e);
-                                        DownloadState::Error
-                                    }
-                                };
-
-                                if let Err(_e) = task_with_result.result_tx.send(result) {
-                                   error!("Failed to send task result");
-                                }
-                            }
-                        }
-                        _ = rx.recv() => {
-                            break;
-                        }
-                    }
-                }
+        let worker = Arc::new(self.clone());
+
+        for slot in 0..self.slots.len() {
+            let handle = supervise(self.slots.clone(), slot, self.running.clone(), {
+                let worker = worker.clone();
+                move || worker.clone().run_slot(slot)
             });
+            self.loops.lock().unwrap().push(handle);
         }
 
         Ok(())
@@ -191,85 +438,205 @@ impl Worker for DownloadWorker {
     async fn stop(&self) -> Result<(), RuntimeError> {
         self.running.store(false, Ordering::SeqCst);
         let _ = self.shutdown.send(());
+
+        // Idle loops leave right away, busy ones once their task is done
+        let loops = std::mem::take(&mut *self.loops.lock().unwrap());
+        for handle in loops {
+            let _ = handle.await;
+        }
         Ok(())
     }
 
     fn is_running(&self) -> bool {
         self.running.load(Ordering::SeqCst)
     }
+
+    fn slots(&self) -> Vec<SlotHealth> {
+        self.slots.snapshot()
+    }
 }
 
 #[derive(Clone)]
 pub struct PostDownloadWorker {
     name: String,
-    concurrency: usize,
     queue_manager: TaskQueueManager,
     bot: Throttle<Bot>,
+    timeouts: StageTimeouts,
     shutdown: broadcast::Sender<()>,
     running: Arc<AtomicBool>,
+    slots: Slots,
+    loops: Arc<Mutex<Vec<JoinHandle<()>>>>,
 }
 
 impl PostDownloadWorker {
-    pub fn new(name: &str, concurrency: usize, queue_manager: TaskQueueManager, bot: Throttle<Bot>) -> Self {
+    pub fn new(
+        name: &str,
+        concurrency: usize,
+        queue_manager: TaskQueueManager,
+        bot: Throttle<Bot>,
+        timeouts: StageTimeouts,
+    ) -> Self {
         let (shutdown, _) = broadcast::channel(1);
         Self {
             name: name.to_string(),
-            concurrency,
             queue_manager,
             bot,
+            timeouts,
             shutdown,
             running: Arc::new(AtomicBool::new(false)),
+            slots: Slots::new(name, concurrency),
+            loops: Arc::new(Mutex::new(Vec::new())),
         }
     }
 
-    async fn process_task(&self, task: PostDownloadTask) -> Result<PostDownloadState, RuntimeError> {
-        let platform_registry = AppState::get().unwrap().platform_registry;
+    async fn process_task(
+        &self,
+        task: PostDownloadTask,
+        token: &CancellationToken,
+    ) -> Result<PostDownloadState, RuntimeError> {
+        if token.is_cancelled() {
+            return Err(RuntimeError::Cancelled);
+        }
+
+        let platform_registry = AppState::get()?.platform_registry;
+        let locale = task.context.language.locale();
 
-        self.bot
+        // Already gone when this is a retry
+        if let Err(e) = self
+            .bot
             .delete_message(ChatId(task.context.chat_id), MessageId(task.context.message_id))
             .await
-            .unwrap();
+        {
+            debug!("Failed to delete preview of task {}: {}", task.id, e);
+        }
 
-        let downloading_msg = self
+        let status_msg = self
             .bot
-            .send_message(ChatId(task.context.chat_id), t!("callbacks.download.downloading"))
+            .send_message(
+                ChatId(task.context.chat_id),
+                t!("callbacks.download.downloading", locale = locale),
+            )
             .await
-            .unwrap();
+            .map_err(|e| RuntimeError::telegram("Failed to send message", &e))?;
 
-        self.bot
-            .delete_message(ChatId(task.context.chat_id), downloading_msg.id)
-            .await
-            .unwrap();
+        let reporter = ProgressReporter::spawn(
+            self.bot.clone(),
+            ChatId(task.context.chat_id),
+            status_msg.id,
+            None,
+            move |progress: &Progress| progress.message(locale),
+        );
 
-        match task.context.platform {
+        let sent = match task.context.platform {
             Platform::Instagram => {
                 let platform = platform_registry
                     .get_platform::<PlatformInstagram>(&task.context.platform)
-                    .unwrap();
-                platform
-                    .send_to_telegram(&self.bot, ChatId(task.context.chat_id), &task.media_file)
-                    .await
-                    .unwrap();
+                    .ok_or_else(|| RuntimeError::Other("Platform not found".into()));
+                let report = |sent, total| reporter.report(Progress::Sending { sent, total });
+                let user_id = task.context.user_id.to_string();
+
+                match platform {
+                    // Dropping the send stops it at its next request, whatever was sent already stays in the chat
+                    Ok(platform) => tokio::select! {
+                        result = send_media(
+                            platform.as_ref(),
+                            &self.bot,
+                            ChatId(task.context.chat_id),
+                            &user_id,
+                            &task.media_file,
+                            self.timeouts.send,
+                            &report,
+                        ) => result,
+                        _ = token.cancelled() => Err(RuntimeError::Cancelled),
+                    },
+                    Err(e) => Err(e),
+                }
             }
             Platform::Youtube => {
                 info!("Processing Youtube download task");
+                Ok(())
             }
             Platform::Bilibili => {
                 info!("Processing Bilibili download task");
+                Ok(())
             }
+        };
+
+        reporter.finish().await;
+
+        if let Err(e) = self
+            .bot
+            .delete_message(ChatId(task.context.chat_id), status_msg.id)
+            .await
+        {
+            debug!("Failed to delete status message of task {}: {}", task.id, e);
         }
 
-        self.bot
+        sent?;
+
+        // The media is out, a retry from here would send it twice
+        if let Err(e) = self
+            .bot
             .send_message(
                 ChatId(task.context.chat_id),
-                t!("callbacks.download.download_completed"),
+                t!("callbacks.download.download_completed", locale = locale),
             )
-            .reply_markup(get_download_ask_for_link_keyboard(task.context.platform))
+            .reply_markup(get_download_ask_for_link_keyboard(task.context.platform, locale))
             .await
-            .unwrap();
+        {
+            warn!("Failed to send completion message of task {}: {}", task.id, e);
+        }
 
         Ok(PostDownloadState::Success)
     }
+
+    /// Takes tasks until the worker is stopped, the supervisor starts it again if it panics
+    async fn run_slot(self: Arc<Self>, slot: usize) {
+        let worker_name = self.slots.name(slot);
+        let queue_manager = self.queue_manager.clone();
+        let mut rx = self.shutdown.subscribe();
+
+        while self.running.load(Ordering::SeqCst) {
+            tokio::select! {
+                task = queue_manager.pop_post_download_task() => {
+                    let task_id = task.id.clone();
+                    let token = queue_manager.cancellation_token(&task_id, task.context.user_id);
+                    self.slots.update(slot, |health| health.busy_since = Some(Utc::now()));
+
+                    let processed = catch_panic({
+                        let worker = self.clone();
+                        let task = task.clone();
+                        async move { worker.process_task(task, &token).await }
+                    })
+                    .await;
+
+                    let result = match processed {
+                        Ok(result) => Some(result),
+                        Err(RuntimeError::Cancelled) => {
+                            info!("Worker {} cancelled task {}", worker_name, task_id);
+                            Some(PostDownloadState::Cancelled)
+                        }
+                        Err(e) => {
+                            error!("Worker {} failed to process task: {}", worker_name, e);
+                            queue_manager
+                                .fail_post_download_task(task, &e)
+                                .await
+                                .map(|_| PostDownloadState::Error)
+                        }
+                    };
+
+                    // Retried tasks are completed by a later attempt
+                    if let Some(result) = result {
+                        queue_manager.complete_post_download_task(&task_id, result).await;
+                    }
+                    self.slots.update(slot, |health| health.busy_since = None);
+                }
+                _ = rx.recv() => {
+                    break;
+                }
+            }
+        }
+    }
 }
 
 #[async_trait]
@@ -284,41 +651,14 @@ impl Worker for PostDownloadWorker {
         }
 
         self.running.store(true, Ordering::SeqCst);
-        let running = self.running.clone();
-        let queue_manager = self.queue_manager.clone();
-        let worker_ref = Arc::new(self.clone());
-
-        for i in 0..self.concurrency {
-            let worker_name = format!("{}_{}", self.name, i);
-            let running = running.clone();
-            let queue_manager = queue_manager.clone();
-            let worker = worker_ref.clone();
-            let mut rx = self.shutdown.subscribe();
-
-            tokio::spawn(async move {
-                while running.load(Ordering::SeqCst) {
-                    tokio::select! {
-                        task = queue_manager.pop_post_download_task() => {
-                            if let Some(task_with_result) = task {
-                                let result = match worker.process_task(task_with_result.task).await {
-                                    Ok(result) => result,
-                                    Err(e) => {
-                                        error!("Worker {} failed to process task: {}", worker_name, e);
-                                        PostDownloadState::Error
-                                    }
-                                };
-
-                                if let Err(_e) = task_with_result.result_tx.send(result) {
-                                   error!("Failed to send task result");
-                                }
-                            }
-                        }
-                        _ = rx.recv() => {
-                            break;
-                        }
-                    }
-                }
+        let worker = Arc::new(self.clone());
+
+        for slot in 0..self.slots.len() {
+            let handle = supervise(self.slots.clone(), slot, self.running.clone(), {
+                let worker = worker.clone();
+                move || worker.clone().run_slot(slot)
             });
+            self.loops.lock().unwrap().push(handle);
         }
 
         Ok(())
@@ -327,10 +667,138 @@ impl Worker for PostDownloadWorker {
     async fn stop(&self) -> Result<(), RuntimeError> {
         self.running.store(false, Ordering::SeqCst);
         let _ = self.shutdown.send(());
+
+        // Idle loops leave right away, busy ones once their task is done
+        let loops = std::mem::take(&mut *self.loops.lock().unwrap());
+        for handle in loops {
+            let _ = handle.await;
+        }
         Ok(())
     }
 
     fn is_running(&self) -> bool {
         self.running.load(Ordering::SeqCst)
     }
+
+    fn slots(&self) -> Vec<SlotHealth> {
+        self.slots.snapshot()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::Any;
+
+    use teloxide::{adaptors::throttle::Limits, requests::RequesterExt};
+
+    use super::*;
+    use crate::{
+        error::HandlerResult,
+        platform::{MediaContentType, MediaFileItem, MediaType, PlatformError, PlatformIdentifier},
+    };
+
+    /// Sends `fails_after` items, then fails
+    struct FlakyPlatform {
+        fails_after: usize,
+    }
+
+    #[async_trait]
+    impl PlatformCapability for FlakyPlatform {
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn platform_id(&self) -> Platform {
+            Platform::Instagram
+        }
+
+        fn platform_name(&self) -> &str {
+            "flaky"
+        }
+
+        async fn parse_url(&self, url_str: &str) -> Result<PlatformIdentifier, PlatformError> {
+            Err(PlatformError::InvalidPlatform(url_str.to_string()))
+        }
+
+        async fn fetch_resource(&self, _: &PlatformIdentifier, _: &str) -> HandlerResult<MediaFile> {
+            unimplemented!()
+        }
+
+        async fn pre_process(&self, _: &Throttle<Bot>, _: ChatId, media_info: &MediaFile) -> HandlerResult<MediaFile> {
+            Ok(media_info.clone())
+        }
+
+        async fn send_to_telegram(
+            &self,
+            _: &Throttle<Bot>,
+            _: ChatId,
+            _: &str,
+            media_file: &MediaFile,
+            progress: &(dyn Fn(usize, usize) + Send + Sync),
+        ) -> HandlerResult<()> {
+            let total = media_file.items.len();
+            for sent in 1..=self.fails_after {
+                progress(sent, total);
+            }
+            Err(PlatformError::ResourceError("connection reset".into()).into())
+        }
+
+        async fn post_process(&self, _: &Throttle<Bot>, _: ChatId, _: &MediaFile) -> HandlerResult<()> {
+            Ok(())
+        }
+    }
+
+    fn media_file(items: usize) -> MediaFile {
+        MediaFile {
+            id: "id".to_string(),
+            created_at: Utc::now(),
+            title: None,
+            description: None,
+            author: None,
+            content_type: MediaContentType::Album,
+            thumbnail: None,
+            items: (0..items)
+                .map(|i| MediaFileItem {
+                    id: i.to_string(),
+                    media_type: MediaType::Image,
+                    url: url::Url::parse(&format!("https://example.com/{}", i)).unwrap(),
+                    duration: None,
+                    created_at: Utc::now(),
+                    telegram_file_id: None,
+                })
+                .collect(),
+            platform: Platform::Instagram,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_failures_are_only_retried_before_anything_was_sent() {
+        let bot = Bot::new("token").throttle(Limits::default());
+        let media_file = media_file(3);
+        let send = |fails_after| {
+            let bot = bot.clone();
+            let media_file = media_file.clone();
+            async move {
+                let platform = FlakyPlatform { fails_after };
+                send_media(
+                    &platform,
+                    &bot,
+                    ChatId(1),
+                    "1",
+                    &media_file,
+                    Duration::from_secs(1),
+                    &|_, _| {},
+                )
+                .await
+            }
+        };
+
+        assert!(send(0).await.unwrap_err().is_retryable());
+        assert!(matches!(
+            send(2).await,
+            Err(RuntimeError::PartiallySent { sent: 2, total: 3, .. })
+        ));
+        assert!(!send(2).await.unwrap_err().is_retryable());
+        assert!(send(3).await.is_ok());
+    }
 }
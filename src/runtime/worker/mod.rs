@@ -1,38 +1,180 @@
 pub mod download;
 
 use async_trait::async_trait;
-use std::collections::HashMap;
-use tokio::sync::broadcast;
+use chrono::{DateTime, Utc};
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::task::{JoinError, JoinHandle, JoinSet};
+
+use crate::config::QueueConfig;
 
 use super::RuntimeError;
 
+/// How long a slot whose loop panicked waits before it is started again, so a loop that keeps panicking
+/// doesn't spin
+const RESPAWN_DELAY: Duration = Duration::from_secs(1);
+
+/// How long each stage of a task may take before the attempt fails and is retried
+#[derive(Debug, Clone, Copy)]
+pub struct StageTimeouts {
+    /// Fetching the resource from the platform
+    pub fetch: Duration,
+    /// Sending the media to the chat
+    pub send: Duration,
+}
+
+impl From<&QueueConfig> for StageTimeouts {
+    fn from(config: &QueueConfig) -> Self {
+        Self {
+            fetch: Duration::from_secs(config.fetch_timeout_secs),
+            send: Duration::from_secs(config.send_timeout_secs),
+        }
+    }
+}
+
 #[async_trait]
 pub trait Worker: Send + Sync + 'static {
     fn name(&self) -> &str;
     async fn start(&self) -> Result<(), RuntimeError>;
-    #[allow(dead_code)]
+    /// Stops taking tasks and returns once the running ones are done
     async fn stop(&self) -> Result<(), RuntimeError>;
-    #[allow(dead_code)]
+    /// Whether the worker takes tasks, see [`Worker::slots`] for each of its loops
     fn is_running(&self) -> bool;
+    fn slots(&self) -> Vec<SlotHealth>;
+}
+
+/// Health of one worker loop, shown to admins in /status
+#[derive(Debug, Clone)]
+pub struct SlotHealth {
+    pub name: String,
+    pub alive: bool,
+    /// Times the loop panicked and was started again
+    pub restarts: u32,
+    /// When the task the slot works on was picked up, `None` while idle
+    pub busy_since: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct WorkerHealth {
+    pub name: String,
+    pub running: bool,
+    pub slots: Vec<SlotHealth>,
+}
+
+/// The slots of a worker, shared with their loops
+#[derive(Clone)]
+pub(super) struct Slots(Arc<Vec<Mutex<SlotHealth>>>);
+
+impl Slots {
+    pub fn new(worker: &str, count: usize) -> Self {
+        Self(Arc::new(
+            (0..count)
+                .map(|i| {
+                    Mutex::new(SlotHealth {
+                        name: format!("{}_{}", worker, i),
+                        alive: false,
+                        restarts: 0,
+                        busy_since: None,
+                    })
+                })
+                .collect(),
+        ))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn name(&self, slot: usize) -> String {
+        self.0[slot].lock().unwrap().name.clone()
+    }
+
+    pub fn update(&self, slot: usize, update: impl FnOnce(&mut SlotHealth)) {
+        update(&mut self.0[slot].lock().unwrap());
+    }
+
+    pub fn snapshot(&self) -> Vec<SlotHealth> {
+        self.0.iter().map(|slot| slot.lock().unwrap().clone()).collect()
+    }
+}
+
+/// Runs the loop of a slot in its own task and starts it again whenever it panics while the worker runs
+pub(super) fn supervise<F, Fut>(slots: Slots, slot: usize, running: Arc<AtomicBool>, run: F) -> JoinHandle<()>
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    tokio::spawn(async move {
+        loop {
+            slots.update(slot, |health| health.alive = true);
+            let result = tokio::spawn(run()).await;
+            slots.update(slot, |health| {
+                health.alive = false;
+                health.busy_since = None;
+            });
+
+            match result {
+                Err(e) if e.is_panic() && running.load(Ordering::SeqCst) => {
+                    error!(
+                        "Worker {} panicked, restarting it: {}",
+                        slots.name(slot),
+                        panic_message(e)
+                    );
+                    slots.update(slot, |health| health.restarts += 1);
+                    tokio::time::sleep(RESPAWN_DELAY).await;
+                }
+                _ => break,
+            }
+        }
+    })
+}
+
+/// Processes a task in its own tokio task, so a panic fails the task instead of taking the slot down and
+/// dropping whoever waits for the result
+pub(super) async fn catch_panic<T, Fut>(task: Fut) -> Result<T, RuntimeError>
+where
+    T: Send + 'static,
+    Fut: Future<Output = Result<T, RuntimeError>> + Send + 'static,
+{
+    match tokio::spawn(task).await {
+        Ok(result) => result,
+        Err(e) => Err(RuntimeError::Panicked(panic_message(e))),
+    }
+}
+
+fn panic_message(error: JoinError) -> String {
+    if !error.is_panic() {
+        return error.to_string();
+    }
+
+    let payload = error.into_panic();
+    payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
 }
 
 pub struct WorkerPool {
-    workers: HashMap<String, Box<dyn Worker>>, // TODO: use DashMap
-    #[allow(dead_code)]
-    shutdown: broadcast::Sender<()>,
+    workers: HashMap<String, Arc<dyn Worker>>, // TODO: use DashMap
 }
 
 impl WorkerPool {
     pub fn new() -> Self {
-        let (shutdown, _) = broadcast::channel(1);
         Self {
             workers: HashMap::new(),
-            shutdown,
         }
     }
 
     pub fn add_worker<W: Worker + 'static>(&mut self, worker: W) {
-        self.workers.insert(worker.name().to_string(), Box::new(worker));
+        self.workers.insert(worker.name().to_string(), Arc::new(worker));
     }
 
     pub async fn start_all(&self) -> Result<(), RuntimeError> {
@@ -41,12 +183,69 @@ impl WorkerPool {
         }
         Ok(())
     }
-    // TODO: graceful shutdown
-    // pub async fn stop_all(&self) -> Result<(), RuntimeError> {
-    //     let _ = self.shutdown.send(());
-    //     for worker in self.workers.values() {
-    //         worker.stop().await?;
-    //     }
-    //     Ok(())
-    // }
+
+    /// Stops every worker at once, so none keeps taking tasks while another is still finishing
+    pub async fn stop_all(&self) -> Result<(), RuntimeError> {
+        let mut stopping = JoinSet::new();
+        for worker in self.workers.values() {
+            let worker = worker.clone();
+            stopping.spawn(async move { worker.stop().await });
+        }
+
+        while let Some(result) = stopping.join_next().await {
+            result.map_err(|e| RuntimeError::Other(e.to_string()))??;
+        }
+        Ok(())
+    }
+
+    /// Health of every worker and its slots, sorted by name
+    pub fn health(&self) -> Vec<WorkerHealth> {
+        let mut health = self
+            .workers
+            .values()
+            .map(|worker| WorkerHealth {
+                name: worker.name().to_string(),
+                running: worker.is_running(),
+                slots: worker.slots(),
+            })
+            .collect::<Vec<_>>();
+        health.sort_by(|a, b| a.name.cmp(&b.name));
+        health
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_panics_fail_the_task_and_restart_the_slot() {
+        let result = catch_panic(async { panic!("boom") as Result<(), RuntimeError> }).await;
+        assert!(matches!(result, Err(RuntimeError::Panicked(e)) if e.contains("boom")));
+
+        let slots = Slots::new("test", 1);
+        let running = Arc::new(AtomicBool::new(true));
+        let runs = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+
+        let handle = supervise(slots.clone(), 0, running.clone(), {
+            let runs = runs.clone();
+            move || {
+                let runs = runs.clone();
+                async move {
+                    // Panics on its first run, then returns like a loop that was told to stop
+                    if runs.fetch_add(1, Ordering::SeqCst) == 0 {
+                        panic!("boom");
+                    }
+                }
+            }
+        });
+
+        tokio::time::timeout(RESPAWN_DELAY * 3, handle).await.unwrap().unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+
+        let health = &slots.snapshot()[0];
+        assert_eq!(health.name, "test_0");
+        assert_eq!(health.restarts, 1);
+        assert!(!health.alive);
+    }
 }
@@ -105,12 +105,7 @@ impl AuthService {
             .get_platform::<PlatformInstagram>(&credentials.platform)
             .ok_or_else(|| AuthError::Other("Platform not supported".into()))?;
 
-        let auth = platform
-            .as_any()
-            .downcast_ref::<Box<dyn PlatformAuth>>()
-            .ok_or_else(|| AuthError::Other("Platform does not support auth".into()))?;
-
-        auth.login(credentials).await
+        platform.login(credentials).await
     }
 
     pub async fn verify_session(&self, session: &Session) -> Result<bool, AuthError> {
@@ -119,12 +114,7 @@ impl AuthService {
             .get_platform::<PlatformInstagram>(&session.platform)
             .ok_or_else(|| AuthError::Other("Platform not supported".into()))?;
 
-        let auth = platform
-            .as_any()
-            .downcast_ref::<Box<dyn PlatformAuth>>()
-            .ok_or_else(|| AuthError::Other("Platform does not support auth".into()))?;
-
-        auth.verify_session(session).await
+        platform.verify_session(session).await
     }
 
     // pub async fn logout(&self, platform: &Platform) -> Result<(), AuthError> {
@@ -2,23 +2,61 @@ mod error;
 
 pub use error::*;
 
-use std::{sync::Arc, time::Duration};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use crate::runtime::{CacheManager, CacheOptions, CacheType};
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 
 use serde::{de::DeserializeOwned, Serialize};
 
+/// Values that expire within this long are not handed out anymore, so they stay usable until they are
+/// delivered, e.g. after the user confirmed the preview and the task waited in the queue
+const EXPIRY_MARGIN: Duration = Duration::from_secs(10 * 60);
+
 #[async_trait]
 pub trait Cacheable: Serialize + DeserializeOwned + Send + Sync {
     fn cache_prefix() -> &'static str;
     fn cache_key(&self) -> String;
+    /// When the content of the value stops being usable, e.g. because the links it holds expire
+    fn expires_at(&self) -> Option<DateTime<Utc>> {
+        None
+    }
+}
+
+pub enum CacheLookup<T> {
+    Hit(T),
+    Miss,
+    /// Cached, but its content expired and has to be fetched again
+    Stale,
+}
+
+/// Lookups since startup
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub refreshes: u64,
+}
+
+#[derive(Default)]
+struct CacheCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    refreshes: AtomicU64,
 }
 
 /// Redis only cache service, no memory cache
 #[derive(Clone)]
 pub struct CacheService {
     cache: Arc<CacheManager>,
+    counters: Arc<CacheCounters>,
 }
 
 impl CacheService {
@@ -26,20 +64,41 @@ impl CacheService {
         let cache = Arc::new(
             CacheManager::new(0).map_err(|e| CacheError::Cache(format!("Failed to create cache manager: {}", e)))?,
         );
-        Ok(Self { cache })
+        Ok(Self {
+            cache,
+            counters: Arc::new(CacheCounters::default()),
+        })
     }
 
-    pub async fn get<T: Cacheable>(&self, key: &str) -> Result<Option<T>, CacheError> {
+    /// Looks a value up and counts the hit, miss or refresh, values that are about to expire count as stale
+    pub async fn lookup<T: Cacheable>(&self, key: &str) -> Result<CacheLookup<T>, CacheError> {
         let options = CacheOptions {
             cache_type: CacheType::Redis,
             ttl: None,
             prefix: Some(T::cache_prefix().to_string()),
         };
 
-        self.cache.get::<T>(key, &options).await.map_err(CacheError::Storage)
+        let (lookup, counter) = match self.cache.get::<T>(key, &options).await? {
+            None => (CacheLookup::Miss, &self.counters.misses),
+            Some(value) if usable_for(&value).is_some_and(|left| left.is_zero()) => {
+                (CacheLookup::Stale, &self.counters.refreshes)
+            }
+            Some(value) => (CacheLookup::Hit(value), &self.counters.hits),
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        Ok(lookup)
     }
 
+    /// Caches a value for `ttl` at most, or until shortly before its content expires. Values that expire
+    /// too soon are not cached at all.
     pub async fn set<T: Cacheable>(&self, value: T, ttl: Duration) -> Result<(), CacheError> {
+        let ttl = usable_for(&value).map_or(ttl, |left| left.min(ttl));
+        // Redis expires keys by the second
+        if ttl.as_secs() == 0 {
+            return Ok(());
+        }
+
         let options = CacheOptions {
             cache_type: CacheType::Redis,
             ttl: Some(ttl),
@@ -52,6 +111,14 @@ impl CacheService {
             .map_err(CacheError::Storage)
     }
 
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.counters.hits.load(Ordering::Relaxed),
+            misses: self.counters.misses.load(Ordering::Relaxed),
+            refreshes: self.counters.refreshes.load(Ordering::Relaxed),
+        }
+    }
+
     // pub async fn keys<T: Cacheable>(&self, pattern: &str) -> Result<Vec<String>, CacheError> {
     //     let options = CacheOptions {
     //         cache_type: CacheType::Redis,
@@ -72,3 +139,13 @@ impl CacheService {
     //     self.cache.del(key, &options).await.map_err(CacheError::Storage)
     // }
 }
+
+/// How long a value can still be handed out, `None` if its content doesn't expire
+fn usable_for<T: Cacheable>(value: &T) -> Option<Duration> {
+    value.expires_at().map(|expires_at| {
+        (expires_at - Utc::now())
+            .to_std()
+            .unwrap_or_default()
+            .saturating_sub(EXPIRY_MARGIN)
+    })
+}
@@ -16,6 +16,9 @@ pub enum DialogueState {
     ConfirmDownload {
         media_file: MediaFile,
     },
+    SelectProfileRange {
+        username: String,
+    },
     // Authentication
     AwaitingUsername(MessageId),
     AwaitingPassword {
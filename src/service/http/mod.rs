@@ -5,14 +5,17 @@ use reqwest::{
     Client, Response,
 };
 use serde_json::Value;
-use std::{sync::Arc, time::Duration};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use url::Url;
 
-use crate::platform::Platform;
+use crate::{platform::Platform, service::CookieData};
 
 #[async_trait]
 pub trait HttpClient: Send + Sync {
     async fn get(&self, url: &str) -> Result<Response, reqwest::Error>;
+    async fn get_with_params(&self, url: &str, params: Option<Value>) -> Result<Response, reqwest::Error>;
     async fn post(&self, url: &str, data: Option<Value>) -> Result<Response, reqwest::Error>;
+    #[allow(dead_code)]
     async fn get_json(&self, url: &str, params: Option<Value>) -> Result<Value, reqwest::Error>;
     #[allow(dead_code)]
     async fn post_json(&self, url: &str, data: Option<Value>) -> Result<Value, reqwest::Error>;
@@ -40,6 +43,31 @@ impl HttpService {
         })
     }
 
+    /// Creates a service with its own cookie jar seeded from previously stored session cookies.
+    pub fn with_cookies(
+        platform: Platform,
+        base_url: &Url,
+        cookies: &HashMap<String, CookieData>,
+    ) -> Result<Self, reqwest::Error> {
+        let cookie_jar = Arc::new(reqwest::cookie::Jar::default());
+
+        for cookie in cookies.values() {
+            let cookie_str = format!(
+                "{}={}; Domain={}; Path={}",
+                cookie.name, cookie.value, cookie.domain, cookie.path
+            );
+            cookie_jar.add_cookie_str(&cookie_str, base_url);
+        }
+
+        let client = Self::create_client(Arc::clone(&cookie_jar), Self::get_platform_headers(&platform))?;
+
+        Ok(Self {
+            client,
+            cookie_jar,
+            platform,
+        })
+    }
+
     fn get_platform_headers(platform: &Platform) -> HeaderMap {
         match platform {
             Platform::Instagram => {
@@ -48,6 +76,7 @@ impl HttpService {
                 headers.insert(header::ORIGIN, HeaderValue::from_static("https://www.instagram.com"));
                 headers.insert("X-Instagram-AJAX", HeaderValue::from_static("1"));
                 headers.insert("X-Requested-With", HeaderValue::from_static("XMLHttpRequest"));
+                headers.insert("X-IG-App-ID", HeaderValue::from_static("936619743392459"));
                 headers
             }
             _ => todo!(),
@@ -204,12 +233,16 @@ impl HttpClient for HttpService {
         builder.send().await
     }
 
-    async fn get_json(&self, url: &str, params: Option<Value>) -> Result<Value, reqwest::Error> {
+    async fn get_with_params(&self, url: &str, params: Option<Value>) -> Result<Response, reqwest::Error> {
         let mut builder = self.client.get(url);
         if let Some(params) = params {
             builder = builder.query(&params);
         }
-        let response = builder.send().await?;
+        builder.send().await
+    }
+
+    async fn get_json(&self, url: &str, params: Option<Value>) -> Result<Value, reqwest::Error> {
+        let response = self.get_with_params(url, params).await?;
         response.json().await
     }
 
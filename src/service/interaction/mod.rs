@@ -4,7 +4,7 @@ use crate::{
     error::BotResult,
     runtime::{CacheManager, CacheOptions, CacheType},
     state::AppState,
-    storage::StorageError,
+    storage::{StorageError, TursoClient},
 };
 
 mod model;
@@ -21,6 +21,18 @@ impl InteractionService {
         info!("Initializing InteractionService...");
         let cache = CacheManager::new(capacity)?;
 
+        let conn = TursoClient::get()?.get_connection().await?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS user_last_interface (
+                telegram_user_id TEXT PRIMARY KEY,
+                interface TEXT NOT NULL,
+                updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+            )",
+            (),
+        )
+        .await
+        .map_err(StorageError::Turso)?;
+
         info!("InteractionService initialized");
         Ok(Self {
             cache,
@@ -59,7 +71,6 @@ impl InteractionService {
         Ok(result)
     }
 
-    #[allow(dead_code)]
     pub async fn cleanup_old_entries(&self) {
         let now = Utc::now();
         let cache_options = CacheOptions {
@@ -78,7 +89,8 @@ impl InteractionService {
             }
         }
     }
-    #[allow(dead_code)]
+
+    /// Writes the cached interfaces to Turso, periodically and on shutdown since the cache only lives in memory
     pub async fn save_interfaces_to_database(&self) -> BotResult<()> {
         let app_state = AppState::get()?;
         let conn = app_state.storage.turso().get_connection().await?;
@@ -1,12 +1,13 @@
-use std::str::FromStr;
+use std::{str::FromStr, sync::Arc};
 
+use dashmap::DashMap;
 pub use model::Language;
 
 use crate::{
     error::BotResult,
     runtime::{CacheManager, CacheOptions, CacheType},
     state::AppState,
-    storage::StorageError,
+    storage::{StorageError, TursoClient},
 };
 
 mod model;
@@ -14,17 +15,35 @@ mod model;
 #[derive(Clone)]
 pub struct LanguageService {
     cache: CacheManager,
+    /// Choices that could not be written to the database yet, kept apart from the cache which may evict them
+    pending: Arc<DashMap<String, Language>>,
 }
 
 impl LanguageService {
     pub async fn new(capacity: usize) -> Result<Self, StorageError> {
         info!("Initializing LanguageService...");
         let cache = CacheManager::new(capacity)?;
+
+        let conn = TursoClient::get()?.get_connection().await?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS user_language (
+                telegram_user_id TEXT PRIMARY KEY,
+                language TEXT NOT NULL,
+                updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+            )",
+            (),
+        )
+        .await
+        .map_err(StorageError::Turso)?;
+
         info!("LanguageService initialized");
-        Ok(Self { cache })
+        Ok(Self {
+            cache,
+            pending: Arc::new(DashMap::new()),
+        })
     }
-    #[allow(dead_code)]
-    pub async fn get_user_language(&self, telegram_user_id: &str) -> BotResult<Language> {
+    /// The language the user picked, `None` if they never did
+    pub async fn get_user_language(&self, telegram_user_id: &str) -> BotResult<Option<Language>> {
         let cache_options = CacheOptions {
             cache_type: CacheType::Memory,
             ttl: None,
@@ -32,18 +51,38 @@ impl LanguageService {
         };
 
         if let Some(lang) = self.cache.get(telegram_user_id, &cache_options).await? {
-            return Ok(lang);
+            return Ok(Some(lang));
         }
 
         let lang = self.load_language_from_database(telegram_user_id).await?;
-        self.cache
-            .set::<Language>(telegram_user_id, lang, &cache_options)
-            .await?;
+        if let Some(lang) = lang {
+            self.cache
+                .set::<Language>(telegram_user_id, lang, &cache_options)
+                .await?;
+        }
 
         Ok(lang)
     }
 
-    async fn load_language_from_database(&self, telegram_user_id: &str) -> BotResult<Language> {
+    /// Resolves the language of a user, on first contact it defaults to their Telegram `language_code`
+    pub async fn resolve_user_language(
+        &self,
+        telegram_user_id: &str,
+        language_code: Option<&str>,
+    ) -> BotResult<Language> {
+        if let Some(language) = self.get_user_language(telegram_user_id).await? {
+            return Ok(language);
+        }
+
+        let language = language_code.and_then(Language::from_telegram_code).unwrap_or_default();
+
+        // Only cached, the Telegram language is derived again after a restart until the user picks one
+        self.cache_language(telegram_user_id, language).await?;
+
+        Ok(language)
+    }
+
+    async fn load_language_from_database(&self, telegram_user_id: &str) -> BotResult<Option<Language>> {
         let app_state = AppState::get()?;
         let conn = app_state.storage.turso().get_connection().await?;
         let mut rows = conn
@@ -54,15 +93,31 @@ impl LanguageService {
             .await
             .map_err(|e| StorageError::Turso(e))?;
 
-        while let Some(row) = rows.next().await.map_err(|e| StorageError::Turso(e))? {
+        if let Some(row) = rows.next().await.map_err(|e| StorageError::Turso(e))? {
             let language = row.get::<String>(0).unwrap_or_else(|_| "en".to_string());
-            return Ok(Language::from_str(&language).unwrap_or(Language::English));
+            return Ok(Some(Language::from_str(&language).unwrap_or_default()));
         }
 
-        Ok(Language::English)
+        Ok(None)
     }
 
+    /// Caches the user's choice and writes it through to the database, failed writes are retried by
+    /// [`Self::save_languages_to_database`]
     pub async fn set_user_language(&self, telegram_user_id: &str, language: Language) -> BotResult<()> {
+        self.cache_language(telegram_user_id, language).await?;
+
+        if let Err(e) = self.persist_language(telegram_user_id, language).await {
+            warn!(
+                "Failed to persist language of {}, retrying later: {}",
+                telegram_user_id, e
+            );
+            self.pending.insert(telegram_user_id.to_string(), language);
+        }
+
+        Ok(())
+    }
+
+    async fn cache_language(&self, telegram_user_id: &str, language: Language) -> BotResult<()> {
         let cache_options = CacheOptions {
             cache_type: CacheType::Memory,
             ttl: None,
@@ -75,45 +130,60 @@ impl LanguageService {
         Ok(())
     }
 
-    #[allow(dead_code)]
-    pub async fn save_languages_to_database(&self) -> BotResult<()> {
-        let app_state = AppState::get()?;
-        let conn = app_state.storage.turso().get_connection().await?;
-
-        let cache_options = CacheOptions {
-            cache_type: CacheType::Memory,
-            ttl: None,
-            prefix: Some("lang".to_string()),
-        };
+    async fn persist_language(&self, telegram_user_id: &str, language: Language) -> BotResult<()> {
+        let conn = TursoClient::get()?.get_connection().await?;
+        conn.execute(
+            "INSERT OR REPLACE INTO user_language (telegram_user_id, language) VALUES (?1, ?2)",
+            [telegram_user_id.to_string(), language.to_string()],
+        )
+        .await
+        .map_err(StorageError::Turso)?;
+
+        // A newer choice that failed to persist is still pending
+        self.pending
+            .remove_if(telegram_user_id, |_, pending| *pending == language);
+        Ok(())
+    }
 
-        let keys = self.cache.keys("*", &cache_options).await?;
-        if keys.is_empty() {
+    /// Flushes the choices whose write-through failed
+    pub async fn save_languages_to_database(&self) -> BotResult<()> {
+        let choices = self
+            .pending
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value()))
+            .collect::<Vec<_>>();
+        if choices.is_empty() {
             return Ok(());
         }
 
-        let tx = conn.transaction().await.map_err(|e| StorageError::Turso(e))?;
+        let conn = TursoClient::get()?.get_connection().await?;
+
+        let tx = conn.transaction().await.map_err(StorageError::Turso)?;
 
         let mut values = Vec::new();
         let mut params = Vec::new();
 
-        for key in keys {
-            if let Some(language) = self.cache.get::<Language>(&key, &cache_options).await? {
-                values.push(format!("(?{}, ?{})", params.len() + 1, params.len() + 2));
-                params.push(key);
-                params.push(language.to_string());
-            }
+        for (key, language) in &choices {
+            values.push(format!("(?{}, ?{})", params.len() + 1, params.len() + 2));
+            params.push(key.clone());
+            params.push(language.to_string());
         }
 
-        if !values.is_empty() {
-            let query = format!(
-                "INSERT OR REPLACE INTO user_language (telegram_user_id, language) VALUES {}",
-                values.join(",")
-            );
+        let query = format!(
+            "INSERT OR REPLACE INTO user_language (telegram_user_id, language) VALUES {}",
+            values.join(",")
+        );
+
+        tx.execute(&query, params).await.map_err(StorageError::Turso)?;
+
+        tx.commit().await.map_err(StorageError::Turso)?;
 
-            tx.execute(&query, params).await.map_err(|e| StorageError::Turso(e))?;
+        // Choices made while flushing stay pending for the next flush
+        for (key, language) in &choices {
+            self.pending.remove_if(key, |_, pending| pending == language);
         }
 
-        tx.commit().await.map_err(|e| StorageError::Turso(e))?;
+        info!("Saved {} pending language choices to the database", choices.len());
 
         Ok(())
     }
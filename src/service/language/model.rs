@@ -2,9 +2,10 @@ use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Copy)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord, Copy)]
 #[serde(rename_all = "lowercase")]
 pub enum Language {
+    #[default]
     English,
     Chinese,
     German,
@@ -29,6 +30,27 @@ impl FromStr for Language {
     }
 }
 
+impl Language {
+    /// Maps Telegram's IETF `language_code` (e.g. `de`, `zh-hans`, `pt-br`) to a supported language
+    pub fn from_telegram_code(code: &str) -> Option<Self> {
+        code.split(['-', '_'])
+            .next()
+            .and_then(|code| Language::from_str(code).ok())
+    }
+
+    /// The rust-i18n locale of the language, to be passed as `t!(key, locale = ...)`
+    pub fn locale(&self) -> &'static str {
+        match self {
+            Language::English => "en",
+            Language::Chinese => "zh",
+            Language::German => "de",
+            Language::French => "fr",
+            Language::Japanese => "ja",
+            Language::Spanish => "es",
+        }
+    }
+}
+
 impl ToString for Language {
     fn to_string(&self) -> String {
         match self {
@@ -41,3 +63,17 @@ impl ToString for Language {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_telegram_code() {
+        assert_eq!(Language::from_telegram_code("de"), Some(Language::German));
+        assert_eq!(Language::from_telegram_code("zh-hans"), Some(Language::Chinese));
+        assert_eq!(Language::from_telegram_code("es_MX"), Some(Language::Spanish));
+        assert_eq!(Language::from_telegram_code("pt-br"), None);
+        assert_eq!(Language::from_telegram_code(""), None);
+    }
+}
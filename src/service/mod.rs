@@ -23,7 +23,7 @@ mod session;
 mod user;
 
 pub use auth::*;
-pub use cache::Cacheable;
+pub use cache::{CacheLookup, Cacheable};
 pub use error::ServiceError;
 pub use interaction::LastInterfaceState;
 pub use language::Language;
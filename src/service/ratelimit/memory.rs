@@ -0,0 +1,79 @@
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::storage::StorageError;
+
+use super::{RateLimitBackend, WindowUsage};
+
+/// First request and number of requests per resource
+type Window = HashMap<String, (DateTime<Utc>, u32)>;
+
+/// Keeps the window of each user in this process, mirroring the scripts of the Redis backend so the service
+/// can be tested without a server
+#[derive(Default)]
+pub struct MemoryRateLimiter {
+    windows: Mutex<HashMap<String, Window>>,
+}
+
+impl MemoryRateLimiter {
+    /// Drops the resources whose window passed, a resource first requested exactly a window ago is gone
+    fn prune(window: &mut Window, length: Duration, now: DateTime<Utc>) {
+        window.retain(|_, (first, _)| *first > now - length);
+    }
+
+    /// Users with a window kept
+    pub fn users(&self) -> usize {
+        self.windows.lock().expect("Rate limit windows lock poisoned").len()
+    }
+}
+
+#[async_trait]
+impl RateLimitBackend for MemoryRateLimiter {
+    async fn acquire(
+        &self,
+        telegram_user_id: &str,
+        resource: &str,
+        max_resources: usize,
+        window: Duration,
+        now: DateTime<Utc>,
+    ) -> Result<bool, StorageError> {
+        let mut windows = self.windows.lock().expect("Rate limit windows lock poisoned");
+        // Users with nothing left in their window are forgotten, not only the one asking
+        windows.retain(|_, resources| {
+            Self::prune(resources, window, now);
+            !resources.is_empty()
+        });
+
+        let resources = windows.entry(telegram_user_id.to_string()).or_default();
+        if !resources.contains_key(resource) && resources.len() >= max_resources {
+            return Ok(false);
+        }
+        resources.entry(resource.to_string()).or_insert((now, 0)).1 += 1;
+        Ok(true)
+    }
+
+    async fn usage(
+        &self,
+        telegram_user_id: &str,
+        window: Duration,
+        now: DateTime<Utc>,
+    ) -> Result<WindowUsage, StorageError> {
+        let mut windows = self.windows.lock().expect("Rate limit windows lock poisoned");
+        let resources = windows.entry(telegram_user_id.to_string()).or_default();
+        Self::prune(resources, window, now);
+
+        // Users with nothing left in their window are forgotten
+        let usage = WindowUsage {
+            resources: resources.len(),
+            requests: resources.values().map(|(_, requests)| requests).sum(),
+            oldest: resources.values().map(|(first, _)| *first).min(),
+        };
+        if resources.is_empty() {
+            windows.remove(telegram_user_id);
+        }
+
+        Ok(usage)
+    }
+}
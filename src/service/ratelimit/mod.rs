@@ -1,19 +1,55 @@
+#[cfg(test)]
+mod memory;
 mod model;
+mod redis;
 
 pub use model::RateLimitInfo;
 
-use chrono::Utc;
-use std::time::Duration;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::{sync::Arc, time::Duration};
 
-use crate::{
-    runtime::{CacheManager, CacheOptions, CacheType},
-    storage::StorageError,
-    utils::seconds_to_human_readable,
-};
+use crate::{storage::StorageError, utils::seconds_to_human_readable};
+
+use self::redis::RedisRateLimiter;
+
+/// The requests a user made within the window
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WindowUsage {
+    /// Distinct resources requested
+    pub resources: usize,
+    /// Requests to all of them combined
+    pub requests: u32,
+    /// First request to the oldest resource, it leaves the window once it is a window old
+    pub oldest: Option<DateTime<Utc>>,
+}
+
+/// Where the sliding window of each user is kept. Each resource counts once from its first request until it
+/// is a window old, checking and recording a request must be atomic so concurrent requests can't both take
+/// the last slot.
+#[async_trait]
+pub trait RateLimitBackend: Send + Sync {
+    /// Records the request unless it is for a new resource and the user already requested `max_resources`
+    /// others within the window, returns whether it was recorded
+    async fn acquire(
+        &self,
+        telegram_user_id: &str,
+        resource: &str,
+        max_resources: usize,
+        window: Duration,
+        now: DateTime<Utc>,
+    ) -> Result<bool, StorageError>;
+    async fn usage(
+        &self,
+        telegram_user_id: &str,
+        window: Duration,
+        now: DateTime<Utc>,
+    ) -> Result<WindowUsage, StorageError>;
+}
 
 #[derive(Clone)]
 pub struct RateLimitService {
-    cache: CacheManager,
+    backend: Arc<dyn RateLimitBackend>,
     max_requests: usize,
     window_seconds: Duration,
 }
@@ -21,86 +57,125 @@ pub struct RateLimitService {
 impl RateLimitService {
     pub async fn new(daily_limit: usize, window_secs: u64) -> Result<Self, StorageError> {
         info!("Initializing rate limit service");
-        Ok(Self {
-            cache: CacheManager::new(0)?,
+        Ok(Self::with_backend(Arc::new(RedisRateLimiter), daily_limit, window_secs))
+    }
+
+    fn with_backend(backend: Arc<dyn RateLimitBackend>, daily_limit: usize, window_secs: u64) -> Self {
+        Self {
+            backend,
             max_requests: daily_limit,
             window_seconds: Duration::from_secs(window_secs),
-        })
+        }
     }
 
+    /// Whether the user may download the resource, repeated downloads of a resource in the window are free
     pub async fn check_rate_limit(&self, telegram_user_id: &str, identifier: &str) -> Result<bool, StorageError> {
-        let today = Utc::now().date_naive();
-        let key = format!("rate_limit:{}:{}:{}", telegram_user_id, identifier, today.to_string());
-
-        info!("key: {}", key);
-
-        let options = CacheOptions {
-            cache_type: CacheType::Redis,
-            ttl: Some(self.window_seconds),
-            prefix: None,
-        };
-
-        if let Some(count) = self.cache.get::<u32>(&key, &options).await? {
-            info!("count: {}", count);
+        self.backend
+            .acquire(
+                telegram_user_id,
+                identifier,
+                self.max_requests,
+                self.window_seconds,
+                Utc::now(),
+            )
+            .await
+    }
 
-            self.cache.set::<u32>(&key, count + 1, &options).await?;
-            return Ok(true);
-        }
+    pub async fn get_rate_limit_info(&self, telegram_user_id: &str) -> Result<RateLimitInfo, StorageError> {
+        let now = Utc::now();
+        let usage = self.backend.usage(telegram_user_id, self.window_seconds, now).await?;
 
-        let pattern = format!("rate_limit:{}:*:{}", telegram_user_id, today);
-        let keys = self.cache.keys(&pattern, &options).await?;
+        // The next slot frees up when the oldest resource leaves the window
+        let reset_in = usage
+            .oldest
+            .map(|oldest| (oldest + self.window_seconds - now).num_seconds().max(0) as u64)
+            .unwrap_or(self.window_seconds.as_secs());
 
-        info!("keys: {:?}", keys);
+        Ok(RateLimitInfo {
+            total_requests: usage.requests,
+            total_used_requests: usage.resources,
+            remaining_requests: self.max_requests.saturating_sub(usage.resources),
+            reset_time: seconds_to_human_readable(reset_in),
+        })
+    }
+}
 
-        if keys.len() >= self.max_requests {
-            return Ok(false);
-        }
+#[cfg(test)]
+mod tests {
+    use super::{memory::MemoryRateLimiter, *};
 
-        self.cache.set::<u32>(&key, 1, &options).await?;
-        Ok(true)
+    async fn service(max_requests: usize, window_secs: u64) -> RateLimitService {
+        RateLimitService::with_backend(Arc::new(MemoryRateLimiter::default()), max_requests, window_secs)
     }
 
-    pub async fn get_rate_limit_info(&self, telegram_user_id: &str) -> Result<RateLimitInfo, StorageError> {
-        let today = Utc::now().date_naive();
-        let options = CacheOptions {
-            cache_type: CacheType::Redis,
-            ttl: Some(self.window_seconds),
-            prefix: None,
-        };
-        let pattern = format!("rate_limit:{}:*:{}", telegram_user_id, today);
-        let keys = self.cache.keys(&pattern, &options).await?;
-
-        if keys.is_empty() {
-            return Ok(RateLimitInfo {
-                total_requests: 0,
-                total_used_requests: 0,
-                remaining_requests: self.max_requests,
-                reset_time: seconds_to_human_readable(self.window_seconds.as_secs()),
-            });
-        }
-
-        let mut total_requests = 0; // combined request number to all resources today
-        let total_used_requests = keys.len(); // total requests to different resources used today
+    #[tokio::test]
+    async fn test_counts_distinct_resources_per_user() {
+        let ratelimit = service(2, 3600).await;
+
+        assert!(ratelimit.check_rate_limit("1", "instagram:a").await.unwrap());
+        assert!(ratelimit.check_rate_limit("1", "instagram:b").await.unwrap());
+        assert!(!ratelimit.check_rate_limit("1", "instagram:c").await.unwrap());
+        // Resources already in the window don't take another slot
+        assert!(ratelimit.check_rate_limit("1", "instagram:a").await.unwrap());
+        assert!(ratelimit.check_rate_limit("2", "instagram:c").await.unwrap());
+
+        let info = ratelimit.get_rate_limit_info("1").await.unwrap();
+        assert_eq!(info.total_requests, 3);
+        assert_eq!(info.total_used_requests, 2);
+        assert_eq!(info.remaining_requests, 0);
+
+        let info = ratelimit.get_rate_limit_info("3").await.unwrap();
+        assert_eq!(info.total_requests, 0);
+        assert_eq!(info.remaining_requests, 2);
+        assert_eq!(info.reset_time, "1h 0m 0s");
+    }
 
-        let mut max_ttl = Duration::from_secs(0);
+    #[tokio::test]
+    async fn test_window_slides_per_resource() {
+        let backend = MemoryRateLimiter::default();
+        let window = Duration::from_secs(60);
+        let start = Utc::now();
+        let at = |secs: i64| start + chrono::Duration::seconds(secs);
+
+        assert!(backend.acquire("1", "a", 2, window, at(0)).await.unwrap());
+        assert!(backend.acquire("1", "b", 2, window, at(30)).await.unwrap());
+        assert!(!backend.acquire("1", "c", 2, window, at(59)).await.unwrap());
+
+        // `a` left the window, `b` still holds its slot
+        assert!(backend.acquire("1", "c", 2, window, at(60)).await.unwrap());
+        assert!(!backend.acquire("1", "d", 2, window, at(89)).await.unwrap());
+
+        let usage = backend.usage("1", window, at(89)).await.unwrap();
+        assert_eq!(usage.resources, 2);
+        assert_eq!(usage.oldest, Some(at(30)));
+        assert_eq!(
+            backend.usage("1", window, at(120)).await.unwrap(),
+            WindowUsage::default()
+        );
+
+        // Requests of others forget users whose window passed
+        assert!(backend.acquire("2", "a", 2, window, at(120)).await.unwrap());
+        assert!(backend.acquire("3", "a", 2, window, at(180)).await.unwrap());
+        assert_eq!(backend.users(), 1);
+    }
 
-        for key in keys {
-            if let Some(count) = self.cache.get::<u32>(&key, &options).await? {
-                total_requests += count;
-                if let Some(ttl) = self.cache.ttl(&key, &options).await? {
-                    max_ttl = max_ttl.max(ttl);
-                }
+    #[tokio::test]
+    async fn test_concurrent_requests_never_exceed_the_limit() {
+        let ratelimit = service(3, 3600).await;
+
+        let requests = (0..20)
+            .map(|i| {
+                let ratelimit = ratelimit.clone();
+                tokio::spawn(async move { ratelimit.check_rate_limit("1", &format!("instagram:{}", i)).await })
+            })
+            .collect::<Vec<_>>();
+
+        let mut allowed = 0;
+        for request in requests {
+            if request.await.unwrap().unwrap() {
+                allowed += 1;
             }
         }
-
-        let remaining_requests = self.max_requests - total_used_requests;
-        let reset_time = seconds_to_human_readable(max_ttl.as_secs());
-
-        Ok(RateLimitInfo {
-            total_requests,
-            total_used_requests,
-            remaining_requests,
-            reset_time,
-        })
+        assert_eq!(allowed, 3);
     }
 }
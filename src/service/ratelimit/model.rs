@@ -1,7 +1,7 @@
 #[derive(Debug, Clone)]
 pub struct RateLimitInfo {
-    pub total_requests: u32,        // combined request number to all resources today
-    pub total_used_requests: usize, // total requests to different resources used today
-    pub remaining_requests: usize,  // remaining requests to different resources today
-    pub reset_time: String,         // timestamp of the next reset
+    pub total_requests: u32,        // combined request number to all resources in the window
+    pub total_used_requests: usize, // different resources requested in the window
+    pub remaining_requests: usize,  // different resources that can still be requested
+    pub reset_time: String,         // time until the next resource leaves the window
 }
@@ -0,0 +1,122 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::storage::{RedisClient, StorageError};
+
+use super::{RateLimitBackend, WindowUsage};
+
+/// Drops the resources whose window passed, shared by the scripts below. `KEYS[1]` holds the resources
+/// scored by their first request in ms, `KEYS[2]` the number of requests per resource.
+macro_rules! prune_lua {
+    () => {
+        r#"
+local now = tonumber(ARGV[1])
+local window = tonumber(ARGV[2])
+local expired = redis.call('ZRANGEBYSCORE', KEYS[1], '-inf', now - window)
+if #expired > 0 then
+    redis.call('ZREM', KEYS[1], unpack(expired))
+    redis.call('HDEL', KEYS[2], unpack(expired))
+end
+"#
+    };
+}
+
+/// Atomically checks the window and records the request, requests to a resource already in the window are
+/// always let through
+const ACQUIRE_SCRIPT: &str = concat!(
+    prune_lua!(),
+    r#"
+if not redis.call('ZSCORE', KEYS[1], ARGV[4]) then
+    if redis.call('ZCARD', KEYS[1]) >= tonumber(ARGV[3]) then
+        return 0
+    end
+    redis.call('ZADD', KEYS[1], now, ARGV[4])
+end
+redis.call('HINCRBY', KEYS[2], ARGV[4], 1)
+redis.call('PEXPIRE', KEYS[1], window)
+redis.call('PEXPIRE', KEYS[2], window)
+return 1
+"#
+);
+
+/// Returns the resources in the window, the requests to them and when the oldest was first requested
+const USAGE_SCRIPT: &str = concat!(
+    prune_lua!(),
+    r#"
+local requests = 0
+for _, count in ipairs(redis.call('HVALS', KEYS[2])) do
+    requests = requests + tonumber(count)
+end
+local oldest = redis.call('ZRANGE', KEYS[1], 0, 0, 'WITHSCORES')
+return {redis.call('ZCARD', KEYS[1]), requests, tonumber(oldest[2] or '-1')}
+"#
+);
+
+/// Keeps the window of each user in Redis, shared by every instance of the bot
+pub struct RedisRateLimiter;
+
+impl RedisRateLimiter {
+    fn keys(telegram_user_id: &str) -> [String; 2] {
+        [
+            format!("rate_limit:{}:resources", telegram_user_id),
+            format!("rate_limit:{}:requests", telegram_user_id),
+        ]
+    }
+}
+
+#[async_trait]
+impl RateLimitBackend for RedisRateLimiter {
+    async fn acquire(
+        &self,
+        telegram_user_id: &str,
+        resource: &str,
+        max_resources: usize,
+        window: Duration,
+        now: DateTime<Utc>,
+    ) -> Result<bool, StorageError> {
+        let [resources, requests] = Self::keys(telegram_user_id);
+        let mut conn = RedisClient::get()?.get_connection().await?;
+
+        let acquired: i32 = redis::cmd("EVAL")
+            .arg(ACQUIRE_SCRIPT)
+            .arg(2)
+            .arg(resources)
+            .arg(requests)
+            .arg(now.timestamp_millis())
+            .arg(window.as_millis() as i64)
+            .arg(max_resources)
+            .arg(resource)
+            .query_async(&mut conn)
+            .await?;
+
+        Ok(acquired == 1)
+    }
+
+    async fn usage(
+        &self,
+        telegram_user_id: &str,
+        window: Duration,
+        now: DateTime<Utc>,
+    ) -> Result<WindowUsage, StorageError> {
+        let [resources_key, requests_key] = Self::keys(telegram_user_id);
+        let mut conn = RedisClient::get()?.get_connection().await?;
+
+        let (resources, requests, oldest): (usize, u32, i64) = redis::cmd("EVAL")
+            .arg(USAGE_SCRIPT)
+            .arg(2)
+            .arg(resources_key)
+            .arg(requests_key)
+            .arg(now.timestamp_millis())
+            .arg(window.as_millis() as i64)
+            .query_async(&mut conn)
+            .await?;
+
+        Ok(WindowUsage {
+            resources,
+            requests,
+            oldest: DateTime::from_timestamp_millis(oldest).filter(|_| oldest >= 0),
+        })
+    }
+}
@@ -93,11 +93,7 @@ impl SessionService {
                     .get_platform::<PlatformInstagram>(platform)
                     .unwrap();
 
-                let platform_session = platform_service
-                    .as_any()
-                    .downcast_ref::<Box<dyn PlatformSession>>()
-                    .ok_or(SessionError::SessionNotFound)?;
-                session.status = platform_session.validate_session(&session).await?;
+                session.status = platform_service.validate_session(&session).await?;
 
                 match session.status {
                     SessionStatus::Active => {
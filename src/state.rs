@@ -1,4 +1,7 @@
-use std::sync::{Arc, OnceLock};
+use std::{
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
 
 use teloxide::adaptors::Throttle;
 use teloxide::Bot;
@@ -33,11 +36,11 @@ impl AppState {
 
         let storage = StorageManager::get().await?;
 
-        let runtime = RuntimeManager::new(config.runtime.queue.capacity, bot)?;
+        let runtime = RuntimeManager::new(&config.runtime.queue, bot)?;
 
         runtime.start().await?;
 
-        let platform_registry = Arc::new(PlatformRegistry::new()?);
+        let platform_registry = Arc::new(PlatformRegistry::new(&config.platform)?);
 
         let service_registry = ServiceRegistry::new(config, Arc::clone(&platform_registry)).await?;
 
@@ -55,6 +58,22 @@ impl AppState {
             .map_err(|_| BotError::AppStateError("Failed to set global app state".into()))
     }
 
+    /// Drains the runtime and flushes what is only kept in memory to Turso, the dispatcher is stopped afterwards
+    pub async fn shutdown(&self) -> BotResult<()> {
+        let deadline = Duration::from_secs(AppConfig::get()?.runtime.queue.shutdown_timeout_secs);
+        self.runtime.shutdown(deadline).await?;
+
+        if let Err(e) = self.service_registry.language.save_languages_to_database().await {
+            error!("Failed to save languages on shutdown: {}", e);
+        }
+
+        if let Err(e) = self.service_registry.interaction.save_interfaces_to_database().await {
+            error!("Failed to save interfaces on shutdown: {}", e);
+        }
+
+        Ok(())
+    }
+
     pub fn get() -> BotResult<AppState> {
         APP_STATE
             .get()